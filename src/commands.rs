@@ -7,12 +7,50 @@
 
 mod about;
 mod debug;
+mod drql;
 mod dry_run;
 mod ping;
+mod roles;
+mod settings;
 mod version;
 
+use std::borrow::Cow;
+
 pub use about::about;
 pub use debug::debug;
+pub use drql::drql;
 pub use dry_run::dry_run;
 pub use ping::ping;
+use poise::serenity_prelude as serenity;
+pub use roles::roles;
+pub use settings::settings;
 pub use version::version;
+
+use super::Context;
+
+/// Reply with `content`, or with `summary` and `text` as an attachment named `filename` if
+/// `content` is too long for a single message
+async fn reply_or_attach(
+    ctx: Context<'_>,
+    content: String,
+    summary: String,
+    text: String,
+    filename: &str,
+) -> Result<(), anyhow::Error> {
+    if content.len() <= 2000 {
+        ctx.say(content).await?;
+        return Ok(());
+    }
+
+    ctx.send(|builder| {
+        builder
+            .content(summary)
+            .attachment(serenity::AttachmentType::Bytes {
+                data: Cow::Owned(text.into_bytes()),
+                filename: filename.to_string(),
+            })
+    })
+    .await?;
+
+    Ok(())
+}
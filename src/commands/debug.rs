@@ -1,14 +1,68 @@
+use std::sync::Mutex;
+
 use anyhow::{bail, Context as _};
+use poise::serenity_prelude as serenity;
 
-use super::super::{drql, Context};
-use crate::drql::ast::Expr;
+use super::{
+    super::{drql, Context},
+    reply_or_attach,
+};
+use crate::{
+    drql::{ast::Expr, interpreter::InterpreterError},
+    resolver,
+};
 
 /// Debug DRQL queries or the DRQL facilities itself
-#[poise::command(slash_command, subcommands("scan", "parse_one", "reduce"))]
+#[poise::command(
+    slash_command,
+    subcommands(
+        "scan",
+        "parse_one",
+        "reduce",
+        "optimize",
+        "normalize",
+        "equivalent",
+        "explain"
+    )
+)]
 pub async fn debug(_ctx: Context<'_>) -> Result<(), anyhow::Error> {
     bail!("unreachable");
 }
 
+/// How to show an AST
+#[derive(Debug, Clone, Copy, Default, poise::ChoiceParameter)]
+enum AstFormat {
+    /// An indented tree, see [`drql::render::ascii_tree`]
+    #[default]
+    #[name = "Tree"]
+    Tree,
+    /// A Graphviz graph, see [`drql::render::dot`]
+    #[name = "Graphviz DOT"]
+    Dot,
+}
+
+/// Reply with an AST rendered in `format` after `heading`, attaching it if it's too long
+async fn reply_with_ast(
+    ctx: Context<'_>,
+    heading: &str,
+    ast: &Expr,
+    format: AstFormat,
+) -> Result<(), anyhow::Error> {
+    let (rendered, language, filename) = match format {
+        AstFormat::Tree => (drql::render::ascii_tree(ast), "", "ast.txt"),
+        AstFormat::Dot => (drql::render::dot(ast), "dot", "ast.dot"),
+    };
+
+    reply_or_attach(
+        ctx,
+        format!("{heading}\n\n```{language}\n{rendered}```"),
+        format!("{heading} The AST is attached."),
+        rendered,
+        filename,
+    )
+    .await
+}
+
 /// Scan input text for DRQL queries
 #[poise::command(slash_command)]
 async fn scan(
@@ -40,12 +94,26 @@ async fn scan(
 async fn parse_one(
     ctx: Context<'_>,
     #[description = "The DRQL query to parse (DO NOT include @{})"] query: String,
+    #[description = "How to show the AST (defaults to a tree)"] format: Option<AstFormat>,
 ) -> Result<(), anyhow::Error> {
-    ctx.say(match drql::parser::parse_drql(query.as_str()) {
-        Err(err) => format!("Encountered an error while parsing:\n\n```{err:?}```"),
-        Ok(ast) => format!("Successfully parsed:\n\n```{ast:?}```"),
-    })
-    .await?;
+    match drql::parser::parse_drql(query.as_str()) {
+        Err(errors) => {
+            ctx.say(format!(
+                "Encountered an error while parsing:\n\n{}",
+                drql::diagnostics::describe_parse_errors(&query, &errors)
+            ))
+            .await?;
+        }
+        Ok(ast) => {
+            reply_with_ast(
+                ctx,
+                "Successfully parsed:",
+                &ast,
+                format.unwrap_or_default(),
+            )
+            .await?;
+        }
+    }
 
     Ok(())
 }
@@ -55,26 +123,204 @@ async fn parse_one(
 async fn reduce(
     ctx: Context<'_>,
     #[description = "The message to scan"] msg: String,
+    #[description = "How to show the AST (defaults to a tree)"] format: Option<AstFormat>,
+) -> Result<(), anyhow::Error> {
+    match drql::parser::parse_drql_chunks(
+        &drql::scanner::scan_with_offsets(msg.as_str()).collect::<Vec<_>>(),
+    ) {
+        Err(errors) => {
+            ctx.say(format!(
+                "Encountered an error while parsing:\n\n{}",
+                drql::diagnostics::describe_chunk_parse_errors(&errors)
+            ))
+            .await?;
+        }
+        Ok(asts) => match Expr::union_all(asts) {
+            None => {
+                ctx.say("No chunks found.").await?;
+            }
+            Some(ast) => {
+                reply_with_ast(
+                    ctx,
+                    "Success! Resulting AST:",
+                    &ast,
+                    format.unwrap_or_default(),
+                )
+                .await?;
+            }
+        },
+    }
+
+    Ok(())
+}
+
+/// Scan and parse the input, then show the reduced tree before and after optimization
+#[poise::command(slash_command)]
+async fn optimize(
+    ctx: Context<'_>,
+    #[description = "The message to scan"] msg: String,
 ) -> Result<(), anyhow::Error> {
     ctx.say(
-        match drql::scanner::scan(msg.as_str())
-            .enumerate()
-            .map(|(n, chunk)| {
-                drql::parser::parse_drql(chunk).context(format!("Error parsing chunk {n}"))
-            })
-            .collect::<Result<Vec<_>, _>>()
-        {
-            Err(err) => format!("Encountered an error while parsing:\n\n```{err:#}```"),
-            Ok(ast) => ast
-                .into_iter()
-                .reduce(|acc, chunk| Expr::Union(Box::new(acc), Box::new(chunk)))
-                .map_or_else(
-                    || "No chunks found.".to_string(),
-                    |ast| format!("Success! Resulting AST:\n\n```{ast:?}```"),
-                ),
+        match drql::parser::parse_drql_chunks(
+            &drql::scanner::scan_with_offsets(msg.as_str()).collect::<Vec<_>>(),
+        ) {
+            Err(errors) => format!(
+                "Encountered an error while parsing:\n\n{}",
+                drql::diagnostics::describe_chunk_parse_errors(&errors)
+            ),
+            Ok(asts) => Expr::union_all(asts).map_or_else(
+                || "No chunks found.".to_string(),
+                |ast| {
+                    let before = ast.to_string();
+                    format!(
+                        "Before optimization:\n```{before}```\nAfter optimization:\n{}",
+                        drql::optimizer::optimize(ast, None).map_or_else(
+                            || "Always empty.".to_string(),
+                            |ast| format!("```{ast}```")
+                        )
+                    )
+                },
+            ),
         },
     )
     .await?;
 
     Ok(())
 }
+
+/// Parse a single DRQL query, describing any errors in a way that's suitable for replying with
+fn parse_query(query: &str) -> Result<Expr, String> {
+    drql::parser::parse_drql(query).map_err(|errors| {
+        format!(
+            "Encountered an error while parsing `{query}`:\n\n{}",
+            drql::diagnostics::describe_parse_errors(query, &errors)
+        )
+    })
+}
+
+/// Convert a single DRQL query to its canonical disjunctive normal form
+#[poise::command(slash_command)]
+async fn normalize(
+    ctx: Context<'_>,
+    #[description = "The DRQL query to normalize (DO NOT include @{})"] query: String,
+) -> Result<(), anyhow::Error> {
+    ctx.say(match parse_query(&query) {
+        Err(message) => message,
+        Ok(ast) => match drql::normal_form::NormalForm::new(&ast) {
+            Err(error) => error.to_string(),
+            Ok(normal_form) => format!(
+                "Normal form:\n\n```{normal_form}```{}",
+                if normal_form.is_empty() {
+                    "\nThis query never matches anyone."
+                } else if normal_form.is_everyone() {
+                    "\nThis query always matches everyone."
+                } else {
+                    ""
+                }
+            ),
+        },
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// Determine whether two DRQL queries always match the same members
+#[poise::command(slash_command)]
+async fn equivalent(
+    ctx: Context<'_>,
+    #[description = "The first DRQL query (DO NOT include @{})"] first: String,
+    #[description = "The second DRQL query (DO NOT include @{})"] second: String,
+) -> Result<(), anyhow::Error> {
+    ctx.say(match (parse_query(&first), parse_query(&second)) {
+        (Err(message), _) | (_, Err(message)) => message,
+        (Ok(first), Ok(second)) => match drql::normal_form::distinguish(&first, &second) {
+            Err(error) => error.to_string(),
+            Ok(None) => "The queries are equivalent.".to_string(),
+            Ok(Some(distinction)) => format!("The queries are not equivalent. {distinction}"),
+        },
+    })
+    .await?;
+
+    Ok(())
+}
+
+/// Run a single DRQL query, showing how many members each part of it matched and how long it took
+#[poise::command(slash_command, guild_only)]
+async fn explain(
+    ctx: Context<'_>,
+    #[description = "The DRQL query to explain (DO NOT include @{})"] query: String,
+) -> Result<(), anyhow::Error> {
+    let ast = match parse_query(&query) {
+        Err(message) => {
+            ctx.say(message).await?;
+            return Ok(());
+        }
+        Ok(ast) => ast,
+    };
+
+    // Resolving the query may require asking the author what they meant, which can take longer
+    // than Discord waits for a response
+    ctx.defer().await?;
+
+    let guild = ctx.guild().context("Unable to resolve guild")?;
+    let member = ctx.author_member().await.context("Error fetching member")?;
+    let channel = ctx
+        .guild_channel()
+        .await
+        .context("Error fetching channel")?;
+    let settings = ctx.data().settings.get(guild.id);
+    settings.limits.check(&ast)?;
+
+    let resolver = resolver::Resolver {
+        guild: &guild,
+        member: &member,
+        ctx: ctx.serenity_context(),
+        channel: &channel,
+        settings: &settings,
+        prompt_target: resolver::PromptTarget::Command(ctx),
+        targets: Mutex::default(),
+        prompt_lock: tokio::sync::Mutex::default(),
+    };
+    let unable_to_explain = |InterpreterError { span, error }: InterpreterError<anyhow::Error>| {
+        error.context(format!(
+            "Unable to explain `{}`",
+            query.get(span.chunk_range()).unwrap_or(&query)
+        ))
+    };
+
+    // Explain the same tree that running the query would interpret
+    let (ast, _) = drql::binder::bind(ast, &resolver)
+        .await
+        .map_err(unable_to_explain)?;
+    let Some(ast) = drql::optimizer::optimize(ast, Some(serenity::RoleId(guild.id.0))) else {
+        ctx.say("The query matches 0 members, as it is always empty.")
+            .await?;
+        return Ok(());
+    };
+
+    let (members, explanation) = drql::interpreter::explain::<_, serenity::UserId>(
+        ast,
+        &resolver,
+        drql::interpreter::InterpretOptions {
+            validate_skipped: settings.validate_skipped_branches,
+        },
+    )
+    .await
+    .map_err(unable_to_explain)?;
+
+    reply_or_attach(
+        ctx,
+        format!(
+            "The query matches {} members:\n```\n{explanation}```",
+            members.len()
+        ),
+        format!(
+            "The query matches {} members. The explanation is attached.",
+            members.len()
+        ),
+        explanation.to_string(),
+        "explain.txt",
+    )
+    .await
+}
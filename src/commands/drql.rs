@@ -0,0 +1,42 @@
+use anyhow::bail;
+
+use super::{
+    super::{drql, Context},
+    reply_or_attach,
+};
+
+/// Work with DRQL queries
+#[poise::command(slash_command, subcommands("format"))]
+pub async fn drql(_ctx: Context<'_>) -> Result<(), anyhow::Error> {
+    bail!("unreachable");
+}
+
+/// Format a DRQL query consistently, for sharing it with others
+#[poise::command(slash_command)]
+async fn format(
+    ctx: Context<'_>,
+    #[description = "The DRQL query to format (DO NOT include @{})"] query: String,
+) -> Result<(), anyhow::Error> {
+    match drql::parser::parse_drql(&query) {
+        Err(errors) => {
+            ctx.say(format!(
+                "Encountered an error while parsing:\n\n{}",
+                drql::diagnostics::describe_parse_errors(&query, &errors)
+            ))
+            .await?;
+        }
+        Ok(ast) => {
+            let formatted = drql::formatter::format(&ast);
+            reply_or_attach(
+                ctx,
+                format!("```\n{formatted}\n```"),
+                "The formatted query is attached.".to_string(),
+                formatted,
+                "query.txt",
+            )
+            .await?;
+        }
+    }
+
+    Ok(())
+}
@@ -1,11 +1,47 @@
-use std::{borrow::Cow, fmt::Write as _};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    fmt::Write as _,
+    sync::Arc,
+};
 
 use anyhow::{bail, Context as _};
 use poise::serenity_prelude::{self as serenity};
 use tracing::{debug, trace};
 
 use super::super::Context;
-use crate::{extensions::CustomGuildImpl, models, parse_and_evaluate_query, util};
+use crate::{
+    drql, extensions::CustomGuildImpl, models, parse_and_evaluate_query, resolver, util,
+    EvaluatedQuery,
+};
+
+/// Count the mentions in a solution to [`util::unionize_set::unionize_set_exact_blocking`] once they
+/// are split into messages, and how many messages that is
+fn estimate(
+    result: &util::unionize_set::OwnedUnionizeSetResult<
+        models::mention::RoleType,
+        serenity::UserId,
+    >,
+    roles_and_their_members: &HashMap<models::mention::RoleType, HashSet<serenity::UserId>>,
+) -> anyhow::Result<(usize, usize)> {
+    let messages = util::split_mentions(
+        &result
+            .sets
+            .iter()
+            .map(|&role| (role, &roles_and_their_members[&role]))
+            .collect::<Vec<_>>(),
+        &result.outliers.iter().copied().collect::<Vec<_>>(),
+        2000,
+    )?;
+
+    Ok((
+        messages
+            .iter()
+            .map(|message| message.split(' ').count())
+            .sum(),
+        messages.len(),
+    ))
+}
 
 /// Run a DRQL query and test what it would do
 #[poise::command(slash_command, ephemeral)]
@@ -19,6 +55,10 @@ pub async fn dry_run(
         bail!("DRQL queries are not available in DMs.");
     }
 
+    // Resolving the query may require asking the author what they meant, which can take longer
+    // than Discord waits for a response
+    ctx.defer_ephemeral().await?;
+
     trace!("Fetching guild, channel, and member information");
     let guild = ctx.guild().context("Unable to resolve guild")?;
     let member = ctx.author_member().await.context("Error fetching member")?;
@@ -26,24 +66,40 @@ pub async fn dry_run(
         .guild_channel()
         .await
         .context("Error fetching channel")?;
+    let settings = ctx.data().settings.get(guild.id);
 
     trace!("Running DRQL parser/interpreter on message");
-    let members_to_ping =
-        parse_and_evaluate_query(ctx.serenity_context(), &[&query], &guild, &member, &channel)
-            .await?;
-
-    // A hashmap of every role in the guild and its members.
-    let roles_and_their_members = guild.all_roles_and_members(ctx.serenity_context())?;
-
-    // next, we represent the list of users as a bunch of roles containing them and one outliers set.
-    let util::unionize_set::UnionizeSetResult { sets, outliers } =
-        util::unionize_set::unionize_set(&members_to_ping, &roles_and_their_members);
+    let EvaluatedQuery {
+        members_to_ping,
+        lints,
+        bindings,
+        ..
+    } = parse_and_evaluate_query(
+        ctx.serenity_context(),
+        &[(0, &query)],
+        &guild,
+        &member,
+        &channel,
+        &settings,
+        resolver::PromptTarget::Command(ctx),
+    )
+    .await?;
 
-    debug!(
-        "unionize_set result sets: {sets:?}, outliers: {outliers:?}",
-        sets = sets,
-        outliers = outliers
-    );
+    // Warnings are always shown here, since checking a query is the whole point of a dry run
+    let warnings = if lints.is_empty() {
+        String::new()
+    } else {
+        format!("{}\n\n", drql::lint::describe_lints(&query, &lints))
+    };
+    // So that the author can check that every name means what they meant
+    let preamble = if bindings.is_empty() {
+        warnings
+    } else {
+        format!(
+            "{warnings}Your query was read as: {}\n\n",
+            drql::binder::describe_bindings(&bindings)
+        )
+    };
 
     // Now stringify solely the USERS we want to ping...
     let stringified_mentions = members_to_ping
@@ -55,47 +111,100 @@ pub async fn dry_run(
 
     if stringified_mentions.is_empty() {
         debug!("Nobody to mention!");
-        ctx.say("Your query matches 0 users.").await?;
+        ctx.say(format!("{preamble}Your query matches 0 users."))
+            .await?;
         return Ok(());
     }
 
-    let message_count_if_optimized = util::wrap_string_vec(
-        &sets
-            .iter()
-            .copied()
-            .map(|id| models::mention::Mention::Role(*id))
-            .chain(
-                outliers
-                    .iter()
-                    .map(|&id| models::mention::Mention::User(*id)),
-            )
-            .map(|x| x.to_string())
-            .collect::<Vec<_>>(),
-        " ",
-        2000,
-    )?
-    .len();
+    // A hashmap of every role in the guild that can be mentioned here, and its members. Members of
+    // any other role would silently go without a notification if it were chosen.
+    let bot = guild
+        .member(
+            ctx.serenity_context(),
+            ctx.serenity_context().cache.current_user_id(),
+        )
+        .await
+        .context("Error fetching the bot's member")?;
+    let roles_and_their_members = Arc::new(guild.mentionable_roles_and_members(
+        ctx.serenity_context(),
+        &member,
+        &bot,
+        &channel,
+    )?);
+    let members_to_ping = Arc::new(members_to_ping);
+
+    // Queries are sent with the mentions that fit in the fewest messages, but the fewest mentions
+    // are estimated too for comparison. Both searches run at the same time.
+    let (fewest_messages, fewest_mentions) = tokio::try_join!(
+        util::unionize_set::unionize_set_exact_blocking(
+            Arc::clone(&members_to_ping),
+            Arc::clone(&roles_and_their_members),
+            util::unionize_set::RenderedLength,
+            settings.solver_budget,
+            settings.overshoot,
+        ),
+        util::unionize_set::unionize_set_exact_blocking(
+            Arc::clone(&members_to_ping),
+            Arc::clone(&roles_and_their_members),
+            util::unionize_set::MentionCount,
+            settings.solver_budget,
+            settings.overshoot,
+        ),
+    )?;
+
+    debug!("unionize_set result: {fewest_messages:?}");
 
-    debug!(
-        "stringified_mentions: {stringified_mentions:?}",
-        stringified_mentions = stringified_mentions
+    let (mentions, messages) = estimate(&fewest_messages, &roles_and_their_members)?;
+    let (fewest_mention_count, messages_with_fewest_mentions) =
+        estimate(&fewest_mentions, &roles_and_their_members)?;
+    let summary = format!(
+        concat!(
+            "This will require sending {} messages",
+            " (optimized by pinging {} roles, saving you {} mentions).\n",
+            "Estimates: {} mentions in {} messages when minimizing mentions,",
+            " or {} mentions in {} messages when minimizing messages."
+        ),
+        messages,
+        fewest_messages.sets.len(),
+        stringified_mentions.len().saturating_sub(mentions),
+        fewest_mention_count,
+        messages_with_fewest_mentions,
+        mentions,
+        messages,
     );
 
+    // The roles that are mentioned may notify people the query doesn't match, if the server
+    // allows it, and they have to be listed too
+    let mut extras = fewest_messages.extras.iter().copied().collect::<Vec<_>>();
+    extras.sort_unstable();
+    let extras_summary = if extras.is_empty() {
+        String::new()
+    } else {
+        format!(
+            "\nTo save mentions, this will also notify {} users your query doesn't match",
+            extras.len()
+        )
+    };
+
     let message_header = format!(
-        "Your query matches the following {} users:\n",
+        "{preamble}Your query matches the following {} users:\n",
         stringified_mentions.len()
     );
-    let message_footer = format!(
-        concat!(
-            "\n\nThis will require sending {} messages.",
-            " (optimized by pinging {} roles, saving you {} mentions)."
-        ),
-        message_count_if_optimized,
-        sets.len(),
-        stringified_mentions.len() - (sets.len() + outliers.len())
-    );
+    let message_footer = if extras.is_empty() {
+        format!("\n\n{summary}")
+    } else {
+        format!(
+            "\n\n{summary}{extras_summary}: {}",
+            extras
+                .iter()
+                .map(|&id| models::mention::Mention::User(id).to_string())
+                .collect::<Vec<_>>()
+                .join(" ")
+        )
+    };
 
-    if stringified_mentions.join(" ").len() <= (2000 - message_header.len() - message_footer.len())
+    if stringified_mentions.join(" ").len()
+        <= 2000_usize.saturating_sub(message_header.len() + message_footer.len())
     {
         debug!("All mentions fit in one message!");
         ctx.say(format!(
@@ -111,7 +220,7 @@ pub async fn dry_run(
     debug!("Mentions do not fit in one message, using text file");
 
     let mut file_contents = String::new();
-    for id in &members_to_ping {
+    for id in members_to_ping.iter() {
         let member = guild.member(ctx.serenity_context(), *id).await?;
         writeln!(
             &mut file_contents,
@@ -119,19 +228,32 @@ pub async fn dry_run(
             member.user.name, member.user.discriminator, member.user.id
         )?;
     }
+    if !extras.is_empty() {
+        writeln!(
+            &mut file_contents,
+            "\nAlso notified, though your query doesn't match them:"
+        )?;
+        for id in &extras {
+            let member = guild.member(ctx.serenity_context(), *id).await?;
+            writeln!(
+                &mut file_contents,
+                "{}#{} ({})",
+                member.user.name, member.user.discriminator, member.user.id
+            )?;
+        }
+    }
 
     ctx.send(|builder| {
         builder
             .content(format!(
-                concat!(
-                    "Your query matches the attached {} users.",
-                    " This will require sending {} messages",
-                    " (optimized by pinging {} roles, saving you {} mentions)."
-                ),
+                "{}Your query matches the attached {} users. {summary}{}",
+                preamble,
                 stringified_mentions.len(),
-                message_count_if_optimized,
-                sets.len(),
-                stringified_mentions.len() - (sets.len() + outliers.len())
+                if extras.is_empty() {
+                    String::new()
+                } else {
+                    format!("{extras_summary}, listed at the end of the file.")
+                },
             ))
             .attachment(serenity::AttachmentType::Bytes {
                 data: Cow::Borrowed(file_contents.as_bytes()),
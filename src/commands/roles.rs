@@ -0,0 +1,148 @@
+use std::{cmp::Reverse, collections::HashSet, fmt::Write as _, sync::Mutex};
+
+use anyhow::{bail, Context as _};
+use poise::serenity_prelude as serenity;
+use tracing::debug;
+
+use super::{
+    super::{drql, Context},
+    reply_or_attach,
+};
+use crate::{drql::interpreter::InterpreterError, resolver};
+
+/// Work with the roles of the server
+#[poise::command(slash_command, subcommands("query"))]
+pub async fn roles(_ctx: Context<'_>) -> Result<(), anyhow::Error> {
+    bail!("unreachable");
+}
+
+/// Name each role, listing them in the order Discord shows roles in, from the highest
+fn named_roles(
+    guild: &serenity::Guild,
+    roles: HashSet<serenity::RoleId>,
+) -> Vec<(serenity::RoleId, String)> {
+    let mut roles = roles
+        .into_iter()
+        .map(|id| (id, guild.roles.get(&id)))
+        .collect::<Vec<_>>();
+    roles.sort_by_key(|&(id, role)| (Reverse(role.map_or(0, |role| role.position)), id));
+
+    roles
+        .into_iter()
+        .map(|(id, role)| {
+            let name = role.map_or_else(|| id.to_string(), |role| role.name.clone());
+            (id, name)
+        })
+        .collect()
+}
+
+/// List the roles a DRQL query matches, like `roles_of(alice) & roles_of(bob)`, without pinging
+/// anyone
+#[poise::command(slash_command, guild_only, ephemeral)]
+async fn query(
+    ctx: Context<'_>,
+    #[description = "The DRQL query over roles (DO NOT include @{})"] query: String,
+) -> Result<(), anyhow::Error> {
+    let ast = match drql::parser::parse_drql(&query) {
+        Err(errors) => {
+            ctx.say(format!(
+                "Encountered an error while parsing:\n\n{}",
+                drql::diagnostics::describe_parse_errors(&query, &errors)
+            ))
+            .await?;
+            return Ok(());
+        }
+        Ok(ast) => ast,
+    };
+
+    // Resolving the query may require asking the author what they meant, which can take longer
+    // than Discord waits for a response
+    ctx.defer_ephemeral().await?;
+
+    let guild = ctx.guild().context("Unable to resolve guild")?;
+    let member = ctx.author_member().await.context("Error fetching member")?;
+    let channel = ctx
+        .guild_channel()
+        .await
+        .context("Error fetching channel")?;
+    let settings = ctx.data().settings.get(guild.id);
+    settings.limits.check(&ast)?;
+
+    let resolver = resolver::Resolver {
+        guild: &guild,
+        member: &member,
+        ctx: ctx.serenity_context(),
+        channel: &channel,
+        settings: &settings,
+        prompt_target: resolver::PromptTarget::Command(ctx),
+        targets: Mutex::default(),
+        prompt_lock: tokio::sync::Mutex::default(),
+    };
+    let locate = |InterpreterError { span, error }: InterpreterError<anyhow::Error>| {
+        error.context(format!(
+            "Unable to find the roles for `{}`",
+            query.get(span.chunk_range()).unwrap_or(&query)
+        ))
+    };
+
+    // The optimizer and lints assume the query is over members, where `everyone` matches
+    // everything, so neither is used here
+    let (ast, bindings) = drql::binder::bind(ast, &resolver).await.map_err(locate)?;
+    let roles = drql::interpreter::interpret::<_, serenity::RoleId>(
+        ast,
+        &resolver,
+        drql::interpreter::InterpretOptions {
+            validate_skipped: settings.validate_skipped_branches,
+        },
+    )
+    .await
+    .map_err(locate)?;
+    debug!("Query matched roles: {roles:?}");
+
+    let roles = named_roles(&guild, roles);
+    let preamble = if bindings.is_empty() {
+        String::new()
+    } else {
+        format!(
+            "Your query was read as: {}\n\n",
+            drql::binder::describe_bindings(&bindings)
+        )
+    };
+    if roles.is_empty() {
+        ctx.say(format!("{preamble}Your query matches no roles."))
+            .await?;
+        return Ok(());
+    }
+
+    // This reply is ephemeral, so mentioning the roles in it doesn't ping anyone
+    let mentions = roles
+        .iter()
+        .map(|(id, _)| {
+            if id.0 == guild.id.0 {
+                "@everyone".to_string()
+            } else {
+                format!("<@&{id}>")
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    let mut names = String::new();
+    for (id, name) in &roles {
+        writeln!(names, "{name} ({id})")?;
+    }
+
+    reply_or_attach(
+        ctx,
+        format!(
+            "{preamble}Your query matches the following {} roles:\n{mentions}",
+            roles.len()
+        ),
+        format!(
+            "{preamble}Your query matches the attached {} roles.",
+            roles.len()
+        ),
+        names,
+        "roles.txt",
+    )
+    .await
+}
@@ -0,0 +1,323 @@
+use anyhow::{bail, Context as _};
+
+use super::super::Context;
+use crate::{
+    drql::limits::QueryLimits,
+    util::unionize_set::{Overshoot, SolverBudget},
+};
+
+/// Describe whether a setting is enabled
+const fn enabled_str(enabled: bool) -> &'static str {
+    if enabled {
+        "enabled"
+    } else {
+        "disabled"
+    }
+}
+
+/// Describe the limits on how complex queries may be
+fn describe_limits(limits: &QueryLimits) -> String {
+    format!(
+        concat!(
+            "Queries per message: {}, names/IDs/operators per query: {},",
+            " nesting depth: {}, distinct names/IDs per query: {},",
+            " conversions per query: {}"
+        ),
+        limits.chunks, limits.nodes, limits.depth, limits.lookups, limits.conversions
+    )
+}
+
+/// Describe how long Intersection searches for the fewest mentions
+fn describe_solver_budget(budget: &SolverBudget) -> String {
+    format!(
+        "Search for the fewest mentions for up to {} ms or {} steps",
+        budget.time_ms, budget.nodes
+    )
+}
+
+/// Describe how many people a query doesn't match may be notified
+fn describe_overshoot(overshoot: Overshoot) -> String {
+    match overshoot {
+        Overshoot::Disabled => "Only mention roles whose members all match the query".to_string(),
+        Overshoot::Values(users) => {
+            format!("Mention roles that notify up to {users} people the query doesn't match")
+        }
+        Overshoot::Percent(percent) => format!(
+            "Mention roles that notify up to {percent}% as many people as the query matches, who \
+             it doesn't match"
+        ),
+    }
+}
+
+/// View or change Intersection's settings for this server
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "MANAGE_GUILD",
+    subcommands(
+        "view",
+        "case_insensitive",
+        "lint_warnings",
+        "validate_skipped",
+        "limits",
+        "solver_budget",
+        "overshoot"
+    )
+)]
+pub async fn settings(_ctx: Context<'_>) -> Result<(), anyhow::Error> {
+    bail!("unreachable");
+}
+
+/// View this server's settings
+#[poise::command(slash_command, guild_only, ephemeral)]
+async fn view(ctx: Context<'_>) -> Result<(), anyhow::Error> {
+    let guild_id = ctx.guild_id().context("Unable to resolve guild")?;
+    let settings = ctx.data().settings.get(guild_id);
+
+    ctx.say(format!(
+        concat!(
+            "**Settings for this server:**\n",
+            "- Accept case-insensitive name matches: {}\n",
+            "- Warn about suspicious queries in replies: {}\n",
+            "- Check names in skipped parts of queries: {}\n",
+            "- {}\n",
+            "- {}\n",
+            "- {}",
+        ),
+        enabled_str(settings.auto_accept_case_insensitive),
+        enabled_str(settings.show_lint_warnings),
+        enabled_str(settings.validate_skipped_branches),
+        describe_limits(&settings.limits),
+        describe_solver_budget(&settings.solver_budget),
+        describe_overshoot(settings.overshoot)
+    ))
+    .await?;
+
+    Ok(())
+}
+
+/// Use a name's only case-insensitive match when nothing matches it exactly
+#[poise::command(
+    slash_command,
+    guild_only,
+    ephemeral,
+    required_permissions = "MANAGE_GUILD"
+)]
+async fn case_insensitive(
+    ctx: Context<'_>,
+    #[description = "Whether to accept case-insensitive matches"] enabled: bool,
+) -> Result<(), anyhow::Error> {
+    let guild_id = ctx.guild_id().context("Unable to resolve guild")?;
+    ctx.data()
+        .settings
+        .update(guild_id, |settings| {
+            settings.auto_accept_case_insensitive = enabled;
+        })
+        .await?;
+
+    ctx.say(format!(
+        "Accepting case-insensitive name matches is now {}.",
+        enabled_str(enabled)
+    ))
+    .await?;
+
+    Ok(())
+}
+
+/// Warn about suspicious queries, like ones that can never match anyone, when replying to them
+#[poise::command(
+    slash_command,
+    guild_only,
+    ephemeral,
+    required_permissions = "MANAGE_GUILD"
+)]
+async fn lint_warnings(
+    ctx: Context<'_>,
+    #[description = "Whether to include warnings in replies to queries"] enabled: bool,
+) -> Result<(), anyhow::Error> {
+    let guild_id = ctx.guild_id().context("Unable to resolve guild")?;
+    ctx.data()
+        .settings
+        .update(guild_id, |settings| {
+            settings.show_lint_warnings = enabled;
+        })
+        .await?;
+
+    ctx.say(format!(
+        "Warnings about suspicious queries are now {} in replies.",
+        enabled_str(enabled)
+    ))
+    .await?;
+
+    Ok(())
+}
+
+/// Check names and IDs in parts of queries that are skipped, like `b` in `a & b` when `a` is empty
+#[poise::command(
+    slash_command,
+    guild_only,
+    ephemeral,
+    required_permissions = "MANAGE_GUILD"
+)]
+async fn validate_skipped(
+    ctx: Context<'_>,
+    #[description = "Whether to report typos in skipped parts of queries"] enabled: bool,
+) -> Result<(), anyhow::Error> {
+    let guild_id = ctx.guild_id().context("Unable to resolve guild")?;
+    ctx.data()
+        .settings
+        .update(guild_id, |settings| {
+            settings.validate_skipped_branches = enabled;
+        })
+        .await?;
+
+    ctx.say(format!(
+        "Checking names in skipped parts of queries is now {}.",
+        enabled_str(enabled)
+    ))
+    .await?;
+
+    Ok(())
+}
+
+/// Change how complex queries may be. Any limits you leave out stay the same.
+#[poise::command(
+    slash_command,
+    guild_only,
+    ephemeral,
+    required_permissions = "MANAGE_GUILD"
+)]
+async fn limits(
+    ctx: Context<'_>,
+    #[description = "The most queries one message may contain"]
+    #[min = 1]
+    chunks: Option<usize>,
+    #[description = "The most names, IDs, and operators a query may contain"]
+    #[min = 1]
+    nodes: Option<usize>,
+    #[description = "How deeply a query may be nested"]
+    #[min = 1]
+    depth: Option<usize>,
+    #[description = "The most distinct names and IDs a query may contain"]
+    #[min = 1]
+    lookups: Option<usize>,
+    #[description = "The most roles_of(...) and members_of(...) conversions a query may contain"]
+    #[min = 0]
+    conversions: Option<usize>,
+) -> Result<(), anyhow::Error> {
+    let guild_id = ctx.guild_id().context("Unable to resolve guild")?;
+    let maximum = QueryLimits::MAXIMUM;
+    for (name, value, max) in [
+        ("queries per message", chunks, maximum.chunks),
+        ("names, IDs, and operators", nodes, maximum.nodes),
+        ("nesting depth", depth, maximum.depth),
+        ("distinct names and IDs", lookups, maximum.lookups),
+        ("conversions", conversions, maximum.conversions),
+    ] {
+        if value.is_some_and(|value| value > max) {
+            bail!("The limit on {name} can be at most {max}.");
+        }
+    }
+
+    let settings = ctx
+        .data()
+        .settings
+        .update(guild_id, |settings| {
+            let limits = &mut settings.limits;
+            limits.chunks = chunks.unwrap_or(limits.chunks);
+            limits.nodes = nodes.unwrap_or(limits.nodes);
+            limits.depth = depth.unwrap_or(limits.depth);
+            limits.lookups = lookups.unwrap_or(limits.lookups);
+            limits.conversions = conversions.unwrap_or(limits.conversions);
+        })
+        .await?;
+
+    ctx.say(format!(
+        "Query limits are now: {}.",
+        describe_limits(&settings.limits)
+    ))
+    .await?;
+
+    Ok(())
+}
+
+/// Change how long to search for the fewest mentions. Anything you leave out stays the same.
+#[poise::command(
+    slash_command,
+    guild_only,
+    ephemeral,
+    required_permissions = "MANAGE_GUILD"
+)]
+async fn solver_budget(
+    ctx: Context<'_>,
+    #[description = "How many milliseconds to search for"] time_ms: Option<u64>,
+    #[description = "How many steps to search for"] nodes: Option<usize>,
+) -> Result<(), anyhow::Error> {
+    let guild_id = ctx.guild_id().context("Unable to resolve guild")?;
+    let maximum = SolverBudget::MAXIMUM;
+    if time_ms.is_some_and(|time_ms| time_ms > maximum.time_ms) {
+        bail!("The search can take at most {} ms.", maximum.time_ms);
+    }
+    if nodes.is_some_and(|nodes| nodes > maximum.nodes) {
+        bail!("The search can take at most {} steps.", maximum.nodes);
+    }
+
+    let settings = ctx
+        .data()
+        .settings
+        .update(guild_id, |settings| {
+            let budget = &mut settings.solver_budget;
+            budget.time_ms = time_ms.unwrap_or(budget.time_ms);
+            budget.nodes = nodes.unwrap_or(budget.nodes);
+        })
+        .await?;
+
+    ctx.say(format!(
+        "{}.",
+        describe_solver_budget(&settings.solver_budget)
+    ))
+    .await?;
+
+    Ok(())
+}
+
+/// Let roles notify a few people a query doesn't match. Leave both options out to turn this off.
+#[poise::command(
+    slash_command,
+    guild_only,
+    ephemeral,
+    required_permissions = "MANAGE_GUILD"
+)]
+async fn overshoot(
+    ctx: Context<'_>,
+    #[description = "The most people a query doesn't match who may be notified"] users: Option<
+        usize,
+    >,
+    #[description = "The most people a query doesn't match who may be notified, as a percentage"]
+    #[max = 100]
+    percent: Option<usize>,
+) -> Result<(), anyhow::Error> {
+    let guild_id = ctx.guild_id().context("Unable to resolve guild")?;
+    let overshoot = match (users, percent) {
+        (Some(_), Some(_)) => bail!("Choose either a number of people or a percentage, not both."),
+        (Some(users), None) => Overshoot::Values(users),
+        (None, Some(percent)) if percent > Overshoot::MAX_PERCENT => {
+            bail!("The percentage can be at most {}.", Overshoot::MAX_PERCENT)
+        }
+        (None, Some(percent)) => Overshoot::Percent(percent),
+        (None, None) => Overshoot::Disabled,
+    };
+
+    let settings = ctx
+        .data()
+        .settings
+        .update(guild_id, |settings| {
+            settings.overshoot = overshoot;
+        })
+        .await?;
+
+    ctx.say(format!("{}.", describe_overshoot(settings.overshoot)))
+        .await?;
+
+    Ok(())
+}
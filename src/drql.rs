@@ -3,7 +3,16 @@
 //! This module provides all of the tools you could ever need to work with DRQL.
 
 pub mod ast;
+pub mod binder;
+pub mod diagnostics;
+pub mod formatter;
 pub mod interpreter;
 pub mod lexer;
+pub mod limits;
+pub mod lint;
+pub mod member_set;
+pub mod normal_form;
+pub mod optimizer;
 pub mod parser;
+pub mod render;
 pub mod scanner;
@@ -1,53 +1,184 @@
 //! DRQL's Abstract Syntax Tree
 
-use std::fmt::{Display, Formatter};
+use std::{
+    fmt::{Display, Formatter},
+    ops::Range,
+};
 
 use poise::serenity_prelude::model::prelude::{RoleId, UserId};
 
+/// A region of source text that some piece of a query was parsed from
+///
+/// Spans are stored relative to the chunk (the contents of a single `@{ ... }`) they were parsed
+/// from, along with the offset of that chunk within the whole message, so that both chunk-relative
+/// and message-relative positions are available.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Span {
+    /// The byte offset this span starts at, relative to the start of its chunk
+    pub start: usize,
+    /// The byte offset this span ends at (exclusive), relative to the start of its chunk
+    pub end: usize,
+    /// The byte offset of the start of the chunk within the whole message
+    pub chunk_offset: usize,
+}
+
+impl Span {
+    /// Create a new [`Span`] from chunk-relative offsets and the offset of that chunk
+    pub const fn new(start: usize, end: usize, chunk_offset: usize) -> Self {
+        Self {
+            start,
+            end,
+            chunk_offset,
+        }
+    }
+
+    /// The range of bytes this span covers within its chunk
+    pub const fn chunk_range(&self) -> Range<usize> {
+        self.start..self.end
+    }
+
+    /// The range of bytes this span covers within the whole message
+    pub const fn message_range(&self) -> Range<usize> {
+        (self.chunk_offset + self.start)..(self.chunk_offset + self.end)
+    }
+
+    /// Create a span covering both this span and `other`
+    ///
+    /// If the two spans belong to different chunks, the resulting span is relative to the whole
+    /// message (it has a `chunk_offset` of 0).
+    pub fn to(self, other: Self) -> Self {
+        if self.chunk_offset == other.chunk_offset {
+            Self::new(
+                self.start.min(other.start),
+                self.end.max(other.end),
+                self.chunk_offset,
+            )
+        } else {
+            let (lhs, rhs) = (self.message_range(), other.message_range());
+            Self::new(lhs.start.min(rhs.start), lhs.end.max(rhs.end), 0)
+        }
+    }
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let Range { start, end } = self.message_range();
+        write!(f, "{start}..{end}")
+    }
+}
+
+/// The names that DRQL reads as keywords rather than role or member names, which have to be quoted
+/// to be used as names
+pub const KEYWORDS: [&str; 2] = ["roles_of", "members_of"];
+
+/// Determine if a name is one of DRQL's [`KEYWORDS`]
+pub fn is_keyword(name: &str) -> bool {
+    KEYWORDS.contains(&name)
+}
+
 /// Represents a single DRQL query, or a view into that query
+///
+/// Every node carries the [`Span`] of the source text it was parsed from as its last field.
 #[derive(Debug, PartialEq)]
 pub enum Expr {
     /// Represents the union of two expressions, `a + b` or `a | b`
-    Union(Box<Expr>, Box<Expr>),
+    Union(Box<Expr>, Box<Expr>, Span),
     /// Represents the intersection of two expressions, `a & b`
-    Intersection(Box<Expr>, Box<Expr>),
+    Intersection(Box<Expr>, Box<Expr>, Span),
     /// Represents the difference between two expressions, `a - b`
-    Difference(Box<Expr>, Box<Expr>),
+    Difference(Box<Expr>, Box<Expr>, Span),
+
+    /// The roles held by any of the members an expression matches, `roles_of(a)`
+    RolesOf(Box<Self>, Span),
+    /// The members who hold any of the roles an expression matches, `members_of(a)`
+    MembersOf(Box<Self>, Span),
 
     /// The name of a role itself, like `everyone`
-    StringLiteral(String),
+    StringLiteral(String, Span),
     /// Some ID. It could belong to a user or role.
-    UnknownID(String),
+    UnknownID(String, Span),
     /// An ID that's guaranteed to belong to a role.
     ///
     /// This is generated when a role is mentioned directly in a query.
-    UserID(UserId),
+    UserID(UserId, Span),
     /// An ID that's guaranteed to belong to a user.
     ///
     /// This is generated when a user is mentioned directly in a query.
-    RoleID(RoleId),
+    RoleID(RoleId, Span),
+}
+
+impl Expr {
+    /// The [`Span`] of source text this node was parsed from
+    pub const fn span(&self) -> Span {
+        match self {
+            Self::Union(_, _, span)
+            | Self::Intersection(_, _, span)
+            | Self::Difference(_, _, span)
+            | Self::RolesOf(_, span)
+            | Self::MembersOf(_, span)
+            | Self::StringLiteral(_, span)
+            | Self::UnknownID(_, span)
+            | Self::UserID(_, span)
+            | Self::RoleID(_, span) => *span,
+        }
+    }
+
+    /// Combine the ASTs of several chunks of a message into one, as the union of all of them.
+    ///
+    /// Returns [`None`] if there are no ASTs to combine.
+    pub fn union_all(asts: impl IntoIterator<Item = Self>) -> Option<Self> {
+        asts.into_iter().reduce(|acc, chunk| {
+            let span = acc.span().to(chunk.span());
+            Self::Union(Box::new(acc), Box::new(chunk), span)
+        })
+    }
+
+    /// Determine if two expressions have the same structure and contents, regardless of where
+    /// they were parsed from
+    pub fn eq_ignoring_spans(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Union(lhs, rhs, _), Self::Union(other_lhs, other_rhs, _))
+            | (Self::Intersection(lhs, rhs, _), Self::Intersection(other_lhs, other_rhs, _))
+            | (Self::Difference(lhs, rhs, _), Self::Difference(other_lhs, other_rhs, _)) => {
+                lhs.eq_ignoring_spans(other_lhs) && rhs.eq_ignoring_spans(other_rhs)
+            }
+            (Self::RolesOf(operand, _), Self::RolesOf(other_operand, _))
+            | (Self::MembersOf(operand, _), Self::MembersOf(other_operand, _)) => {
+                operand.eq_ignoring_spans(other_operand)
+            }
+            (Self::StringLiteral(lhs, _), Self::StringLiteral(rhs, _))
+            | (Self::UnknownID(lhs, _), Self::UnknownID(rhs, _)) => lhs == rhs,
+            (Self::UserID(lhs, _), Self::UserID(rhs, _)) => lhs == rhs,
+            (Self::RoleID(lhs, _), Self::RoleID(rhs, _)) => lhs == rhs,
+            _ => false,
+        }
+    }
 }
 
 impl Display for Expr {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::Union(lhs, rhs) => write!(f, "({lhs} | {rhs})"),
-            Self::Intersection(lhs, rhs) => write!(f, "({lhs} & {rhs})"),
-            Self::Difference(lhs, rhs) => write!(f, "({lhs} - {rhs})"),
+            Self::Union(lhs, rhs, _) => write!(f, "({lhs} | {rhs})"),
+            Self::Intersection(lhs, rhs, _) => write!(f, "({lhs} & {rhs})"),
+            Self::Difference(lhs, rhs, _) => write!(f, "({lhs} - {rhs})"),
+
+            Self::RolesOf(operand, _) => write!(f, "roles_of({operand})"),
+            Self::MembersOf(operand, _) => write!(f, "members_of({operand})"),
 
-            Self::StringLiteral(contents) => {
+            Self::StringLiteral(contents, _) => {
                 if contents
                     .chars()
                     .all(|char| char.is_ascii_alphanumeric() || char == '_')
+                    && !is_keyword(contents)
                 {
                     write!(f, "{contents}")
                 } else {
                     write!(f, "\"{contents}\"")
                 }
             }
-            Self::UnknownID(id) => write!(f, "{id}"),
-            Self::UserID(id) => write!(f, "<@{id}>"),
-            Self::RoleID(id) => write!(f, "<@&{id}>"),
+            Self::UnknownID(id, _) => write!(f, "{id}"),
+            Self::UserID(id, _) => write!(f, "<@{id}>"),
+            Self::RoleID(id, _) => write!(f, "<@&{id}>"),
         }
     }
 }
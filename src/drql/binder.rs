@@ -0,0 +1,344 @@
+//! Binding names and IDs in DRQL ASTs to the members and roles they refer to
+//!
+//! A name like `mods` could refer to a role or a member, and the resolver picks one without the
+//! author ever seeing which. Binding replaces every [`Expr::StringLiteral`] and [`Expr::UnknownID`]
+//! with an explicit [`Expr::UserID`] or [`Expr::RoleID`] before the query is interpreted, and
+//! describes each choice so that it can be shown to the author.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{Display, Formatter},
+};
+
+use poise::{
+    async_trait,
+    serenity_prelude::{RoleId, UserId},
+};
+use tokio::sync::Semaphore;
+
+use super::{
+    ast::{Expr, Span},
+    interpreter::{InterpreterError, MAX_CONCURRENT_RESOLUTIONS},
+};
+
+/// The most bindings [`describe_bindings`] will describe. They are all written on a single line,
+/// which stops being readable at a glance past about ten.
+const MAX_DESCRIBED_BINDINGS: usize = 10;
+
+/// The member or role a name or ID was bound to, along with a description of it for the author
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Bound {
+    /// A member, like "user Alice#0001"
+    User(UserId, String),
+    /// A role, like "role @Moderators"
+    Role(RoleId, String),
+}
+
+/// Describes the functions used to bind names and IDs in [`bind`]
+///
+/// Distinct names and IDs are bound concurrently, so these functions take `&self` and may be
+/// called again before an earlier call has finished. Implementations that ask the author a question
+/// must make sure only one question is asked at a time.
+#[allow(clippy::module_name_repetitions)]
+#[async_trait]
+pub trait BinderResolver<E> {
+    /// Determine which member or role a name refers to. Names that don't refer to a single member
+    /// or role, like `here`, can be left for the interpreter to resolve by returning [`None`].
+    async fn bind_string_literal(&self, literal: String) -> Result<Option<Bound>, E>;
+    /// Determine whether an ID belongs to a member or a role
+    async fn bind_unknown_id(&self, id: String) -> Result<Bound, E>;
+}
+
+/// A name or ID in a query, and what it was bound to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Binding {
+    /// The name or ID, as it is displayed in DRQL
+    pub source: String,
+    /// A description of the member or role it was bound to
+    pub target: String,
+}
+
+impl Display for Binding {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "`{}` \u{2192} `{}`", self.source, self.target)
+    }
+}
+
+/// A leaf that can be bound, without its [`Span`], so that every occurrence of it is bound the
+/// same way
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Unbound {
+    /// A name, see [`Expr::StringLiteral`]
+    Name(String),
+    /// An ID, see [`Expr::UnknownID`]
+    Id(String),
+}
+
+/// Collect every leaf that can be bound, in the order they appear, along with the span of each
+fn collect_unbound(node: &Expr, unbound: &mut Vec<(Unbound, Span, String)>) {
+    match node {
+        Expr::Union(lhs, rhs, _)
+        | Expr::Intersection(lhs, rhs, _)
+        | Expr::Difference(lhs, rhs, _) => {
+            collect_unbound(lhs, unbound);
+            collect_unbound(rhs, unbound);
+        }
+        Expr::RolesOf(operand, _) | Expr::MembersOf(operand, _) => {
+            collect_unbound(operand, unbound);
+        }
+        Expr::StringLiteral(name, span) => {
+            unbound.push((Unbound::Name(name.clone()), *span, node.to_string()));
+        }
+        Expr::UnknownID(id, span) => {
+            unbound.push((Unbound::Id(id.clone()), *span, node.to_string()));
+        }
+        Expr::UserID(..) | Expr::RoleID(..) => {}
+    }
+}
+
+/// Replace every leaf that was bound with the member or role it was bound to
+fn rebind(node: Expr, bound: &HashMap<Unbound, Option<Bound>>) -> Expr {
+    let replace = |unbound: Unbound, node: Expr| match bound.get(&unbound) {
+        Some(Some(Bound::User(id, _))) => Expr::UserID(*id, node.span()),
+        Some(Some(Bound::Role(id, _))) => Expr::RoleID(*id, node.span()),
+        Some(None) | None => node,
+    };
+
+    match node {
+        Expr::Union(lhs, rhs, span) => Expr::Union(
+            Box::new(rebind(*lhs, bound)),
+            Box::new(rebind(*rhs, bound)),
+            span,
+        ),
+        Expr::Intersection(lhs, rhs, span) => Expr::Intersection(
+            Box::new(rebind(*lhs, bound)),
+            Box::new(rebind(*rhs, bound)),
+            span,
+        ),
+        Expr::Difference(lhs, rhs, span) => Expr::Difference(
+            Box::new(rebind(*lhs, bound)),
+            Box::new(rebind(*rhs, bound)),
+            span,
+        ),
+        Expr::RolesOf(operand, span) => Expr::RolesOf(Box::new(rebind(*operand, bound)), span),
+        Expr::MembersOf(operand, span) => Expr::MembersOf(Box::new(rebind(*operand, bound)), span),
+        Expr::StringLiteral(ref name, _) => replace(Unbound::Name(name.clone()), node),
+        Expr::UnknownID(ref id, _) => replace(Unbound::Id(id.clone()), node),
+        Expr::UserID(..) | Expr::RoleID(..) => node,
+    }
+}
+
+/// Bind every name and ID in a DRQL AST to the member or role it refers to.
+///
+/// Each distinct name or ID is only bound once, with at most [`MAX_CONCURRENT_RESOLUTIONS`] being
+/// bound at the same time, since binding may look members up over HTTP. Returns the bound AST,
+/// which keeps the spans of the original, along with a [`Binding`] for each distinct name or ID
+/// that was bound, in the order they first appear. If several leaves fail to bind, the error of
+/// the first one is returned, alongside the [`Span`] of its first occurrence.
+pub async fn bind<E>(
+    node: Expr,
+    binder: &(impl BinderResolver<E> + Sync),
+) -> Result<(Expr, Vec<Binding>), InterpreterError<E>> {
+    let mut unbound = Vec::new();
+    collect_unbound(&node, &mut unbound);
+    let mut seen = HashSet::new();
+    unbound.retain(|(leaf, _, _)| seen.insert(leaf.clone()));
+
+    let permits = Semaphore::new(MAX_CONCURRENT_RESOLUTIONS);
+    let results = futures::future::join_all(unbound.iter().map(|(leaf, _, _)| async {
+        let _permit = permits
+            .acquire()
+            .await
+            .expect("the semaphore is never closed");
+        match leaf {
+            Unbound::Name(name) => binder.bind_string_literal(name.clone()).await,
+            Unbound::Id(id) => binder.bind_unknown_id(id.clone()).await.map(Some),
+        }
+    }))
+    .await;
+
+    let mut bound = HashMap::new();
+    let mut bindings = Vec::new();
+    for ((leaf, span, source), result) in unbound.into_iter().zip(results) {
+        let result = result.map_err(|error| InterpreterError { span, error })?;
+        if let Some(Bound::User(_, target) | Bound::Role(_, target)) = &result {
+            bindings.push(Binding {
+                source,
+                target: target.clone(),
+            });
+        }
+        bound.insert(leaf, result);
+    }
+
+    Ok((rebind(node, &bound), bindings))
+}
+
+/// Describe what each name and ID was bound to on a single line, as a list of each [`Binding`].
+/// Only the first few bindings are described in full.
+pub fn describe_bindings(bindings: &[Binding]) -> String {
+    let mut descriptions = bindings
+        .iter()
+        .take(MAX_DESCRIBED_BINDINGS)
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+    if bindings.len() > MAX_DESCRIBED_BINDINGS {
+        descriptions.push(format!(
+            "and {} more",
+            bindings.len() - MAX_DESCRIBED_BINDINGS
+        ));
+    }
+    descriptions.join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    };
+
+    use super::*;
+    use crate::drql::parser::parse_drql;
+
+    /// A binder where names starting with "user" are members, names starting with "typo" can't be
+    /// bound, `here` is left alone, and every other name is a role. IDs are always members. Every
+    /// name and ID that is bound is recorded, along with how many were being bound at once.
+    #[derive(Default)]
+    struct Binder {
+        bound: Mutex<Vec<String>>,
+        in_flight: AtomicUsize,
+        max_in_flight: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl BinderResolver<String> for Binder {
+        async fn bind_string_literal(&self, literal: String) -> Result<Option<Bound>, String> {
+            self.bound
+                .lock()
+                .expect("lock should not be poisoned")
+                .push(literal.clone());
+            let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+            // Give other names a chance to start binding
+            for _ in 0..10 {
+                tokio::task::yield_now().await;
+            }
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+
+            if literal == "here" {
+                Ok(None)
+            } else if literal.starts_with("typo") {
+                Err(format!("unknown name {literal}"))
+            } else if literal.starts_with("user") {
+                Ok(Some(Bound::User(UserId(1), format!("user {literal}#0001"))))
+            } else {
+                Ok(Some(Bound::Role(RoleId(2), format!("role @{literal}"))))
+            }
+        }
+
+        async fn bind_unknown_id(&self, id: String) -> Result<Bound, String> {
+            self.bound
+                .lock()
+                .expect("lock should not be poisoned")
+                .push(id.clone());
+            Ok(Bound::User(
+                UserId(id.parse().map_err(|_| id.clone())?),
+                format!("user {id}"),
+            ))
+        }
+    }
+
+    /// Bind a query, returning the bound query as a string and a description of the bindings
+    async fn bound(
+        query: &str,
+        binder: &Binder,
+    ) -> Result<(String, String), InterpreterError<String>> {
+        let (ast, bindings) =
+            bind(parse_drql(query).expect("parsing should succeed"), binder).await?;
+        Ok((ast.to_string(), describe_bindings(&bindings)))
+    }
+
+    #[tokio::test]
+    async fn names_and_ids_are_bound() {
+        assert_eq!(
+            bound("mods + useralice - 3 & here", &Binder::default())
+                .await
+                .expect("binding should succeed"),
+            (
+                "(((<@&2> | <@1>) - <@3>) & here)".to_string(),
+                "`mods` \u{2192} `role @mods`, `useralice` \u{2192} `user useralice#0001`, `3` \u{2192} `user 3`".to_string()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn each_leaf_is_bound_once() {
+        let binder = Binder::default();
+        bound("mods & (mods + 3) - 3", &binder)
+            .await
+            .expect("binding should succeed");
+        assert_eq!(
+            *binder.bound.lock().expect("lock should not be poisoned"),
+            vec!["mods", "3"]
+        );
+    }
+
+    #[tokio::test]
+    async fn names_are_bound_concurrently_up_to_the_limit() {
+        let binder = Binder::default();
+        bound("a + b + c + d + e + f + a", &binder)
+            .await
+            .expect("binding should succeed");
+
+        let max_in_flight = binder.max_in_flight.load(Ordering::SeqCst);
+        assert!(max_in_flight > 1, "names should be bound concurrently");
+        assert!(max_in_flight <= MAX_CONCURRENT_RESOLUTIONS);
+        assert_eq!(
+            *binder.bound.lock().expect("lock should not be poisoned"),
+            vec!["a", "b", "c", "d", "e", "f"]
+        );
+    }
+
+    #[tokio::test]
+    async fn the_first_error_is_returned() {
+        let error = bound("typo1 + a + typo2", &Binder::default())
+            .await
+            .expect_err("binding should fail");
+        assert_eq!(error.error, "unknown name typo1");
+    }
+
+    #[tokio::test]
+    async fn bound_leaves_keep_their_spans() {
+        let (ast, _) = bind(
+            parse_drql("a + b").expect("parsing should succeed"),
+            &Binder::default(),
+        )
+        .await
+        .expect("binding should succeed");
+        let Expr::Union(lhs, rhs, _) = ast else {
+            panic!("the bound query should still be a union");
+        };
+        assert_eq!(lhs.span(), Span::new(0, 1, 0));
+        assert_eq!(rhs.span(), Span::new(4, 5, 0));
+    }
+
+    #[tokio::test]
+    async fn errors_point_at_the_leaf() {
+        let error = bound("a + typo", &Binder::default())
+            .await
+            .expect_err("binding should fail");
+        assert_eq!(error.error, "unknown name typo");
+        assert_eq!(error.span, Span::new(4, 8, 0));
+    }
+
+    #[test]
+    fn long_descriptions_are_cut_off() {
+        let bindings = (0..12)
+            .map(|index| Binding {
+                source: index.to_string(),
+                target: "role @a".to_string(),
+            })
+            .collect::<Vec<_>>();
+        assert!(describe_bindings(&bindings).ends_with("`9` \u{2192} `role @a`, and 2 more"));
+    }
+}
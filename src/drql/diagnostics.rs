@@ -0,0 +1,315 @@
+//! Human-friendly diagnostics for DRQL queries
+//!
+//! The errors produced by LALRPOP are great for debugging the parser, but not for someone who just
+//! wanted to ping a few roles. This module turns them into plain English and points at the part of
+//! the query that caused the problem.
+
+use std::ops::Range;
+
+use lalrpop_util::ParseError;
+
+use super::{
+    lexer::LexicalError,
+    parser::{ChunkParseErrors, DrqlParseError},
+};
+
+/// The most errors [`describe_chunk_parse_errors`] will describe. Each error quotes its line of the
+/// query with a caret under it, and past the first few, later errors are usually caused by earlier
+/// ones anyway.
+const MAX_DESCRIBED_ERRORS: usize = 5;
+
+/// Describe a terminal from LALRPOP's list of expected tokens in plain words.
+fn describe_expected_token(token: &str) -> String {
+    match token {
+        "STRING_LITERAL" => "a name".to_string(),
+        "ID_LITERAL" => "an ID".to_string(),
+        "USER_MENTION" => "a user mention".to_string(),
+        "ROLE_MENTION" => "a role mention".to_string(),
+        "\"roles_of\"" | "\"members_of\"" => "a conversion like `roles_of(...)`".to_string(),
+        // Terminals written as string literals in the grammar are quoted, like "\"+\""
+        quoted => format!("`{}`", quoted.trim_matches('"')),
+    }
+}
+
+/// Join a list of expected tokens into a sentence, like "`(`, a name, or an ID".
+fn describe_expected_tokens(expected: &[String]) -> String {
+    /// The order operands are listed in. Punctuation always comes before these.
+    const OPERAND_ORDER: [&str; 5] = [
+        "a name",
+        "an ID",
+        "a user mention",
+        "a role mention",
+        "a conversion like `roles_of(...)`",
+    ];
+
+    let mut descriptions = Vec::<String>::new();
+    for token in expected {
+        let description = describe_expected_token(token);
+        if !descriptions.contains(&description) {
+            descriptions.push(description);
+        }
+    }
+    descriptions.sort_by_key(|description| {
+        OPERAND_ORDER
+            .iter()
+            .position(|operand| operand == description)
+    });
+
+    match descriptions.as_slice() {
+        [] => "nothing".to_string(),
+        [only] => only.clone(),
+        [first, second] => format!("{first} or {second}"),
+        [rest @ .., last] => format!("{}, or {last}", rest.join(", ")),
+    }
+}
+
+/// Quote the line of `source` containing `range` in a code block, with carets under the range.
+///
+/// `range` is a range of byte offsets into `source`. An empty range (like the end of the input)
+/// is shown as a single caret.
+pub fn quote_span(source: &str, range: Range<usize>) -> String {
+    let start = range.start.min(source.len());
+    let end = range.end.clamp(start, source.len());
+
+    let line_start = source[..start].rfind('\n').map_or(0, |index| index + 1);
+    let line_end = source[start..]
+        .find('\n')
+        .map_or(source.len(), |index| start + index);
+    // Only underline the part of the range that is on the first line
+    let underline_end = end.min(line_end);
+
+    let padding = source[line_start..start].chars().count();
+    let width = source[start..underline_end].chars().count().max(1);
+
+    format!(
+        "```\n{}\n{}{}\n```",
+        &source[line_start..line_end],
+        " ".repeat(padding),
+        "^".repeat(width)
+    )
+}
+
+/// Turn an error from [`parse_drql`] into a readable message, quoting `source` (the chunk that was
+/// being parsed) with a caret under the error.
+///
+/// [`parse_drql`]: super::parser::parse_drql
+pub fn describe_parse_error(source: &str, error: &DrqlParseError) -> String {
+    let (message, range) = match error {
+        ParseError::InvalidToken { location } => {
+            ("Invalid token.".to_string(), Some(*location..*location))
+        }
+        ParseError::UnrecognizedEof { location, expected } => (
+            format!(
+                "Your query ended unexpectedly. Expected {} next.",
+                describe_expected_tokens(expected)
+            ),
+            Some(*location..*location),
+        ),
+        ParseError::UnrecognizedToken {
+            token: (start, _, end),
+            expected,
+        } => (
+            format!(
+                "Unexpected `{}`. Expected {} here.",
+                source.get(*start..*end).unwrap_or_default(),
+                describe_expected_tokens(expected)
+            ),
+            Some(*start..*end),
+        ),
+        ParseError::ExtraToken {
+            token: (start, _, end),
+        } => (
+            format!(
+                "Unexpected `{}` after the end of your query.",
+                source.get(*start..*end).unwrap_or_default()
+            ),
+            Some(*start..*end),
+        ),
+        ParseError::User { error } => match error {
+            LexicalError::NoMatchingRule => ("Unrecognized input.".to_string(), None),
+            LexicalError::UnknownToken((index, char)) => (
+                format!("Unknown character `{char}`."),
+                Some(*index..(*index + char.len_utf8())),
+            ),
+            LexicalError::UnterminatedStringLiteral(index) => (
+                "This string is never closed. Add a `\"` at the end of it.".to_string(),
+                Some(*index..*index),
+            ),
+            LexicalError::ParseIntError(err) => (format!("Invalid ID in mention: {err}."), None),
+        },
+    };
+
+    match range {
+        Some(range) => format!("{message}\n{}", quote_span(source, range)),
+        None => message,
+    }
+}
+
+/// Join the descriptions of several errors, only including the first few in full.
+fn join_descriptions(descriptions: &[String]) -> String {
+    let message = descriptions
+        .iter()
+        .take(MAX_DESCRIBED_ERRORS)
+        .cloned()
+        .collect::<Vec<_>>()
+        .join("\n");
+    if descriptions.len() > MAX_DESCRIBED_ERRORS {
+        format!(
+            "{message}\n...and {} more errors.",
+            descriptions.len() - MAX_DESCRIBED_ERRORS
+        )
+    } else {
+        message
+    }
+}
+
+/// Describe every error from parsing `source` with [`parse_drql`].
+///
+/// Only the first few errors are described in full.
+///
+/// [`parse_drql`]: super::parser::parse_drql
+pub fn describe_parse_errors(source: &str, errors: &[DrqlParseError]) -> String {
+    join_descriptions(
+        &errors
+            .iter()
+            .map(|error| describe_parse_error(source, error))
+            .collect::<Vec<_>>(),
+    )
+}
+
+/// Describe every error from parsing several chunks of a message with [`parse_drql_chunks`].
+///
+/// Only the first few errors are described in full.
+///
+/// [`parse_drql_chunks`]: super::parser::parse_drql_chunks
+pub fn describe_chunk_parse_errors(chunks: &[ChunkParseErrors]) -> String {
+    join_descriptions(
+        &chunks
+            .iter()
+            .flat_map(|chunk| {
+                chunk.errors.iter().map(|error| {
+                    format!(
+                        "Error parsing chunk {}: {}",
+                        chunk.index,
+                        describe_parse_error(chunk.source, error)
+                    )
+                })
+            })
+            .collect::<Vec<_>>(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drql::parser::{parse_drql, parse_drql_chunks};
+
+    fn describe(source: &str) -> String {
+        describe_parse_error(
+            source,
+            &parse_drql(source).expect_err("parsing should have failed")[0],
+        )
+    }
+
+    #[test]
+    fn unexpected_token() {
+        assert_eq!(
+            describe("a + )"),
+            concat!(
+                "Unexpected `)`. Expected `(`, a name, an ID, a user mention, a role mention, or a conversion like `roles_of(...)` here.\n",
+                "```\n",
+                "a + )\n",
+                "    ^\n",
+                "```"
+            )
+        );
+    }
+
+    #[test]
+    fn unexpected_eof() {
+        assert_eq!(
+            describe("a & "),
+            concat!(
+                "Your query ended unexpectedly. Expected `(`, a name, an ID, a user mention, a role mention, or a conversion like `roles_of(...)` next.\n",
+                "```\n",
+                "a & \n",
+                "   ^\n",
+                "```"
+            )
+        );
+    }
+
+    #[test]
+    fn unknown_character() {
+        assert_eq!(
+            describe("staff # mods"),
+            concat!(
+                "Unknown character `#`.\n",
+                "```\n",
+                "staff # mods\n",
+                "      ^\n",
+                "```"
+            )
+        );
+    }
+
+    #[test]
+    fn carets_span_the_whole_token() {
+        assert_eq!(
+            describe("a \"b c\""),
+            concat!(
+                "Unexpected `\"b c\"`. Expected `&`, `+`, `-`, or `|` here.\n",
+                "```\n",
+                "a \"b c\"\n",
+                "  ^^^^^\n",
+                "```"
+            )
+        );
+    }
+
+    #[test]
+    fn quote_span_counts_characters_not_bytes() {
+        assert_eq!(
+            quote_span("\u{201c}a\u{201d} + )", 10..11),
+            "```\n\u{201c}a\u{201d} + )\n      ^\n```"
+        );
+    }
+
+    #[test]
+    fn quote_span_only_shows_the_relevant_line() {
+        assert_eq!(quote_span("a +\nb )", 6..7), "```\nb )\n  ^\n```");
+    }
+
+    #[test]
+    fn describes_errors_from_every_chunk() {
+        assert_eq!(
+            describe_chunk_parse_errors(
+                &parse_drql_chunks(&[(0, "a +"), (10, "b"), (20, "c # d")])
+                    .expect_err("parsing should have failed")
+            ),
+            concat!(
+                "Error parsing chunk 0: Your query ended unexpectedly. Expected `(`, a name, an ID, a user mention, a role mention, or a conversion like `roles_of(...)` next.\n",
+                "```\n",
+                "a +\n",
+                "   ^\n",
+                "```\n",
+                "Error parsing chunk 2: Unknown character `#`.\n",
+                "```\n",
+                "c # d\n",
+                "  ^\n",
+                "```"
+            )
+        );
+    }
+
+    #[test]
+    fn describes_only_the_first_few_errors() {
+        let source = "# # # # # # #";
+        let description = describe_parse_errors(
+            source,
+            &parse_drql(source).expect_err("parsing should have failed"),
+        );
+        assert_eq!(description.matches("Unknown character").count(), 5);
+        assert!(description.ends_with("...and 2 more errors."));
+    }
+}
@@ -0,0 +1,304 @@
+//! Canonical formatting of DRQL queries
+//!
+//! The same query can be written in many ways: with redundant parentheses, `|` or `+`, curly or
+//! straight quotes, and any amount of spacing. [`format`] writes every query that parses to the
+//! same AST in exactly one way, so that queries shared between people stay consistent.
+
+use super::ast::{is_keyword, Expr};
+
+/// The width, in bytes, that [`format`] wraps queries to
+pub const DEFAULT_WIDTH: usize = 80;
+
+/// How far each level of a wrapped query is indented
+const INDENT: usize = 4;
+
+/// The symbol and operands of an operator, or [`None`] for a leaf or conversion. Unions are always
+/// written as `+`.
+fn operator(node: &Expr) -> Option<(&'static str, &Expr, &Expr)> {
+    match node {
+        Expr::Union(lhs, rhs, _) => Some(("+", lhs, rhs)),
+        Expr::Intersection(lhs, rhs, _) => Some(("&", lhs, rhs)),
+        Expr::Difference(lhs, rhs, _) => Some(("-", lhs, rhs)),
+        Expr::RolesOf(..)
+        | Expr::MembersOf(..)
+        | Expr::StringLiteral(..)
+        | Expr::UnknownID(..)
+        | Expr::UserID(..)
+        | Expr::RoleID(..) => None,
+    }
+}
+
+/// The keyword and operand of a conversion, like `roles_of(a)`, or [`None`] for anything else
+fn conversion(node: &Expr) -> Option<(&'static str, &Expr)> {
+    match node {
+        Expr::RolesOf(operand, _) => Some(("roles_of", operand)),
+        Expr::MembersOf(operand, _) => Some(("members_of", operand)),
+        Expr::Union(..)
+        | Expr::Intersection(..)
+        | Expr::Difference(..)
+        | Expr::StringLiteral(..)
+        | Expr::UnknownID(..)
+        | Expr::UserID(..)
+        | Expr::RoleID(..) => None,
+    }
+}
+
+/// Determine if a name can be written without quotes, which is when the lexer would read it as a
+/// name rather than an ID, a keyword, or something else
+fn is_bare_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|char| char.is_ascii_alphanumeric() || char == '_')
+        && !is_keyword(name)
+}
+
+/// Format a query on a single line
+fn flat(node: &Expr) -> String {
+    match node {
+        Expr::Union(..) | Expr::Intersection(..) | Expr::Difference(..) => {
+            let (symbol, lhs, rhs) = operator(node).expect("node should be an operator");
+            format!("{} {symbol} {}", flat(lhs), flat_operand(rhs))
+        }
+        Expr::RolesOf(..) | Expr::MembersOf(..) => {
+            let (keyword, operand) = conversion(node).expect("node should be a conversion");
+            format!("{keyword}({})", flat(operand))
+        }
+        Expr::StringLiteral(name, _) => {
+            if is_bare_name(name) {
+                name.clone()
+            } else {
+                format!("\"{name}\"")
+            }
+        }
+        Expr::UnknownID(id, _) => id.clone(),
+        Expr::UserID(id, _) => format!("<@{id}>"),
+        Expr::RoleID(id, _) => format!("<@&{id}>"),
+    }
+}
+
+/// Format the right operand of an operator on a single line.
+///
+/// Every operator has the same precedence and associates to the left, so the left operand never
+/// needs parentheses and the right operand only needs them if it is an operator itself.
+fn flat_operand(node: &Expr) -> String {
+    if operator(node).is_some() {
+        format!("({})", flat(node))
+    } else {
+        flat(node)
+    }
+}
+
+/// Format a query starting at column `indent`, putting each operand of a chain of operators on
+/// its own line if the query doesn't fit within `width`
+fn wrapped(node: &Expr, indent: usize, width: usize) -> String {
+    let single_line = flat(node);
+    if indent + single_line.len() <= width {
+        return single_line;
+    }
+
+    // `a + b - c` is `(a + b) - c`, so the operands of a chain are found down its left side
+    let mut operands = Vec::new();
+    let mut first = node;
+    while let Some((symbol, lhs, rhs)) = operator(first) {
+        operands.push((symbol, rhs));
+        first = lhs;
+    }
+
+    let continuation = indent + INDENT;
+    let mut lines = vec![wrapped_conversion(first, indent, indent, width)];
+    lines.extend(operands.into_iter().rev().map(|(symbol, rhs)| {
+        format!(
+            "{}{symbol} {}",
+            " ".repeat(continuation),
+            wrapped_operand(rhs, continuation, width)
+        )
+    }));
+    lines.join("\n")
+}
+
+/// Format the right operand of an operator, on a line starting at column `indent`. Operands that
+/// don't fit are wrapped inside their parentheses.
+fn wrapped_operand(node: &Expr, indent: usize, width: usize) -> String {
+    let single_line = flat_operand(node);
+    if operator(node).is_none() {
+        return wrapped_conversion(node, indent, indent + 2, width);
+    }
+    if indent + 2 + single_line.len() <= width {
+        return single_line;
+    }
+
+    let inner = indent + INDENT;
+    format!(
+        "(\n{}{}\n{})",
+        " ".repeat(inner),
+        wrapped(node, inner, width),
+        " ".repeat(indent)
+    )
+}
+
+/// Format an operand that isn't an operator, starting at column `start` on a line indented to
+/// `indent`. Conversions that don't fit are wrapped inside their parentheses.
+fn wrapped_conversion(node: &Expr, indent: usize, start: usize, width: usize) -> String {
+    let single_line = flat(node);
+    let Some((keyword, operand)) = conversion(node) else {
+        return single_line;
+    };
+    if start + single_line.len() <= width {
+        return single_line;
+    }
+
+    let inner = indent + INDENT;
+    format!(
+        "{keyword}(\n{}{}\n{})",
+        " ".repeat(inner),
+        wrapped(operand, inner, width),
+        " ".repeat(indent)
+    )
+}
+
+/// Format a query canonically, wrapping it over several lines if it is longer than `width`.
+///
+/// The result only has the parentheses needed to keep the same meaning, writes unions as `+`,
+/// quotes names with straight quotes only when they need them, and parses back to the same AST.
+pub fn format_with_width(node: &Expr, width: usize) -> String {
+    wrapped(node, 0, width)
+}
+
+/// Format a query canonically, wrapping it to [`DEFAULT_WIDTH`]. See [`format_with_width`].
+pub fn format(node: &Expr) -> String {
+    format_with_width(node, DEFAULT_WIDTH)
+}
+
+#[cfg(test)]
+mod tests {
+    use poise::serenity_prelude::{RoleId, UserId};
+    use rand::{seq::SliceRandom, Rng};
+
+    use super::*;
+    use crate::drql::{ast::Span, parser::parse_drql};
+
+    fn parse(query: &str) -> Expr {
+        parse_drql(query).expect("parsing should succeed")
+    }
+
+    #[test]
+    fn only_needed_parentheses_are_kept() {
+        assert_eq!(format(&parse("((a | b)) - (c & (d))")), "a + b - (c & d)");
+        assert_eq!(format(&parse("a & (b - (c + d))")), "a & (b - (c + d))");
+    }
+
+    #[test]
+    fn quoting_and_spacing_are_normalized() {
+        assert_eq!(
+            format(&parse(
+                "\"plain\"   |@everyone-<@!1>&\"1st\"+<@&2>+3 + \u{201c}Big Role\u{201d}"
+            )),
+            "plain + everyone - <@1> & \"1st\" + <@&2> + 3 + \"Big Role\""
+        );
+    }
+
+    #[test]
+    fn conversions_are_formatted() {
+        assert_eq!(
+            format(&parse("roles_of( (a) )&members_of(\"roles_of\" | b)")),
+            "roles_of(a) & members_of(\"roles_of\" + b)"
+        );
+        assert_eq!(
+            format_with_width(&parse("a - roles_of(alpha + beta)"), 16),
+            concat!(
+                "a\n",
+                "    - roles_of(\n",
+                "        alpha\n",
+                "            + beta\n",
+                "    )",
+            )
+        );
+    }
+
+    #[test]
+    fn long_queries_are_wrapped() {
+        assert_eq!(
+            format_with_width(&parse("alpha + beta - (gamma & delta)"), 20),
+            concat!(
+                "alpha\n",
+                "    + beta\n",
+                "    - (\n",
+                "        gamma\n",
+                "            & delta\n",
+                "    )",
+            )
+        );
+        assert_eq!(
+            format_with_width(&parse("alpha + beta - (gamma & delta)"), 25),
+            "alpha\n    + beta\n    - (gamma & delta)"
+        );
+    }
+
+    /// Create a random name from pieces that exercise quoting, including escaped quotes
+    fn random_name(rng: &mut impl Rng) -> String {
+        const PIECES: &[&str] = &[
+            "a",
+            "Z",
+            "_",
+            "1",
+            " ",
+            "-",
+            "+",
+            "(",
+            "@",
+            "\\\"",
+            "\\\\",
+            "\u{e9}",
+            "members_of",
+        ];
+        (0..rng.gen_range(0..6))
+            .map(|_| *PIECES.choose(rng).expect("there should be pieces"))
+            .collect()
+    }
+
+    /// Create a random query with every kind of node
+    fn random_query(rng: &mut impl Rng, depth: usize) -> Expr {
+        let span = Span::default();
+        if depth == 0 || rng.gen_bool(0.25) {
+            return match rng.gen_range(0..4) {
+                0 => Expr::UnknownID(rng.gen_range(0..1000_u64).to_string(), span),
+                1 => Expr::UserID(UserId(rng.gen_range(1..1000)), span),
+                2 => Expr::RoleID(RoleId(rng.gen_range(1..1000)), span),
+                _ => Expr::StringLiteral(random_name(rng), span),
+            };
+        }
+
+        let lhs = Box::new(random_query(rng, depth - 1));
+        match rng.gen_range(0..5) {
+            0 => Expr::RolesOf(lhs, span),
+            1 => Expr::MembersOf(lhs, span),
+            2 => Expr::Union(lhs, Box::new(random_query(rng, depth - 1)), span),
+            3 => Expr::Intersection(lhs, Box::new(random_query(rng, depth - 1)), span),
+            _ => Expr::Difference(lhs, Box::new(random_query(rng, depth - 1)), span),
+        }
+    }
+
+    #[test]
+    fn formatted_queries_parse_to_the_same_ast() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..500 {
+            let ast = random_query(&mut rng, 6);
+            let formatted = format_with_width(&ast, rng.gen_range(1..=100));
+            assert!(
+                parse(&formatted).eq_ignoring_spans(&ast),
+                "`{formatted}` should parse to {ast}"
+            );
+        }
+    }
+
+    #[test]
+    fn formatting_is_idempotent() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..200 {
+            let formatted = format(&random_query(&mut rng, 6));
+            assert_eq!(format(&parse(&formatted)), formatted);
+        }
+    }
+}
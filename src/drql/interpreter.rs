@@ -1,66 +1,751 @@
 //! Utilities and functions for interpreting DRQL queries
 
-use std::collections::HashSet;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{Debug, Display, Formatter},
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
 
 use async_recursion::async_recursion;
 use poise::{
     async_trait,
     serenity_prelude::{RoleId, UserId},
 };
-use tracing::instrument;
+use tokio::sync::{OnceCell, Semaphore};
+use tracing::{instrument, trace};
+
+use super::{
+    ast::{Expr, Span},
+    member_set::{Element, MemberIndex, MemberSet},
+};
+
+/// An error raised while interpreting a DRQL AST, along with the [`Span`] of the node that failed
+#[derive(Debug)]
+#[allow(clippy::module_name_repetitions)]
+pub struct InterpreterError<E> {
+    /// The span of the node that failed to be interpreted
+    pub span: Span,
+    /// The underlying error, usually raised by the [`InterpreterResolver`]
+    pub error: E,
+}
+
+impl<E: Display> Display for InterpreterError<E> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (at {})", self.error, self.span)
+    }
+}
+
+impl<E: Display + Debug> std::error::Error for InterpreterError<E> {}
+
+/// Raised when a query converts between members and roles with `roles_of(...)` or
+/// `members_of(...)`, but the [`InterpreterResolver`] can't resolve both members and roles
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnsupportedConversion;
+
+impl Display for UnsupportedConversion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "`roles_of(...)` and `members_of(...)` can't be used here."
+        )
+    }
+}
 
-use super::ast::Expr;
+impl std::error::Error for UnsupportedConversion {}
+
+/// The most leaves that may be resolved at the same time during a single call to [`interpret`]
+pub const MAX_CONCURRENT_RESOLUTIONS: usize = 4;
 
 /// Describes a set of functions used to resolve values in [interpret].
+///
+/// A query is evaluated over sets of some element type `T`, which is usually the [`UserId`]s of
+/// members, but can be anything a leaf can be resolved to, like the [`RoleId`]s of roles.
+///
+/// Independent leaves of a query are resolved concurrently, so these functions take `&self` and
+/// may be called again before an earlier call has finished.
 #[allow(clippy::module_name_repetitions)]
 #[async_trait]
-pub trait InterpreterResolver<E> {
-    /// Resolve a role name to the HashSet of its members
-    async fn resolve_string_literal(&mut self, literal: String) -> Result<HashSet<UserId>, E>;
-    /// Resolve an ID to the HashSet of its members
-    async fn resolve_unknown_id(&mut self, id: String) -> Result<HashSet<UserId>, E>;
-    /// Resolve a user ID to the HashSet of just its ID
-    async fn resolve_user_id(&mut self, id: UserId) -> Result<HashSet<UserId>, E>;
-    /// Resolve a role ID to the HashSet of its members
-    async fn resolve_role_id(&mut self, id: RoleId) -> Result<HashSet<UserId>, E>;
+pub trait InterpreterResolver<E, T = UserId> {
+    /// Resolve a role name to the HashSet of its elements, like its members
+    async fn resolve_string_literal(&self, literal: String) -> Result<HashSet<T>, E>;
+    /// Resolve an ID to the HashSet of its elements
+    async fn resolve_unknown_id(&self, id: String) -> Result<HashSet<T>, E>;
+    /// Resolve a user ID to the HashSet of its elements, like just its ID
+    async fn resolve_user_id(&self, id: UserId) -> Result<HashSet<T>, E>;
+    /// Resolve a role ID to the HashSet of its elements, like its members
+    async fn resolve_role_id(&self, id: RoleId) -> Result<HashSet<T>, E>;
+
+    /// Check that a leaf node looks like it could be resolved, without doing anything expensive or
+    /// interactive like HTTP requests or asking the author a question.
+    ///
+    /// This is used to catch typos in the parts of a query that [`interpret`] skips when
+    /// [`InterpretOptions::validate_skipped`] is set. By default, every leaf is valid.
+    fn validate_leaf(&self, _leaf: &Expr) -> Result<(), E> {
+        Ok(())
+    }
+
+    /// Describe what a leaf node that has just been resolved refers to, like the role or member a
+    /// name matched, for [`explain`]. By default, leaves aren't described.
+    fn describe_target(&self, _leaf: &Expr) -> Option<String> {
+        None
+    }
+
+    /// Every element leaves are expected to resolve to, like the members of the guild, so that
+    /// they can all be given positions in the [`MemberIndex`] before any leaf is resolved. Any
+    /// other elements are given positions as they are resolved. By default, there are none.
+    fn known_elements(&self) -> Vec<T> {
+        Vec::new()
+    }
+
+    /// The resolver for the operand of `roles_of(...)`, which matches members. Returns [`None`]
+    /// if queries can't convert between members and roles, which is the default.
+    fn members_resolver(&self) -> Option<&(dyn InterpreterResolver<E, UserId> + Sync)> {
+        None
+    }
+
+    /// The resolver for the operand of `members_of(...)`, which matches roles. Returns [`None`]
+    /// if queries can't convert between members and roles, which is the default.
+    fn roles_resolver(&self) -> Option<&(dyn InterpreterResolver<E, RoleId> + Sync)> {
+        None
+    }
+}
+
+/// Options changing how [`interpret`] evaluates a query
+#[derive(Debug, Clone, Copy, Default)]
+#[allow(clippy::module_name_repetitions)]
+pub struct InterpretOptions {
+    /// When the right side of an intersection or difference is skipped because the left side is
+    /// empty, still check its leaves with [`InterpreterResolver::validate_leaf`].
+    pub validate_skipped: bool,
+}
+
+/// How a single node of a query was evaluated, as recorded by [`explain`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Explanation {
+    /// The operator at this node, the leaf itself, or the whole subtree if it was skipped
+    pub label: String,
+    /// What a leaf was resolved to, if the resolver described it
+    pub target: Option<String>,
+    /// How many members this node matched, or [`None`] if it was skipped
+    pub members: Option<usize>,
+    /// How long this node took to evaluate, including any time spent waiting to resolve leaves
+    pub elapsed: Duration,
+    /// How the operands of an operator were evaluated
+    pub children: Vec<Self>,
+}
+
+impl Explanation {
+    /// Write this explanation and its children as lines of an indented tree
+    fn write_indented(&self, f: &mut Formatter<'_>, depth: usize) -> std::fmt::Result {
+        write!(f, "{}{}", "  ".repeat(depth), self.label)?;
+        if let Some(target) = &self.target {
+            write!(f, " \u{2192} {target}")?;
+        }
+        match self.members {
+            Some(members) => writeln!(f, ": {members} members in {:?}", self.elapsed)?,
+            None => writeln!(f, ": skipped")?,
+        }
+
+        for child in &self.children {
+            child.write_indented(f, depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Explanation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.write_indented(f, 0)
+    }
+}
+
+/// The members that each leaf of a query put in its result, as recorded by
+/// [`interpret_with_provenance`]. Every member of the result has the [`Span`] of at least one leaf,
+/// in the order the leaves appear in the query.
+pub type Provenance<T = UserId> = HashMap<T, Vec<Span>>;
+
+/// What kind of node a [`Trace`] was recorded for
+#[derive(Debug)]
+enum TraceKind {
+    /// A union, see [`Expr::Union`]
+    Union,
+    /// An intersection, see [`Expr::Intersection`]
+    Intersection,
+    /// A difference, see [`Expr::Difference`]
+    Difference,
+    /// A conversion between members and roles, see [`Expr::RolesOf`] and [`Expr::MembersOf`].
+    /// Its operand was evaluated over the other kind of element.
+    Conversion {
+        /// Where the conversion is in the query
+        span: Span,
+        /// The kind of conversion
+        label: String,
+    },
+    /// A leaf that was resolved
+    Leaf {
+        /// Where the leaf is in the query
+        span: Span,
+        /// The leaf as it is displayed
+        label: String,
+        /// What the leaf was resolved to, if the resolver described it
+        target: Option<String>,
+    },
+    /// A subtree that was skipped because it couldn't change the result, as it is displayed
+    Skipped(String),
+}
+
+/// A record of how a node was evaluated, kept when a query is explained or its provenance is
+/// tracked
+#[derive(Debug)]
+struct Trace {
+    /// What kind of node this is
+    kind: TraceKind,
+    /// The members the node matched
+    members: MemberSet,
+    /// How long the node took to evaluate
+    elapsed: Duration,
+    /// The traces of an operator's operands
+    children: Vec<Self>,
+}
+
+impl Trace {
+    /// Convert this trace to an [`Explanation`]
+    fn explain(self) -> Explanation {
+        let members = Some(self.members.len());
+        let (label, target, members) = match self.kind {
+            TraceKind::Union => ("Union".to_string(), None, members),
+            TraceKind::Intersection => ("Intersection".to_string(), None, members),
+            TraceKind::Difference => ("Difference".to_string(), None, members),
+            TraceKind::Conversion { label, .. } => (label, None, members),
+            TraceKind::Leaf { label, target, .. } => (label, target, members),
+            TraceKind::Skipped(label) => (label, None, None),
+        };
+
+        Explanation {
+            label,
+            target,
+            members,
+            elapsed: self.elapsed,
+            children: self.children.into_iter().map(Self::explain).collect(),
+        }
+    }
+
+    /// Record the leaves below this node that put each member of `reached` in the result, where
+    /// `reached` is the part of the result that this node contributed to
+    fn collect_provenance<T: Element>(
+        &self,
+        reached: &MemberSet,
+        index: &MemberIndex<T>,
+        provenance: &mut Provenance<T>,
+    ) {
+        if reached.is_empty() {
+            return;
+        }
+
+        match &self.kind {
+            // Each side of a union only accounts for the members it matched itself
+            TraceKind::Union => {
+                for child in &self.children {
+                    child.collect_provenance(
+                        &reached.clone().intersection(&child.members),
+                        index,
+                        provenance,
+                    );
+                }
+            }
+            // Every member of an intersection was matched by both sides
+            TraceKind::Intersection => {
+                for child in &self.children {
+                    child.collect_provenance(reached, index, provenance);
+                }
+            }
+            // Members of a difference are there because of the left side, not the right
+            TraceKind::Difference => {
+                if let Some(minuend) = self.children.first() {
+                    minuend.collect_provenance(reached, index, provenance);
+                }
+            }
+            // The operand of a conversion matched different elements, so the conversion is as far
+            // back as members can be traced
+            TraceKind::Conversion { span, .. } | TraceKind::Leaf { span, .. } => {
+                for member in index.members_of(reached) {
+                    provenance.entry(member).or_default().push(*span);
+                }
+            }
+            TraceKind::Skipped(_) => {}
+        }
+    }
+}
+
+/// A leaf of a DRQL AST, without its [`Span`], so that every occurrence of the same leaf in a query
+/// can share the result of resolving it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Leaf {
+    /// A role or member name, see [`Expr::StringLiteral`]
+    StringLiteral(String),
+    /// An ID belonging to a user or role, see [`Expr::UnknownID`]
+    UnknownID(String),
+    /// A user ID, see [`Expr::UserID`]
+    UserID(UserId),
+    /// A role ID, see [`Expr::RoleID`]
+    RoleID(RoleId),
+}
+
+/// The state shared by every node during a single call to [`interpret`]
+struct Evaluation<'r, R: ?Sized, T> {
+    /// The resolver leaves are resolved with
+    resolver: &'r R,
+    /// The options this evaluation was started with
+    options: InterpretOptions,
+    /// Whether to record a [`Trace`] of every node
+    trace: bool,
+    /// Limits how many leaves are resolved at once, shared with the evaluations of any conversions
+    /// in the query
+    permits: Arc<Semaphore>,
+    /// Gives every member of a resolved leaf a position in the [`MemberSet`]s being evaluated
+    index: Mutex<MemberIndex<T>>,
+    /// The members of every leaf that has been (or is being) resolved. Occurrences of a leaf that
+    /// is already being resolved wait for that resolution to finish rather than starting another.
+    cache: Mutex<HashMap<Leaf, Arc<OnceCell<MemberSet>>>>,
+}
+
+impl<'r, R: ?Sized, T: Element> Evaluation<'r, R, T> {
+    /// Start evaluating a query with `resolver`, resolving leaves with `permits`. The index starts
+    /// out with the resolver's [known elements](InterpreterResolver::known_elements).
+    fn new<E>(
+        resolver: &'r R,
+        options: InterpretOptions,
+        trace: bool,
+        permits: Arc<Semaphore>,
+    ) -> Self
+    where
+        R: InterpreterResolver<E, T>,
+    {
+        Self {
+            resolver,
+            options,
+            trace,
+            permits,
+            index: Mutex::new(MemberIndex::with_members(resolver.known_elements())),
+            cache: Mutex::default(),
+        }
+    }
+
+    /// Resolve a leaf, or reuse its members if it has already been resolved
+    async fn resolve<E>(&self, leaf: Leaf) -> Result<MemberSet, E>
+    where
+        R: InterpreterResolver<E, T> + Sync,
+    {
+        let cell = Arc::clone(
+            self.cache
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .entry(leaf.clone())
+                .or_default(),
+        );
+
+        cell.get_or_try_init(|| async {
+            let _permit = self
+                .permits
+                .acquire()
+                .await
+                .expect("the semaphore is never closed");
+            trace!("Resolving {leaf:?}");
+
+            let members = match leaf.clone() {
+                Leaf::StringLiteral(contents) => {
+                    self.resolver.resolve_string_literal(contents).await
+                }
+                Leaf::UnknownID(id) => self.resolver.resolve_unknown_id(id).await,
+                Leaf::UserID(id) => self.resolver.resolve_user_id(id).await,
+                Leaf::RoleID(id) => self.resolver.resolve_role_id(id).await,
+            }?;
+
+            Ok(self
+                .index
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .set_of(&members))
+        })
+        .await
+        .cloned()
+    }
 }
 
 /// Interpret a DRQL AST, deferring to the Resolver to resolve string literals, user IDs, and role IDs.
+///
+/// Sets of members (or other elements) are represented as [`MemberSet`]s while the query is
+/// evaluated, and are only converted back to elements at the end.
+///
+/// Both sides of a union are interpreted concurrently, with at most [`MAX_CONCURRENT_RESOLUTIONS`]
+/// leaves being resolved at once, and each distinct leaf is only resolved once no matter how many
+/// times it appears in the query. The right side of an intersection or difference is only
+/// interpreted once the left side is known to be non-empty, as it can't change the result
+/// otherwise. Errors raised by the resolver are returned alongside the [`Span`] of the node being
+/// resolved.
+pub async fn interpret<E: Send + From<UnsupportedConversion>, T: Element>(
+    node: Expr,
+    resolver: &(impl InterpreterResolver<E, T> + Sync),
+    options: InterpretOptions,
+) -> Result<HashSet<T>, InterpreterError<E>> {
+    let (index, members, _) = evaluate(node, resolver, options, false).await?;
+    Ok(index.members_of(&members))
+}
+
+/// Interpret a DRQL AST like [`interpret`], also recording how many members every node matched,
+/// how long it took, and what each leaf was resolved to.
+pub async fn explain<E: Send + From<UnsupportedConversion>, T: Element>(
+    node: Expr,
+    resolver: &(impl InterpreterResolver<E, T> + Sync),
+    options: InterpretOptions,
+) -> Result<(HashSet<T>, Explanation), InterpreterError<E>> {
+    let (index, members, trace) = evaluate(node, resolver, options, true).await?;
+    Ok((
+        index.members_of(&members),
+        trace.expect("every node is traced when tracing").explain(),
+    ))
+}
+
+/// Interpret a DRQL AST like [`interpret`], also recording which leaves put each member in the
+/// result.
+///
+/// A member is put in the result by every leaf that matched them on a path that contributed to the
+/// result: either side of a union that matched them, both sides of an intersection, and only the
+/// left side of a difference.
+pub async fn interpret_with_provenance<E: Send + From<UnsupportedConversion>, T: Element>(
+    node: Expr,
+    resolver: &(impl InterpreterResolver<E, T> + Sync),
+    options: InterpretOptions,
+) -> Result<(HashSet<T>, Provenance<T>), InterpreterError<E>> {
+    let (index, members, trace) = evaluate(node, resolver, options, true).await?;
+
+    let mut provenance = Provenance::new();
+    trace
+        .expect("every node is traced when tracing")
+        .collect_provenance(&members, &index, &mut provenance);
+    Ok((index.members_of(&members), provenance))
+}
+
+/// Interpret a DRQL AST, tracing every node if `trace` is set. Returns the index the result was
+/// built from, so that it can be converted back to members.
+async fn evaluate<E, T, R>(
+    node: Expr,
+    resolver: &R,
+    options: InterpretOptions,
+    trace: bool,
+) -> Result<(MemberIndex<T>, MemberSet, Option<Trace>), InterpreterError<E>>
+where
+    E: Send + From<UnsupportedConversion>,
+    T: Element,
+    R: InterpreterResolver<E, T> + Sync + ?Sized,
+{
+    let permits = Arc::new(Semaphore::new(MAX_CONCURRENT_RESOLUTIONS));
+    evaluate_sharing(node, resolver, options, trace, permits).await
+}
+
+/// Interpret a DRQL AST like [`evaluate`], resolving leaves with `permits` shared with another
+/// evaluation
+async fn evaluate_sharing<E, T, R>(
+    node: Expr,
+    resolver: &R,
+    options: InterpretOptions,
+    trace: bool,
+    permits: Arc<Semaphore>,
+) -> Result<(MemberIndex<T>, MemberSet, Option<Trace>), InterpreterError<E>>
+where
+    E: Send + From<UnsupportedConversion>,
+    T: Element,
+    R: InterpreterResolver<E, T> + Sync + ?Sized,
+{
+    let evaluation = Evaluation::new(resolver, options, trace, permits);
+    let (members, trace) = interpret_node(node, &evaluation).await?;
+
+    Ok((
+        evaluation
+            .index
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner),
+        members,
+        trace,
+    ))
+}
+
+/// Validate every leaf of a skipped subtree with [`InterpreterResolver::validate_leaf`]
+fn validate<E, T, R: InterpreterResolver<E, T> + ?Sized>(
+    node: &Expr,
+    resolver: &R,
+) -> Result<(), InterpreterError<E>> {
+    if let Expr::Union(lhs, rhs, _)
+    | Expr::Intersection(lhs, rhs, _)
+    | Expr::Difference(lhs, rhs, _) = node
+    {
+        validate(lhs, resolver)?;
+        validate(rhs, resolver)
+    } else if let Expr::RolesOf(operand, _) | Expr::MembersOf(operand, _) = node {
+        validate(operand, resolver)
+    } else {
+        resolver
+            .validate_leaf(node)
+            .map_err(|error| InterpreterError {
+                span: node.span(),
+                error,
+            })
+    }
+}
+
+/// The members a node matched, and a [`Trace`] of how if the evaluation is being traced
+type Interpreted = (MemberSet, Option<Trace>);
+
+/// Interpret the left side of an intersection or difference, and then the right side if the left
+/// side is non-empty. Returns [`None`] if the right side was skipped, along with the traces of
+/// both sides.
+async fn interpret_unless_empty<
+    E: Send + From<UnsupportedConversion>,
+    T: Element,
+    R: InterpreterResolver<E, T> + Sync + ?Sized,
+>(
+    lhs: Expr,
+    rhs: Expr,
+    evaluation: &Evaluation<'_, R, T>,
+) -> Result<(Option<(MemberSet, MemberSet)>, Vec<Trace>), InterpreterError<E>> {
+    let (lhs, lhs_trace) = interpret_node(lhs, evaluation).await?;
+    if lhs.is_empty() {
+        trace!("Left side is empty, skipping {rhs}");
+        if evaluation.options.validate_skipped {
+            validate(&rhs, evaluation.resolver)?;
+        }
+        let rhs_trace = evaluation.trace.then(|| Trace {
+            kind: TraceKind::Skipped(rhs.to_string()),
+            members: MemberSet::default(),
+            elapsed: Duration::ZERO,
+            children: Vec::new(),
+        });
+        return Ok((None, lhs_trace.into_iter().chain(rhs_trace).collect()));
+    }
+
+    let (rhs, rhs_trace) = interpret_node(rhs, evaluation).await?;
+    Ok((
+        Some((lhs, rhs)),
+        lhs_trace.into_iter().chain(rhs_trace).collect(),
+    ))
+}
+
+/// Interpret both sides of a union concurrently as part of an [`Evaluation`]
+///
+/// If both sides fail, the error from the left side is returned, no matter which side failed
+/// first.
+async fn interpret_sides<
+    E: Send + From<UnsupportedConversion>,
+    T: Element,
+    R: InterpreterResolver<E, T> + Sync + ?Sized,
+>(
+    lhs: Expr,
+    rhs: Expr,
+    evaluation: &Evaluation<'_, R, T>,
+) -> Result<((MemberSet, MemberSet), Vec<Trace>), InterpreterError<E>> {
+    let (lhs, rhs) = futures::join!(
+        interpret_node(lhs, evaluation),
+        interpret_node(rhs, evaluation)
+    );
+    let ((lhs, lhs_trace), (rhs, rhs_trace)) = (lhs?, rhs?);
+    Ok(((lhs, rhs), lhs_trace.into_iter().chain(rhs_trace).collect()))
+}
+
+/// Resolve every leaf in `leaves` as part of an [`Evaluation`], returning every member they
+/// matched together. The leaves share the evaluation's cache and permits, so at most
+/// [`MAX_CONCURRENT_RESOLUTIONS`] of them are resolved at once.
+async fn resolve_all<E, T: Element, R: InterpreterResolver<E, T> + Sync + ?Sized>(
+    leaves: impl IntoIterator<Item = Leaf>,
+    evaluation: &Evaluation<'_, R, T>,
+) -> Result<MemberSet, E> {
+    Ok(
+        futures::future::try_join_all(leaves.into_iter().map(|leaf| evaluation.resolve(leaf)))
+            .await?
+            .iter()
+            .fold(MemberSet::default(), MemberSet::union),
+    )
+}
+
+/// Interpret `roles_of(...)` or `members_of(...)` as part of an [`Evaluation`].
+///
+/// The operand is evaluated on its own over the other kind of element, with the resolver for that
+/// kind. Each element it matched is then converted (a member to their roles, or a role to its
+/// members) and resolved like a user or role ID leaf of this evaluation. Every resolution shares
+/// this evaluation's permits.
+async fn interpret_conversion<
+    E: Send + From<UnsupportedConversion>,
+    T: Element,
+    R: InterpreterResolver<E, T> + Sync + ?Sized,
+>(
+    node: Expr,
+    evaluation: &Evaluation<'_, R, T>,
+) -> Result<Interpreted, InterpreterError<E>> {
+    let span = node.span();
+    let located = |error| InterpreterError { span, error };
+    let resolver = evaluation.resolver;
+    let (Some(members_resolver), Some(roles_resolver)) =
+        (resolver.members_resolver(), resolver.roles_resolver())
+    else {
+        return Err(located(UnsupportedConversion.into()));
+    };
+    let (options, trace) = (evaluation.options, evaluation.trace);
+    let permits = &evaluation.permits;
+
+    match node {
+        Expr::RolesOf(operand, _) => {
+            let (index, members, operand_trace) = evaluate_sharing(
+                *operand,
+                members_resolver,
+                options,
+                trace,
+                Arc::clone(permits),
+            )
+            .await?;
+            let conversion = Evaluation::new(roles_resolver, options, false, Arc::clone(permits));
+            let roles = resolve_all(
+                index.members_of(&members).into_iter().map(Leaf::UserID),
+                &conversion,
+            )
+            .await
+            .map_err(located)?;
+            let roles = conversion
+                .index
+                .into_inner()
+                .unwrap_or_else(PoisonError::into_inner)
+                .members_of(&roles);
+            let members = resolve_all(roles.into_iter().map(Leaf::RoleID), evaluation)
+                .await
+                .map_err(located)?;
+            Ok((members, operand_trace))
+        }
+        Expr::MembersOf(operand, _) => {
+            let (index, roles, operand_trace) = evaluate_sharing(
+                *operand,
+                roles_resolver,
+                options,
+                trace,
+                Arc::clone(permits),
+            )
+            .await?;
+            let conversion = Evaluation::new(members_resolver, options, false, Arc::clone(permits));
+            let members = resolve_all(
+                index.members_of(&roles).into_iter().map(Leaf::RoleID),
+                &conversion,
+            )
+            .await
+            .map_err(located)?;
+            let members = conversion
+                .index
+                .into_inner()
+                .unwrap_or_else(PoisonError::into_inner)
+                .members_of(&members);
+            let members = resolve_all(members.into_iter().map(Leaf::UserID), evaluation)
+                .await
+                .map_err(located)?;
+            Ok((members, operand_trace))
+        }
+        Expr::Union(..)
+        | Expr::Intersection(..)
+        | Expr::Difference(..)
+        | Expr::StringLiteral(..)
+        | Expr::UnknownID(..)
+        | Expr::UserID(..)
+        | Expr::RoleID(..) => unreachable!("only conversions are interpreted as conversions"),
+    }
+}
+
+/// Interpret a single node of a DRQL AST as part of an [`Evaluation`]
 #[async_recursion]
 #[instrument(skip_all, fields(node = %node))]
 #[allow(clippy::multiple_bound_locations)]
-pub async fn interpret<E: Send>(
+async fn interpret_node<
+    E: Send + From<UnsupportedConversion>,
+    T: Element,
+    R: InterpreterResolver<E, T> + Sync + ?Sized,
+>(
     node: Expr,
-    resolver: &mut (impl InterpreterResolver<E> + Send),
-) -> Result<HashSet<UserId>, E> {
-    Ok(match node {
-        Expr::Difference(lhs, rhs) => interpret(*lhs, resolver)
-            .await?
-            .difference(&interpret(*rhs, resolver).await?)
-            .copied()
-            .collect::<HashSet<_>>(),
-        Expr::Intersection(lhs, rhs) => interpret(*lhs, resolver)
-            .await?
-            .intersection(&interpret(*rhs, resolver).await?)
-            .copied()
-            .collect::<HashSet<_>>(),
-        Expr::Union(lhs, rhs) => interpret(*lhs, resolver)
-            .await?
-            .union(&interpret(*rhs, resolver).await?)
-            .copied()
-            .collect::<HashSet<_>>(),
+    evaluation: &Evaluation<'async_recursion, R, T>,
+) -> Result<Interpreted, InterpreterError<E>> {
+    let start = Instant::now();
+    let span = node.span();
+    let traced = |kind, members: MemberSet, children| {
+        let trace = evaluation.trace.then(|| Trace {
+            kind,
+            members: members.clone(),
+            elapsed: start.elapsed(),
+            children,
+        });
+        (members, trace)
+    };
+
+    let leaf = match node {
+        Expr::Difference(lhs, rhs, _) => {
+            let (sides, children) = interpret_unless_empty(*lhs, *rhs, evaluation).await?;
+            let members = sides
+                .map(|(lhs, rhs)| lhs.difference(&rhs))
+                .unwrap_or_default();
+            return Ok(traced(TraceKind::Difference, members, children));
+        }
+        Expr::Intersection(lhs, rhs, _) => {
+            let (sides, children) = interpret_unless_empty(*lhs, *rhs, evaluation).await?;
+            let members = sides
+                .map(|(lhs, rhs)| lhs.intersection(&rhs))
+                .unwrap_or_default();
+            return Ok(traced(TraceKind::Intersection, members, children));
+        }
+        Expr::Union(lhs, rhs, _) => {
+            let ((lhs, rhs), children) = interpret_sides(*lhs, *rhs, evaluation).await?;
+            return Ok(traced(TraceKind::Union, lhs.union(&rhs), children));
+        }
+        Expr::RolesOf(..) | Expr::MembersOf(..) => {
+            let label = if matches!(node, Expr::RolesOf(..)) {
+                "RolesOf"
+            } else {
+                "MembersOf"
+            }
+            .to_string();
+            let (members, operand_trace) = interpret_conversion(node, evaluation).await?;
+            return Ok(traced(
+                TraceKind::Conversion { span, label },
+                members,
+                operand_trace.into_iter().collect(),
+            ));
+        }
+
+        Expr::StringLiteral(ref contents, _) => Leaf::StringLiteral(contents.clone()),
+        Expr::UnknownID(ref id, _) => Leaf::UnknownID(id.clone()),
+        Expr::UserID(id, _) => Leaf::UserID(id),
+        Expr::RoleID(id, _) => Leaf::RoleID(id),
+    };
 
-        Expr::StringLiteral(contents) => resolver.resolve_string_literal(contents).await?,
-        Expr::UnknownID(id) => resolver.resolve_unknown_id(id).await?,
-        Expr::UserID(id) => resolver.resolve_user_id(id).await?,
-        Expr::RoleID(id) => resolver.resolve_role_id(id).await?,
-    })
+    let members = evaluation
+        .resolve(leaf)
+        .await
+        .map_err(|error| InterpreterError { span, error })?;
+    if !evaluation.trace {
+        return Ok((members, None));
+    }
+
+    let kind = TraceKind::Leaf {
+        span,
+        label: node.to_string(),
+        target: evaluation.resolver.describe_target(&node),
+    };
+    Ok(traced(kind, members, Vec::new()))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// Let resolvers that fail with a message use the interpreter
+    impl From<UnsupportedConversion> for String {
+        fn from(error: UnsupportedConversion) -> Self {
+            error.to_string()
+        }
+    }
+
     mod basic_cases {
         use anyhow::anyhow;
 
@@ -71,7 +756,7 @@ mod tests {
         #[async_trait]
         impl InterpreterResolver<anyhow::Error> for Resolver {
             async fn resolve_string_literal(
-                &mut self,
+                &self,
                 contents: String,
             ) -> Result<HashSet<UserId>, anyhow::Error> {
                 if contents == "test_ok_case" {
@@ -82,7 +767,7 @@ mod tests {
             }
 
             async fn resolve_unknown_id(
-                &mut self,
+                &self,
                 id: String,
             ) -> Result<HashSet<UserId>, anyhow::Error> {
                 if id == "0" {
@@ -92,10 +777,7 @@ mod tests {
                 }
             }
 
-            async fn resolve_user_id(
-                &mut self,
-                id: UserId,
-            ) -> Result<HashSet<UserId>, anyhow::Error> {
+            async fn resolve_user_id(&self, id: UserId) -> Result<HashSet<UserId>, anyhow::Error> {
                 if id.0 == 0 {
                     Ok(HashSet::from([UserId(3)]))
                 } else {
@@ -103,10 +785,7 @@ mod tests {
                 }
             }
 
-            async fn resolve_role_id(
-                &mut self,
-                id: RoleId,
-            ) -> Result<HashSet<UserId>, anyhow::Error> {
+            async fn resolve_role_id(&self, id: RoleId) -> Result<HashSet<UserId>, anyhow::Error> {
                 if id.0 == 0 {
                     Ok(HashSet::from([UserId(4)]))
                 } else {
@@ -120,16 +799,23 @@ mod tests {
             assert_eq!(
                 interpret(
                     Expr::Union(
-                        Box::new(Expr::StringLiteral("test_ok_case".to_string())),
+                        Box::new(Expr::StringLiteral(
+                            "test_ok_case".to_string(),
+                            Span::default()
+                        )),
                         Box::new(Expr::Union(
-                            Box::new(Expr::UnknownID("0".to_string())),
+                            Box::new(Expr::UnknownID("0".to_string(), Span::default())),
                             Box::new(Expr::Union(
-                                Box::new(Expr::UserID(UserId(0))),
-                                Box::new(Expr::RoleID(RoleId(0)))
-                            ))
-                        ))
+                                Box::new(Expr::UserID(UserId(0), Span::default())),
+                                Box::new(Expr::RoleID(RoleId(0), Span::default())),
+                                Span::default()
+                            )),
+                            Span::default()
+                        )),
+                        Span::default()
                     ),
-                    &mut Resolver {}
+                    &Resolver {},
+                    InterpretOptions::default()
                 )
                 .await
                 .expect("interpret should not fail"),
@@ -141,13 +827,983 @@ mod tests {
         async fn errors_bubble() {
             assert!(interpret(
                 Expr::Union(
-                    Box::new(Expr::StringLiteral("7".to_string())),
-                    Box::new(Expr::StringLiteral("test_ok_case".to_string())),
+                    Box::new(Expr::StringLiteral("7".to_string(), Span::default())),
+                    Box::new(Expr::StringLiteral(
+                        "test_ok_case".to_string(),
+                        Span::default()
+                    )),
+                    Span::default()
                 ),
-                &mut Resolver {},
+                &Resolver {},
+                InterpretOptions::default(),
             )
             .await
             .is_err());
         }
+
+        #[tokio::test]
+        async fn errors_carry_span_of_failing_node() {
+            let err = interpret(
+                Expr::Intersection(
+                    Box::new(Expr::StringLiteral(
+                        "test_ok_case".to_string(),
+                        Span::new(0, 12, 5),
+                    )),
+                    Box::new(Expr::UnknownID("7".to_string(), Span::new(15, 16, 5))),
+                    Span::new(0, 16, 5),
+                ),
+                &Resolver {},
+                InterpretOptions::default(),
+            )
+            .await
+            .expect_err("interpret should fail");
+
+            assert_eq!(err.span, Span::new(15, 16, 5));
+            assert_eq!(err.error.to_string(), "error case 2");
+        }
+
+        #[tokio::test]
+        async fn conversions_need_both_resolvers() {
+            let err = interpret(
+                Expr::RolesOf(
+                    Box::new(Expr::UserID(UserId(0), Span::new(9, 13, 0))),
+                    Span::new(0, 14, 0),
+                ),
+                &Resolver {},
+                InterpretOptions::default(),
+            )
+            .await
+            .expect_err("interpret should fail");
+
+            assert_eq!(err.span, Span::new(0, 14, 0));
+            assert!(err.error.is::<UnsupportedConversion>());
+        }
+    }
+
+    mod memoization {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        use super::*;
+        use crate::drql::parser::parse_drql;
+
+        /// A resolver that counts how many times each leaf was resolved, and how many leaves were
+        /// being resolved at once. Names starting with "empty" resolve to nobody, and every other
+        /// leaf resolves to the same single member.
+        #[derive(Default)]
+        struct CountingResolver {
+            calls: Mutex<HashMap<String, usize>>,
+            in_flight: AtomicUsize,
+            max_in_flight: AtomicUsize,
+        }
+
+        impl CountingResolver {
+            async fn record(&self, leaf: String) -> Result<HashSet<UserId>, String> {
+                *self
+                    .calls
+                    .lock()
+                    .expect("lock should not be poisoned")
+                    .entry(leaf.clone())
+                    .or_default() += 1;
+
+                let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+                // Give other leaves a chance to start resolving
+                for _ in 0..10 {
+                    tokio::task::yield_now().await;
+                }
+                self.in_flight.fetch_sub(1, Ordering::SeqCst);
+
+                if leaf.starts_with("name empty") {
+                    Ok(HashSet::new())
+                } else {
+                    Ok(HashSet::from([UserId(1)]))
+                }
+            }
+
+            fn calls(&self) -> HashMap<String, usize> {
+                self.calls
+                    .lock()
+                    .expect("lock should not be poisoned")
+                    .clone()
+            }
+        }
+
+        #[async_trait]
+        impl InterpreterResolver<String> for CountingResolver {
+            async fn resolve_string_literal(
+                &self,
+                literal: String,
+            ) -> Result<HashSet<UserId>, String> {
+                self.record(format!("name {literal}")).await
+            }
+
+            async fn resolve_unknown_id(&self, id: String) -> Result<HashSet<UserId>, String> {
+                self.record(format!("id {id}")).await
+            }
+
+            async fn resolve_user_id(&self, id: UserId) -> Result<HashSet<UserId>, String> {
+                self.record(format!("user {id}")).await
+            }
+
+            async fn resolve_role_id(&self, id: RoleId) -> Result<HashSet<UserId>, String> {
+                self.record(format!("role {id}")).await
+            }
+
+            fn members_resolver(
+                &self,
+            ) -> Option<&(dyn InterpreterResolver<String, UserId> + Sync)> {
+                Some(self)
+            }
+
+            fn roles_resolver(&self) -> Option<&(dyn InterpreterResolver<String, RoleId> + Sync)> {
+                Some(self)
+            }
+        }
+
+        /// Every member has roles 1 through 8
+        #[async_trait]
+        impl InterpreterResolver<String, RoleId> for CountingResolver {
+            async fn resolve_string_literal(
+                &self,
+                literal: String,
+            ) -> Result<HashSet<RoleId>, String> {
+                Err(literal)
+            }
+
+            async fn resolve_unknown_id(&self, id: String) -> Result<HashSet<RoleId>, String> {
+                Err(id)
+            }
+
+            async fn resolve_user_id(&self, id: UserId) -> Result<HashSet<RoleId>, String> {
+                self.record(format!("roles of user {id}")).await?;
+                Ok((1..=8).map(RoleId).collect())
+            }
+
+            async fn resolve_role_id(&self, id: RoleId) -> Result<HashSet<RoleId>, String> {
+                Ok(HashSet::from([id]))
+            }
+        }
+
+        async fn run(query: &str, resolver: &CountingResolver) {
+            interpret::<_, UserId>(
+                parse_drql(query).expect("parsing should succeed"),
+                resolver,
+                InterpretOptions::default(),
+            )
+            .await
+            .expect("interpret should not fail");
+        }
+
+        #[tokio::test]
+        async fn repeated_leaves_are_resolved_once() {
+            let resolver = CountingResolver::default();
+            run(
+                "staff + (staff & \"staff\") - <@1> + <@1> & <@&2> | <@&2> + 3 - 3",
+                &resolver,
+            )
+            .await;
+
+            assert_eq!(
+                resolver.calls(),
+                HashMap::from([
+                    ("name staff".to_string(), 1),
+                    ("user 1".to_string(), 1),
+                    ("role 2".to_string(), 1),
+                    ("id 3".to_string(), 1),
+                ])
+            );
+        }
+
+        #[tokio::test]
+        async fn different_kinds_of_leaves_are_resolved_separately() {
+            let resolver = CountingResolver::default();
+            run("\"1\" + 1 + <@1> + <@&1>", &resolver).await;
+
+            let calls = resolver.calls();
+            assert_eq!(calls.len(), 4);
+            assert!(calls.values().all(|&calls| calls == 1));
+        }
+
+        #[tokio::test]
+        async fn each_evaluation_resolves_leaves_again() {
+            let resolver = CountingResolver::default();
+            run("staff + staff", &resolver).await;
+            run("staff + staff", &resolver).await;
+
+            assert_eq!(
+                resolver.calls(),
+                HashMap::from([("name staff".to_string(), 2)])
+            );
+        }
+
+        #[tokio::test]
+        async fn leaves_resolve_concurrently_up_to_the_limit() {
+            let resolver = CountingResolver::default();
+            run("a + b + c + d + e + f + g + h + (a & b & c & d)", &resolver).await;
+
+            let max_in_flight = resolver.max_in_flight.load(Ordering::SeqCst);
+            assert!(max_in_flight > 1, "leaves should resolve concurrently");
+            assert!(max_in_flight <= MAX_CONCURRENT_RESOLUTIONS);
+            assert_eq!(resolver.calls().len(), 8);
+        }
+
+        #[tokio::test]
+        async fn conversions_share_the_cache_and_the_limit() {
+            let resolver = CountingResolver::default();
+            run("members_of(<@1>) + <@1>", &resolver).await;
+
+            let max_in_flight = resolver.max_in_flight.load(Ordering::SeqCst);
+            assert!(max_in_flight > 1, "roles should be expanded concurrently");
+            assert!(max_in_flight <= MAX_CONCURRENT_RESOLUTIONS);
+            let calls = resolver.calls();
+            assert_eq!(calls.get("roles of user 1"), Some(&1));
+            assert_eq!(calls.get("user 1"), Some(&1));
+            assert!((1..=8).all(|role| calls.get(&format!("role {role}")) == Some(&1)));
+        }
+    }
+
+    mod short_circuiting {
+        use super::*;
+        use crate::drql::parser::parse_drql;
+
+        /// A resolver where names starting with "empty" resolve to nobody, and names starting with
+        /// "typo" can't be resolved. Every name that is resolved is recorded.
+        #[derive(Default)]
+        struct Resolver {
+            resolved: Mutex<Vec<String>>,
+        }
+
+        #[async_trait]
+        impl InterpreterResolver<String> for Resolver {
+            async fn resolve_string_literal(
+                &self,
+                literal: String,
+            ) -> Result<HashSet<UserId>, String> {
+                self.resolved
+                    .lock()
+                    .expect("lock should not be poisoned")
+                    .push(literal.clone());
+                if literal.starts_with("typo") {
+                    Err(format!("unknown name {literal}"))
+                } else if literal.starts_with("empty") {
+                    Ok(HashSet::new())
+                } else {
+                    Ok(HashSet::from([UserId(1)]))
+                }
+            }
+
+            async fn resolve_unknown_id(&self, id: String) -> Result<HashSet<UserId>, String> {
+                Err(id)
+            }
+
+            async fn resolve_user_id(&self, id: UserId) -> Result<HashSet<UserId>, String> {
+                Ok(HashSet::from([id]))
+            }
+
+            async fn resolve_role_id(&self, id: RoleId) -> Result<HashSet<UserId>, String> {
+                Err(id.to_string())
+            }
+
+            fn validate_leaf(&self, leaf: &Expr) -> Result<(), String> {
+                if let Expr::StringLiteral(literal, _) = leaf {
+                    if literal.starts_with("typo") {
+                        return Err(format!("unknown name {literal}"));
+                    }
+                }
+                Ok(())
+            }
+        }
+
+        async fn run(
+            query: &str,
+            resolver: &Resolver,
+            options: InterpretOptions,
+        ) -> Result<HashSet<UserId>, InterpreterError<String>> {
+            interpret(
+                parse_drql(query).expect("parsing should succeed"),
+                resolver,
+                options,
+            )
+            .await
+        }
+
+        fn resolved(resolver: &Resolver) -> Vec<String> {
+            resolver
+                .resolved
+                .lock()
+                .expect("lock should not be poisoned")
+                .clone()
+        }
+
+        #[tokio::test]
+        async fn empty_left_sides_skip_right_sides() {
+            let resolver = Resolver::default();
+            let result = run(
+                "empty & a - (b + c)",
+                &resolver,
+                InterpretOptions::default(),
+            )
+            .await
+            .expect("interpret should not fail");
+
+            assert!(result.is_empty());
+            assert_eq!(resolved(&resolver), vec!["empty".to_string()]);
+        }
+
+        #[tokio::test]
+        async fn non_empty_left_sides_are_not_skipped() {
+            let resolver = Resolver::default();
+            let result = run("a & empty", &resolver, InterpretOptions::default())
+                .await
+                .expect("interpret should not fail");
+
+            assert!(result.is_empty());
+            assert_eq!(
+                resolved(&resolver),
+                vec!["a".to_string(), "empty".to_string()]
+            );
+        }
+
+        #[tokio::test]
+        async fn skipped_errors_are_hidden_by_default() {
+            let resolver = Resolver::default();
+            assert!(run("empty - typo", &resolver, InterpretOptions::default())
+                .await
+                .expect("interpret should not fail")
+                .is_empty());
+        }
+
+        #[tokio::test]
+        async fn skipped_branches_can_be_validated() {
+            let resolver = Resolver::default();
+            let err = run(
+                "empty & (a + typo)",
+                &resolver,
+                InterpretOptions {
+                    validate_skipped: true,
+                },
+            )
+            .await
+            .expect_err("interpret should fail");
+
+            assert_eq!(err.error, "unknown name typo");
+            assert_eq!(err.span, Span::new(13, 17, 0));
+            assert_eq!(resolved(&resolver), vec!["empty".to_string()]);
+        }
+    }
+
+    mod traces {
+        use super::*;
+        use crate::drql::parser::parse_drql;
+
+        /// A resolver where each name resolves to the members with the IDs of its letters' positions
+        /// in the alphabet, like `ab` to 1 and 2, and is described as a role
+        struct Resolver;
+
+        #[async_trait]
+        impl InterpreterResolver<String> for Resolver {
+            async fn resolve_string_literal(
+                &self,
+                literal: String,
+            ) -> Result<HashSet<UserId>, String> {
+                Ok(literal
+                    .bytes()
+                    .map(|letter| UserId(u64::from(letter - b'a' + 1)))
+                    .collect())
+            }
+
+            async fn resolve_unknown_id(&self, id: String) -> Result<HashSet<UserId>, String> {
+                Err(id)
+            }
+
+            async fn resolve_user_id(&self, id: UserId) -> Result<HashSet<UserId>, String> {
+                Ok(HashSet::from([id]))
+            }
+
+            async fn resolve_role_id(&self, id: RoleId) -> Result<HashSet<UserId>, String> {
+                Err(id.to_string())
+            }
+
+            fn describe_target(&self, leaf: &Expr) -> Option<String> {
+                if let Expr::StringLiteral(literal, _) = leaf {
+                    Some(format!("role @{literal}"))
+                } else {
+                    None
+                }
+            }
+        }
+
+        /// Explain a query, leaving out how long each node took so that it can be compared
+        async fn explained(query: &str) -> Explanation {
+            /// Zero out how long a node and its children took
+            fn without_elapsed(explanation: Explanation) -> Explanation {
+                Explanation {
+                    elapsed: Duration::ZERO,
+                    children: explanation
+                        .children
+                        .into_iter()
+                        .map(without_elapsed)
+                        .collect(),
+                    ..explanation
+                }
+            }
+
+            let (_, explanation) = explain(
+                parse_drql(query).expect("parsing should succeed"),
+                &Resolver,
+                InterpretOptions::default(),
+            )
+            .await
+            .expect("interpreting should succeed");
+            without_elapsed(explanation)
+        }
+
+        fn leaf(label: &str, target: Option<&str>, members: usize) -> Explanation {
+            Explanation {
+                label: label.to_string(),
+                target: target.map(str::to_string),
+                members: Some(members),
+                elapsed: Duration::ZERO,
+                children: Vec::new(),
+            }
+        }
+
+        fn operator(label: &str, members: usize, children: Vec<Explanation>) -> Explanation {
+            Explanation {
+                children,
+                ..leaf(label, None, members)
+            }
+        }
+
+        #[tokio::test]
+        async fn every_node_is_explained() {
+            assert_eq!(
+                explained("(abc + cd) & <@2>").await,
+                operator(
+                    "Intersection",
+                    1,
+                    vec![
+                        operator(
+                            "Union",
+                            4,
+                            vec![
+                                leaf("abc", Some("role @abc"), 3),
+                                leaf("cd", Some("role @cd"), 2)
+                            ]
+                        ),
+                        leaf("<@2>", None, 1)
+                    ]
+                )
+            );
+        }
+
+        #[tokio::test]
+        async fn skipped_nodes_are_explained() {
+            let mut expected = operator(
+                "Difference",
+                0,
+                vec![
+                    operator(
+                        "Intersection",
+                        0,
+                        vec![leaf("a", Some("role @a"), 1), leaf("b", Some("role @b"), 1)],
+                    ),
+                    leaf("(c | d)", None, 0),
+                ],
+            );
+            expected.children[1].members = None;
+
+            assert_eq!(explained("a & b - (c + d)").await, expected);
+        }
+
+        /// Interpret a query, returning the parts of the query that put each member in the result
+        async fn provenance(query: &'static str) -> HashMap<u64, Vec<&'static str>> {
+            let (members, provenance) = interpret_with_provenance(
+                parse_drql(query).expect("parsing should succeed"),
+                &Resolver,
+                InterpretOptions::default(),
+            )
+            .await
+            .expect("interpreting should succeed");
+            assert_eq!(members, provenance.keys().copied().collect());
+
+            provenance
+                .into_iter()
+                .map(|(member, spans)| {
+                    (
+                        member.0,
+                        spans
+                            .into_iter()
+                            .map(|span| &query[span.message_range()])
+                            .collect(),
+                    )
+                })
+                .collect()
+        }
+
+        #[tokio::test]
+        async fn members_are_traced_to_the_leaves_that_matched_them() {
+            // Only the sides of a union that matched a member count
+            assert_eq!(
+                provenance("ab + bc - c").await,
+                HashMap::from([(1, vec!["ab"]), (2, vec!["ab", "bc"])])
+            );
+            // Both sides of an intersection count
+            assert_eq!(
+                provenance("ab & bc").await,
+                HashMap::from([(2, vec!["ab", "bc"])])
+            );
+            // Parts of the query that were removed or came up empty don't count
+            assert_eq!(
+                provenance("(a - a) + b").await,
+                HashMap::from([(2, vec!["b"])])
+            );
+        }
+
+        #[test]
+        fn explanations_are_rendered_as_indented_trees() {
+            let mut explanation = operator(
+                "Difference",
+                1,
+                vec![
+                    leaf("ab", Some("role @ab"), 2),
+                    leaf("everyone - b", None, 0),
+                ],
+            );
+            explanation.children[1].members = None;
+
+            assert_eq!(
+                explanation.to_string(),
+                concat!(
+                    "Difference: 1 members in 0ns\n",
+                    "  ab \u{2192} role @ab: 2 members in 0ns\n",
+                    "  everyone - b: skipped\n",
+                )
+            );
+        }
+    }
+
+    mod determinism {
+        use super::*;
+        use crate::drql::parser::parse_drql;
+
+        /// A resolver where names fail after yielding as many times as they are long, so that
+        /// shorter names fail sooner
+        struct SlowFailingResolver;
+
+        #[async_trait]
+        impl InterpreterResolver<String> for SlowFailingResolver {
+            async fn resolve_string_literal(
+                &self,
+                literal: String,
+            ) -> Result<HashSet<UserId>, String> {
+                for _ in 0..literal.len() {
+                    tokio::task::yield_now().await;
+                }
+                if literal.starts_with("ok") {
+                    Ok(HashSet::from([UserId(
+                        literal.len().try_into().expect("name should be short"),
+                    )]))
+                } else {
+                    Err(literal)
+                }
+            }
+
+            async fn resolve_unknown_id(&self, id: String) -> Result<HashSet<UserId>, String> {
+                Err(id)
+            }
+
+            async fn resolve_user_id(&self, id: UserId) -> Result<HashSet<UserId>, String> {
+                Ok(HashSet::from([id]))
+            }
+
+            async fn resolve_role_id(&self, id: RoleId) -> Result<HashSet<UserId>, String> {
+                Err(id.to_string())
+            }
+        }
+
+        #[tokio::test]
+        async fn leftmost_error_is_returned() {
+            for _ in 0..10 {
+                let err = interpret(
+                    parse_drql("ok + slow_failure + (ok_too & fail)")
+                        .expect("parsing should succeed"),
+                    &SlowFailingResolver,
+                    InterpretOptions::default(),
+                )
+                .await
+                .expect_err("interpret should fail");
+
+                assert_eq!(err.error, "slow_failure");
+                assert_eq!(err.span, Span::new(5, 17, 0));
+            }
+        }
+
+        #[tokio::test]
+        async fn results_do_not_depend_on_timing() {
+            let result = interpret(
+                parse_drql("(ok_long_name + ok) - <@2> + <@3>").expect("parsing should succeed"),
+                &SlowFailingResolver,
+                InterpretOptions::default(),
+            )
+            .await
+            .expect("interpret should not fail");
+
+            assert_eq!(result, HashSet::from([UserId(12), UserId(3)]));
+        }
+    }
+
+    /// Compares [`interpret`] with evaluating every operator on [`HashSet`]s, which is how the
+    /// interpreter used to work
+    mod hash_set_comparison {
+        use std::time::{Duration, Instant};
+
+        use rand::{rngs::StdRng, seq::IteratorRandom, Rng, SeedableRng};
+
+        use super::*;
+
+        /// A resolver with a fixed set of members for every name, in a guild whose members have
+        /// the IDs below `members`
+        struct MapResolver {
+            members: u64,
+            roles: HashMap<String, HashSet<UserId>>,
+        }
+
+        #[async_trait]
+        impl InterpreterResolver<String> for MapResolver {
+            async fn resolve_string_literal(
+                &self,
+                literal: String,
+            ) -> Result<HashSet<UserId>, String> {
+                self.roles.get(&literal).cloned().ok_or(literal)
+            }
+
+            async fn resolve_unknown_id(&self, id: String) -> Result<HashSet<UserId>, String> {
+                Err(id)
+            }
+
+            async fn resolve_user_id(&self, id: UserId) -> Result<HashSet<UserId>, String> {
+                Ok(HashSet::from([id]))
+            }
+
+            async fn resolve_role_id(&self, id: RoleId) -> Result<HashSet<UserId>, String> {
+                Err(id.to_string())
+            }
+
+            fn known_elements(&self) -> Vec<UserId> {
+                (0..self.members).map(UserId).collect()
+            }
+        }
+
+        /// Evaluate a query by building a new [`HashSet`] for every operator
+        fn interpret_with_hash_sets(node: &Expr, resolver: &MapResolver) -> HashSet<UserId> {
+            match node {
+                Expr::Union(lhs, rhs, _) => interpret_with_hash_sets(lhs, resolver)
+                    .union(&interpret_with_hash_sets(rhs, resolver))
+                    .copied()
+                    .collect(),
+                Expr::Intersection(lhs, rhs, _) => interpret_with_hash_sets(lhs, resolver)
+                    .intersection(&interpret_with_hash_sets(rhs, resolver))
+                    .copied()
+                    .collect(),
+                Expr::Difference(lhs, rhs, _) => interpret_with_hash_sets(lhs, resolver)
+                    .difference(&interpret_with_hash_sets(rhs, resolver))
+                    .copied()
+                    .collect(),
+                Expr::StringLiteral(literal, _) => resolver.roles[literal].clone(),
+                Expr::UserID(id, _) => HashSet::from([*id]),
+                Expr::UnknownID(..)
+                | Expr::RoleID(..)
+                | Expr::RolesOf(..)
+                | Expr::MembersOf(..) => unreachable!(),
+            }
+        }
+
+        /// Create `roles` roles, each with a random selection of `members` members
+        fn random_roles(rng: &mut impl Rng, roles: usize, members: u64) -> MapResolver {
+            MapResolver {
+                members,
+                roles: (0..roles)
+                    .map(|role| {
+                        let size = rng.gen_range(0..=members);
+                        (
+                            format!("r{role}"),
+                            (0..members)
+                                .choose_multiple(rng, usize::try_from(size).expect("fits"))
+                                .into_iter()
+                                .map(UserId)
+                                .collect(),
+                        )
+                    })
+                    .collect(),
+            }
+        }
+
+        /// Create a random query using the roles from [`random_roles`] and a few user mentions
+        fn random_query(rng: &mut impl Rng, roles: usize, members: u64, depth: usize) -> Expr {
+            let span = Span::default();
+            if depth == 0 || rng.gen_bool(0.2) {
+                return if rng.gen_bool(0.1) {
+                    Expr::UserID(UserId(rng.gen_range(0..members * 2)), span)
+                } else {
+                    Expr::StringLiteral(format!("r{}", rng.gen_range(0..roles)), span)
+                };
+            }
+
+            let lhs = Box::new(random_query(rng, roles, members, depth - 1));
+            let rhs = Box::new(random_query(rng, roles, members, depth - 1));
+            match rng.gen_range(0..3) {
+                0 => Expr::Union(lhs, rhs, span),
+                1 => Expr::Intersection(lhs, rhs, span),
+                _ => Expr::Difference(lhs, rhs, span),
+            }
+        }
+
+        #[tokio::test]
+        async fn matches_hash_set_evaluation() {
+            let mut rng = StdRng::seed_from_u64(0);
+            for _ in 0..200 {
+                let resolver = random_roles(&mut rng, 6, 40);
+                let query = random_query(&mut rng, 6, 40, 5);
+                let expected = interpret_with_hash_sets(&query, &resolver);
+
+                assert_eq!(
+                    interpret(query, &resolver, InterpretOptions::default())
+                        .await
+                        .expect("interpret should not fail"),
+                    expected
+                );
+            }
+        }
+
+        // Run with `cargo test --release -- --ignored --nocapture benchmark`, since debug builds
+        // are too slow for the timings to mean anything. The bitset timings include indexing every
+        // member of the guild for each query.
+        #[tokio::test]
+        #[ignore = "benchmark, only run when needed"]
+        #[allow(clippy::print_stdout)] // the timings are the output
+        async fn benchmark() {
+            const MEMBERS: u64 = 150_000;
+            const ROLES: usize = 20;
+            const QUERIES: usize = 20;
+
+            let mut rng = StdRng::seed_from_u64(0);
+            let resolver = random_roles(&mut rng, ROLES, MEMBERS);
+            let queries = (0..QUERIES)
+                .map(|_| random_query(&mut rng, ROLES, MEMBERS, 6))
+                .collect::<Vec<_>>();
+
+            let mut hash_set_time = Duration::ZERO;
+            let mut bitset_time = Duration::ZERO;
+            for query in queries {
+                let start = Instant::now();
+                let expected = std::hint::black_box(interpret_with_hash_sets(&query, &resolver));
+                hash_set_time += start.elapsed();
+
+                let start = Instant::now();
+                let result = std::hint::black_box(
+                    interpret(query, &resolver, InterpretOptions::default())
+                        .await
+                        .expect("interpret should not fail"),
+                );
+                bitset_time += start.elapsed();
+
+                assert_eq!(result, expected);
+            }
+
+            println!(
+                "Over {QUERIES} queries and {MEMBERS} members, bitset evaluation took \
+                 {bitset_time:?} and HashSet evaluation took {hash_set_time:?}"
+            );
+        }
+    }
+
+    /// Evaluating queries over roles instead of members
+    mod element_types {
+        use super::*;
+        use crate::drql::parser::parse_drql;
+
+        /// A guild where member 1 has roles 10 and 11, member 2 has roles 11 and 12, and role 11
+        /// is called "shared" when resolving roles. Leaves resolve to members or roles depending on
+        /// the element type.
+        struct Resolver;
+
+        /// The roles of each member
+        fn roles_of(id: UserId) -> HashSet<RoleId> {
+            match id.0 {
+                1 => HashSet::from([RoleId(10), RoleId(11)]),
+                2 => HashSet::from([RoleId(11), RoleId(12)]),
+                _ => HashSet::new(),
+            }
+        }
+
+        #[async_trait]
+        impl InterpreterResolver<String> for Resolver {
+            async fn resolve_string_literal(
+                &self,
+                literal: String,
+            ) -> Result<HashSet<UserId>, String> {
+                Err(literal)
+            }
+
+            async fn resolve_unknown_id(&self, id: String) -> Result<HashSet<UserId>, String> {
+                Err(id)
+            }
+
+            async fn resolve_user_id(&self, id: UserId) -> Result<HashSet<UserId>, String> {
+                Ok(HashSet::from([id]))
+            }
+
+            async fn resolve_role_id(&self, id: RoleId) -> Result<HashSet<UserId>, String> {
+                Ok([UserId(1), UserId(2)]
+                    .into_iter()
+                    .filter(|&member| roles_of(member).contains(&id))
+                    .collect())
+            }
+
+            fn members_resolver(
+                &self,
+            ) -> Option<&(dyn InterpreterResolver<String, UserId> + Sync)> {
+                Some(self)
+            }
+
+            fn roles_resolver(&self) -> Option<&(dyn InterpreterResolver<String, RoleId> + Sync)> {
+                Some(self)
+            }
+        }
+
+        #[async_trait]
+        impl InterpreterResolver<String, RoleId> for Resolver {
+            async fn resolve_string_literal(
+                &self,
+                literal: String,
+            ) -> Result<HashSet<RoleId>, String> {
+                if literal == "shared" {
+                    Ok(HashSet::from([RoleId(11)]))
+                } else {
+                    Err(literal)
+                }
+            }
+
+            async fn resolve_unknown_id(&self, id: String) -> Result<HashSet<RoleId>, String> {
+                Err(id)
+            }
+
+            async fn resolve_user_id(&self, id: UserId) -> Result<HashSet<RoleId>, String> {
+                Ok(roles_of(id))
+            }
+
+            async fn resolve_role_id(&self, id: RoleId) -> Result<HashSet<RoleId>, String> {
+                Ok(HashSet::from([id]))
+            }
+
+            fn members_resolver(
+                &self,
+            ) -> Option<&(dyn InterpreterResolver<String, UserId> + Sync)> {
+                Some(self)
+            }
+
+            fn roles_resolver(&self) -> Option<&(dyn InterpreterResolver<String, RoleId> + Sync)> {
+                Some(self)
+            }
+        }
+
+        async fn roles(query: &str) -> HashSet<RoleId> {
+            interpret(
+                parse_drql(query).expect("parsing should succeed"),
+                &Resolver,
+                InterpretOptions::default(),
+            )
+            .await
+            .expect("interpret should not fail")
+        }
+
+        #[tokio::test]
+        async fn queries_can_be_evaluated_over_roles() {
+            assert_eq!(roles("<@1> & <@2>").await, HashSet::from([RoleId(11)]));
+            assert_eq!(
+                roles("<@1> + <@2> - shared").await,
+                HashSet::from([RoleId(10), RoleId(12)])
+            );
+            assert_eq!(
+                roles("<@&12> + <@1>").await,
+                HashSet::from([RoleId(10), RoleId(11), RoleId(12)])
+            );
+        }
+
+        #[tokio::test]
+        async fn the_element_type_picks_the_resolver() {
+            let members: HashSet<UserId> = interpret(
+                parse_drql("<@&11> - <@&10>").expect("parsing should succeed"),
+                &Resolver,
+                InterpretOptions::default(),
+            )
+            .await
+            .expect("interpret should not fail");
+            assert_eq!(members, HashSet::from([UserId(2)]));
+
+            let (roles, provenance) = interpret_with_provenance::<_, RoleId>(
+                parse_drql("<@1> & <@2>").expect("parsing should succeed"),
+                &Resolver,
+                InterpretOptions::default(),
+            )
+            .await
+            .expect("interpret should not fail");
+            assert_eq!(roles, HashSet::from([RoleId(11)]));
+            assert_eq!(
+                provenance,
+                Provenance::from([(RoleId(11), vec![Span::new(0, 4, 0), Span::new(7, 11, 0)])])
+            );
+        }
+
+        async fn members(query: &str) -> HashSet<UserId> {
+            interpret(
+                parse_drql(query).expect("parsing should succeed"),
+                &Resolver,
+                InterpretOptions::default(),
+            )
+            .await
+            .expect("interpret should not fail")
+        }
+
+        #[tokio::test]
+        async fn conversions_switch_between_members_and_roles() {
+            assert_eq!(
+                roles("roles_of(<@1>) & roles_of(<@2>)").await,
+                HashSet::from([RoleId(11)])
+            );
+            // The operand of `members_of` is over roles, so "shared" is a role here
+            assert_eq!(
+                roles("members_of(shared)").await,
+                HashSet::from([RoleId(10), RoleId(11), RoleId(12)])
+            );
+            assert_eq!(
+                members("members_of(shared) - <@1>").await,
+                HashSet::from([UserId(2)])
+            );
+            assert_eq!(
+                members("members_of(roles_of(<@1>) - <@&11>)").await,
+                HashSet::from([UserId(1)])
+            );
+        }
+
+        #[tokio::test]
+        async fn conversions_are_explained_with_their_operands() {
+            let (_, explanation) = explain::<_, RoleId>(
+                parse_drql("roles_of(<@1>)").expect("parsing should succeed"),
+                &Resolver,
+                InterpretOptions::default(),
+            )
+            .await
+            .expect("explain should not fail");
+
+            assert_eq!(explanation.label, "RolesOf");
+            assert_eq!(explanation.members, Some(2));
+            let [operand] = explanation.children.as_slice() else {
+                panic!("a conversion should have one child");
+            };
+            assert_eq!(operand.label, "<@1>");
+            assert_eq!(operand.members, Some(1));
+        }
     }
 }
@@ -64,6 +64,12 @@ pub enum Tok {
     /// The token `)`
     #[token(")")]
     RightParen,
+    /// The keyword `roles_of`, which converts members to the roles they have
+    #[token("roles_of")]
+    RolesOf,
+    /// The keyword `members_of`, which converts roles to the members who have them
+    #[token("members_of")]
+    MembersOf,
 
     /// String literals: `"abc def"`, `abc`, `everyone`, `here`, etc
     /// From issue #25, `@everyone` and `@here` (the exact strings, which are the mentions)
@@ -111,6 +117,8 @@ impl std::fmt::Display for Tok {
             Self::Ampersand => write!(f, "&"),
             Self::LeftParen => write!(f, "("),
             Self::RightParen => write!(f, ")"),
+            Self::RolesOf => write!(f, "roles_of"),
+            Self::MembersOf => write!(f, "members_of"),
             Self::StringLiteral(contents) => write!(f, "\"{contents}\""),
             Self::IDLiteral(id) => write!(f, "{id}"),
             Self::UserMention(id) => write!(f, "<@{id}>"),
@@ -0,0 +1,283 @@
+//! Limits on how complex a DRQL query may be
+//!
+//! Every name in a query may need an HTTP request to resolve, and deeply nested queries are
+//! expensive to analyze and interpret. These limits are checked before a query is evaluated, so
+//! that an overly complex query is rejected with a clear error instead of doing any of that work.
+
+use std::{
+    collections::HashSet,
+    fmt::{Display, Formatter},
+    mem,
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::ast::Expr;
+
+/// How complex a query may be. Guilds may change these, up to [`QueryLimits::MAXIMUM`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+#[allow(clippy::module_name_repetitions)]
+pub struct QueryLimits {
+    /// The most queries (`@{ ... }` chunks) a single message may contain
+    pub chunks: usize,
+    /// The most AST nodes (names, IDs, and operators) a query may contain, across every chunk
+    pub nodes: usize,
+    /// How deeply the nodes of a query may be nested
+    pub depth: usize,
+    /// The most distinct names and IDs a query may contain, each of which the resolver is called
+    /// for once
+    pub lookups: usize,
+    /// The most `roles_of(...)` and `members_of(...)` conversions a query may contain. Each one
+    /// resolves every member or role its operand matched, so they cost far more than a lookup.
+    pub conversions: usize,
+}
+
+impl QueryLimits {
+    /// The highest each limit can be set to
+    pub const MAXIMUM: Self = Self {
+        chunks: 50,
+        nodes: 500,
+        depth: 200,
+        lookups: 100,
+        conversions: 10,
+    };
+
+    /// Check that a message with `chunks` chunks is within these limits
+    pub const fn check_chunks(&self, chunks: usize) -> Result<(), LimitExceeded> {
+        if chunks > self.chunks {
+            return Err(LimitExceeded::Chunks {
+                count: chunks,
+                max: self.chunks,
+            });
+        }
+        Ok(())
+    }
+
+    /// Check that a query is within these limits
+    pub fn check(&self, ast: &Expr) -> Result<(), LimitExceeded> {
+        let mut nodes = 0;
+        let mut depth = 0;
+        let mut lookups = HashSet::new();
+        let mut conversions = 0;
+
+        // This walks the tree without recursion, as the whole point is to protect against
+        // queries that are too deeply nested
+        let mut stack = vec![(ast, 1)];
+        while let Some((node, node_depth)) = stack.pop() {
+            nodes += 1;
+            depth = depth.max(node_depth);
+
+            if let Expr::Union(lhs, rhs, _)
+            | Expr::Intersection(lhs, rhs, _)
+            | Expr::Difference(lhs, rhs, _) = node
+            {
+                stack.push((rhs, node_depth + 1));
+                stack.push((lhs, node_depth + 1));
+            } else if let Expr::RolesOf(operand, _) | Expr::MembersOf(operand, _) = node {
+                conversions += 1;
+                stack.push((operand, node_depth + 1));
+            } else {
+                lookups.insert((mem::discriminant(node), node.to_string()));
+            }
+        }
+
+        if nodes > self.nodes {
+            return Err(LimitExceeded::Nodes {
+                count: nodes,
+                max: self.nodes,
+            });
+        }
+        if depth > self.depth {
+            return Err(LimitExceeded::Depth {
+                count: depth,
+                max: self.depth,
+            });
+        }
+        if lookups.len() > self.lookups {
+            return Err(LimitExceeded::Lookups {
+                count: lookups.len(),
+                max: self.lookups,
+            });
+        }
+        if conversions > self.conversions {
+            return Err(LimitExceeded::Conversions {
+                count: conversions,
+                max: self.conversions,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Default for QueryLimits {
+    fn default() -> Self {
+        Self {
+            chunks: 10,
+            nodes: 100,
+            depth: 50,
+            lookups: 25,
+            conversions: 2,
+        }
+    }
+}
+
+/// Which limit a query exceeded, and by how much
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitExceeded {
+    /// The message had too many chunks
+    Chunks {
+        /// How many chunks the message had
+        count: usize,
+        /// The most chunks allowed
+        max: usize,
+    },
+    /// The query had too many nodes
+    Nodes {
+        /// How many nodes the query had
+        count: usize,
+        /// The most nodes allowed
+        max: usize,
+    },
+    /// The query was nested too deeply
+    Depth {
+        /// How deeply the query was nested
+        count: usize,
+        /// The deepest nesting allowed
+        max: usize,
+    },
+    /// The query had too many distinct names and IDs
+    Lookups {
+        /// How many distinct names and IDs the query had
+        count: usize,
+        /// The most distinct names and IDs allowed
+        max: usize,
+    },
+    /// The query had too many conversions between members and roles
+    Conversions {
+        /// How many conversions the query had
+        count: usize,
+        /// The most conversions allowed
+        max: usize,
+    },
+}
+
+impl Display for LimitExceeded {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Chunks { count, max } => write!(
+                f,
+                "Your message contains {count} queries, but at most {max} are allowed in one message."
+            ),
+            Self::Nodes { count, max } => write!(
+                f,
+                "Your query has {count} names, IDs, and operators, but at most {max} are allowed. Try simplifying it."
+            ),
+            Self::Depth { count, max } => write!(
+                f,
+                "Your query is nested {count} levels deep, but at most {max} levels are allowed. Try simplifying it."
+            ),
+            Self::Lookups { count, max } => write!(
+                f,
+                "Your query refers to {count} different names and IDs, but at most {max} can be looked up at once."
+            ),
+            Self::Conversions { count, max } => write!(
+                f,
+                "Your query uses `roles_of(...)` and `members_of(...)` {count} times, but at most {max} are allowed. Try simplifying it."
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drql::parser::parse_drql;
+
+    fn check(limits: QueryLimits, query: &str) -> Result<(), LimitExceeded> {
+        limits.check(&parse_drql(query).expect("parsing should succeed"))
+    }
+
+    #[test]
+    fn queries_within_limits_pass() {
+        assert_eq!(check(QueryLimits::default(), "a + (b & c) - <@1>"), Ok(()));
+        assert_eq!(QueryLimits::default().check_chunks(10), Ok(()));
+    }
+
+    #[test]
+    fn too_many_chunks_fail() {
+        assert_eq!(
+            QueryLimits::default().check_chunks(11),
+            Err(LimitExceeded::Chunks { count: 11, max: 10 })
+        );
+    }
+
+    #[test]
+    fn too_many_nodes_fail() {
+        let limits = QueryLimits {
+            nodes: 4,
+            ..QueryLimits::default()
+        };
+        assert_eq!(check(limits, "a + a"), Ok(()));
+        assert_eq!(
+            check(limits, "a + a + a"),
+            Err(LimitExceeded::Nodes { count: 5, max: 4 })
+        );
+    }
+
+    #[test]
+    fn too_much_nesting_fails() {
+        let limits = QueryLimits {
+            depth: 3,
+            ..QueryLimits::default()
+        };
+        assert_eq!(check(limits, "(a + b) & (c + d)"), Ok(()));
+        assert_eq!(
+            check(limits, "a + (b & (c - d))"),
+            Err(LimitExceeded::Depth { count: 4, max: 3 })
+        );
+    }
+
+    #[test]
+    fn too_many_lookups_fail() {
+        let limits = QueryLimits {
+            lookups: 2,
+            ..QueryLimits::default()
+        };
+        // Repeated names are only looked up once
+        assert_eq!(check(limits, "a + b - \"a\" & b"), Ok(()));
+        // The same digits as a name and as an ID are looked up separately
+        assert_eq!(
+            check(limits, "a + \"1\" + 1"),
+            Err(LimitExceeded::Lookups { count: 3, max: 2 })
+        );
+    }
+
+    #[test]
+    fn too_many_conversions_fail() {
+        let limits = QueryLimits {
+            conversions: 1,
+            ..QueryLimits::default()
+        };
+        assert_eq!(check(limits, "members_of(a) + b"), Ok(()));
+        // Every conversion counts, even of the same operand
+        assert_eq!(
+            check(limits, "members_of(roles_of(a)) + members_of(roles_of(a))"),
+            Err(LimitExceeded::Conversions { count: 4, max: 1 })
+        );
+    }
+
+    #[test]
+    fn long_chains_do_not_overflow_the_stack() {
+        let query = vec!["a"; 1000].join(" + ");
+        assert_eq!(
+            check(QueryLimits::default(), &query),
+            Err(LimitExceeded::Nodes {
+                count: 1999,
+                max: 100
+            })
+        );
+    }
+}
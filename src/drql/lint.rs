@@ -0,0 +1,328 @@
+//! Warnings about DRQL queries that are valid, but probably don't do what their author meant
+//!
+//! A query like `here - everyone` parses and runs just fine, but it can never match anyone, and the
+//! author only finds out when nobody gets pinged. This module looks for these mistakes before a
+//! query is run, so they can be pointed out alongside the result.
+
+use std::fmt::{Display, Formatter};
+
+use super::{
+    ast::{Expr, Span},
+    diagnostics::quote_span,
+    normal_form::{Atoms, TruthTable},
+};
+
+/// The most warnings [`describe_lints`] will describe. Warnings are sent alongside the notification,
+/// so a handful is enough to point the author at the problem without burying the reply.
+const MAX_DESCRIBED_LINTS: usize = 5;
+
+/// The kinds of suspicious patterns [`lint`] looks for, from the most to the least specific
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LintKind {
+    /// Part of a query can never match anyone, like `a - a`
+    AlwaysEmpty,
+    /// Part of a query always matches everyone, like `everyone + a`
+    AlwaysEveryone,
+    /// An operand has no effect on the result of its operator, like `b` in `a + (a & b)`
+    RedundantOperand,
+    /// An intersection of a union or difference without parentheses, like `a + b & c`, which is
+    /// read as `(a + b) & c` since DRQL operators have no precedence
+    PrecedenceTrap,
+}
+
+/// A single warning about part of a query
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lint {
+    /// What kind of problem this is
+    pub kind: LintKind,
+    /// The part of the query with the problem
+    pub span: Span,
+    /// A description of the problem, suitable for showing to the author of the query
+    pub message: String,
+}
+
+impl Display for Lint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// Determine if `node` is a union or difference that wasn't wrapped in parentheses, given the span
+/// of the expression it is the left-hand side of
+const fn is_unparenthesized_union_or_difference(node: &Expr, parent: Span) -> bool {
+    matches!(node, Expr::Union(..) | Expr::Difference(..))
+        && node.span().message_range().start == parent.message_range().start
+}
+
+/// Find an operand of the operator `node` that has no effect on its result, along with a
+/// description of why, given the truth tables of `node` and both of its operands
+fn redundant_operand<'a>(
+    node: &'a Expr,
+    table: &TruthTable,
+    lhs_table: &TruthTable,
+    rhs_table: &TruthTable,
+) -> Option<(&'a Expr, &'static str)> {
+    match node {
+        Expr::Union(_, rhs, _) if table == lhs_table => Some((
+            rhs,
+            "Everyone this matches is already included by the rest of the union.",
+        )),
+        Expr::Union(lhs, _, _) if table == rhs_table => Some((
+            lhs,
+            "Everyone this matches is already included by the rest of the union.",
+        )),
+        Expr::Intersection(_, rhs, _) if table == lhs_table => Some((
+            rhs,
+            "This doesn't narrow down the rest of the intersection.",
+        )),
+        Expr::Intersection(lhs, _, _) if table == rhs_table => Some((
+            lhs,
+            "This doesn't narrow down the rest of the intersection.",
+        )),
+        Expr::Difference(_, rhs, _) if table == lhs_table => {
+            Some((rhs, "This never removes anyone."))
+        }
+        Expr::Union(..)
+        | Expr::Intersection(..)
+        | Expr::Difference(..)
+        | Expr::RolesOf(..)
+        | Expr::MembersOf(..)
+        | Expr::StringLiteral(..)
+        | Expr::UnknownID(..)
+        | Expr::UserID(..)
+        | Expr::RoleID(..) => None,
+    }
+}
+
+/// Check `node` and every node below it, adding any warnings to `lints` and returning the truth
+/// table of `node`.
+///
+/// Truth tables are built from the bottom up over the atoms of the whole query, so that each node
+/// is only evaluated once. Without `atoms`, because the query has too many of them, only checks
+/// that don't need truth tables are done.
+fn lint_node(node: &Expr, atoms: Option<&Atoms>, lints: &mut Vec<Lint>) -> Option<TruthTable> {
+    let (lhs, rhs, span) = match node {
+        Expr::Union(lhs, rhs, span)
+        | Expr::Intersection(lhs, rhs, span)
+        | Expr::Difference(lhs, rhs, span) => (lhs, rhs, *span),
+        // The operand of `roles_of(...)` matches members, but is a query of its own with its own
+        // atoms. The operand of `members_of(...)` matches roles, where `everyone` is a single role
+        // rather than everything, so checking it would produce bogus warnings.
+        Expr::RolesOf(operand, _) => {
+            lints.extend(lint(operand));
+            return atoms.map(|atoms| atoms.truth_table(node));
+        }
+        Expr::MembersOf(..)
+        | Expr::StringLiteral(..)
+        | Expr::UnknownID(..)
+        | Expr::UserID(..)
+        | Expr::RoleID(..) => return atoms.map(|atoms| atoms.truth_table(node)),
+    };
+
+    // Warnings below this node are only kept if this node isn't moot as a whole
+    let mut below = Vec::new();
+    let lhs_table = lint_node(lhs, atoms, &mut below);
+    let rhs_table = lint_node(rhs, atoms, &mut below);
+    let tables = lhs_table.zip(rhs_table).map(|(lhs_table, rhs_table)| {
+        let table = match node {
+            Expr::Union(..) => lhs_table.union(&rhs_table),
+            Expr::Intersection(..) => lhs_table.intersection(&rhs_table),
+            Expr::Difference(..) => lhs_table.difference(&rhs_table),
+            Expr::RolesOf(..)
+            | Expr::MembersOf(..)
+            | Expr::StringLiteral(..)
+            | Expr::UnknownID(..)
+            | Expr::UserID(..)
+            | Expr::RoleID(..) => unreachable!("only operators are combined"),
+        };
+        (table, lhs_table, rhs_table)
+    });
+    let mut warn = |kind, span, message: String| {
+        lints.push(Lint {
+            kind,
+            span,
+            message,
+        });
+    };
+
+    if let Some((table, _, _)) = &tables {
+        if table.is_empty() {
+            warn(
+                LintKind::AlwaysEmpty,
+                span,
+                "This never matches anyone.".to_string(),
+            );
+            return Some(table.clone());
+        }
+        if table.is_everyone() {
+            warn(
+                LintKind::AlwaysEveryone,
+                span,
+                "This always matches everyone, so you could just use `everyone`.".to_string(),
+            );
+            return Some(table.clone());
+        }
+    }
+
+    if let Expr::Intersection(..) = node {
+        if is_unparenthesized_union_or_difference(lhs, span) {
+            warn(
+                LintKind::PrecedenceTrap,
+                span,
+                format!(
+                    concat!(
+                        "This is read as `{}`, since DRQL reads operators from left to right.",
+                        " Add parentheses to make the order explicit."
+                    ),
+                    node
+                ),
+            );
+        }
+    }
+
+    if let Some((operand, message)) = tables.as_ref().and_then(|(table, lhs_table, rhs_table)| {
+        redundant_operand(node, table, lhs_table, rhs_table)
+    }) {
+        warn(
+            LintKind::RedundantOperand,
+            operand.span(),
+            message.to_string(),
+        );
+    }
+
+    lints.append(&mut below);
+    tables.map(|(table, _, _)| table)
+}
+
+/// Look for tautologies, contradictions, redundant operands, and operator precedence traps in a
+/// query, returning a warning for each one in the order they appear.
+///
+/// Only one warning is given for each part of the query, preferring the most specific kind. This
+/// doesn't need to know anything about the guild the query is run in.
+pub fn lint(node: &Expr) -> Vec<Lint> {
+    let atoms = Atoms::new(node).ok();
+    let mut lints = Vec::new();
+    lint_node(node, atoms.as_ref(), &mut lints);
+    lints.sort_by_key(|lint| (lint.span.message_range().start, lint.kind));
+    lints.dedup_by_key(|lint| lint.span);
+    lints
+}
+
+/// Describe each warning, quoting the part of `source` it is about.
+///
+/// `source` should be the whole message the query was scanned from, as warnings are located by
+/// their message-relative position. Only the first few warnings are described in full.
+pub fn describe_lints(source: &str, lints: &[Lint]) -> String {
+    let mut descriptions = lints
+        .iter()
+        .take(MAX_DESCRIBED_LINTS)
+        .map(|lint| {
+            format!(
+                "Warning: {lint}\n{}",
+                quote_span(source, lint.span.message_range())
+            )
+        })
+        .collect::<Vec<_>>();
+    if lints.len() > MAX_DESCRIBED_LINTS {
+        descriptions.push(format!(
+            "...and {} more warnings.",
+            lints.len() - MAX_DESCRIBED_LINTS
+        ));
+    }
+    descriptions.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drql::parser::{parse_drql, parse_drql_chunks};
+
+    /// Lint a query, returning the kind of each warning and the source text it points at
+    fn lints(query: &str) -> Vec<(LintKind, &str)> {
+        lint(&parse_drql(query).expect("parsing should succeed"))
+            .into_iter()
+            .map(|lint| (lint.kind, &query[lint.span.message_range()]))
+            .collect()
+    }
+
+    #[test]
+    fn clean_queries_have_no_warnings() {
+        assert_eq!(lints("a"), vec![]);
+        assert_eq!(lints("a + b - c"), vec![]);
+        assert_eq!(lints("(a + b) & c"), vec![]);
+        assert_eq!(lints("a & b + c"), vec![]);
+    }
+
+    #[test]
+    fn finds_contradictions() {
+        assert_eq!(
+            lints("here - everyone"),
+            vec![(LintKind::AlwaysEmpty, "here - everyone")]
+        );
+        assert_eq!(
+            lints("a - (a + b)"),
+            vec![(LintKind::AlwaysEmpty, "a - (a + b)")]
+        );
+        assert_eq!(lints("b + (a - a)"), vec![(LintKind::AlwaysEmpty, "a - a")]);
+    }
+
+    #[test]
+    fn finds_tautologies() {
+        assert_eq!(
+            lints("everyone + x"),
+            vec![(LintKind::AlwaysEveryone, "everyone + x")]
+        );
+        assert_eq!(
+            lints("a + (everyone - a)"),
+            vec![(LintKind::AlwaysEveryone, "a + (everyone - a)")]
+        );
+    }
+
+    #[test]
+    fn finds_redundant_operands() {
+        assert_eq!(
+            lints("a + (a & b)"),
+            vec![(LintKind::RedundantOperand, "a & b")]
+        );
+        assert_eq!(
+            lints("everyone & a"),
+            vec![(LintKind::RedundantOperand, "everyone")]
+        );
+        assert_eq!(
+            lints("(a & b) - (b - a)"),
+            vec![(LintKind::RedundantOperand, "b - a")]
+        );
+    }
+
+    #[test]
+    fn finds_precedence_traps() {
+        assert_eq!(
+            lints("a + b & c"),
+            vec![(LintKind::PrecedenceTrap, "a + b & c")]
+        );
+        assert_eq!(
+            lints("a - b & c"),
+            vec![(LintKind::PrecedenceTrap, "a - b & c")]
+        );
+        assert_eq!(lints("(a - b) & c"), vec![]);
+    }
+
+    #[test]
+    fn describes_warnings_across_chunks() {
+        let message = "@{a} and @{a}";
+        let ast = Expr::union_all(
+            parse_drql_chunks(&[(2, "a"), (11, "a")]).expect("parsing should succeed"),
+        )
+        .expect("there should be an AST");
+        assert_eq!(
+            describe_lints(message, &lint(&ast)),
+            concat!(
+                "Warning: Everyone this matches is already included by the rest of the union.\n",
+                "```\n",
+                "@{a} and @{a}\n",
+                "           ^\n",
+                "```"
+            )
+        );
+    }
+}
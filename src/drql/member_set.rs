@@ -0,0 +1,216 @@
+//! Dense bitset representations of sets of members, used while interpreting DRQL queries
+//!
+//! Building a fresh [`HashSet`] for every operator in a query is slow and uses a lot of memory in
+//! large guilds. Instead, the interpreter gives every member of the guild a bit position in a
+//! [`MemberIndex`] up front, along with anyone else it comes across, and evaluates operators as
+//! bitwise operations on [`MemberSet`]s. Members are only converted back to [`UserId`]s once the
+//! whole query has been evaluated.
+//!
+//! Despite the names, sets can hold any kind of [`Element`], such as the [`RoleId`]s of roles for
+//! queries over roles.
+//!
+//! [`RoleId`]: poise::serenity_prelude::RoleId
+
+use std::{
+    collections::{HashMap, HashSet},
+    hash::Hash,
+};
+
+use bitvec::prelude::*;
+use poise::serenity_prelude::UserId;
+
+/// Anything a DRQL query can be evaluated over, like the [`UserId`]s of members
+pub trait Element: Copy + Eq + Hash + Send + Sync {}
+
+impl<T: Copy + Eq + Hash + Send + Sync> Element for T {}
+
+/// A set of members, as a bitset over the positions of a [`MemberIndex`]
+///
+/// Sets built from the same index can be combined even if more members were added to the index
+/// in between, as a set never contains members past its own length.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemberSet {
+    /// Whether the member at each position of the index is in this set
+    bits: BitVec,
+}
+
+impl MemberSet {
+    /// Extend this set with absent members until it is at least `len` bits long
+    fn pad_to(&mut self, len: usize) {
+        if self.bits.len() < len {
+            self.bits.resize(len, false);
+        }
+    }
+
+    /// The members in either set
+    pub fn union(mut self, other: &Self) -> Self {
+        self.pad_to(other.bits.len());
+        self.bits[..other.bits.len()] |= other.bits.as_bitslice();
+        self
+    }
+
+    /// The members in both sets
+    pub fn intersection(mut self, other: &Self) -> Self {
+        self.bits.truncate(other.bits.len());
+        let len = self.bits.len();
+        self.bits &= &other.bits[..len];
+        self
+    }
+
+    /// The members in this set but not `other`
+    pub fn difference(self, other: &Self) -> Self {
+        let len = self.bits.len();
+        self.intersection(&other.clone().complement(len))
+    }
+
+    /// The members in the first `len` positions of the index that aren't in this set
+    pub fn complement(mut self, len: usize) -> Self {
+        self.pad_to(len);
+        self.bits.truncate(len);
+        self.bits = !self.bits;
+        self
+    }
+
+    /// The number of members in this set
+    pub fn len(&self) -> usize {
+        self.bits.count_ones()
+    }
+
+    /// Determine if this set has no members
+    pub fn is_empty(&self) -> bool {
+        self.bits.not_any()
+    }
+}
+
+/// Assigns every member a position in the bitsets of [`MemberSet`]s
+#[derive(Debug)]
+pub struct MemberIndex<T = UserId> {
+    /// The member at each position
+    ids: Vec<T>,
+    /// The position of each member
+    positions: HashMap<T, usize>,
+}
+
+impl<T> Default for MemberIndex<T> {
+    fn default() -> Self {
+        Self {
+            ids: Vec::new(),
+            positions: HashMap::new(),
+        }
+    }
+}
+
+impl<T: Element> MemberIndex<T> {
+    /// Create an index giving each of `members` a position, in order
+    pub fn with_members(members: impl IntoIterator<Item = T>) -> Self {
+        let mut index = Self::default();
+        for member in members {
+            index.position_of(member);
+        }
+        index
+    }
+
+    /// The number of members with a position in this index
+    #[allow(clippy::len_without_is_empty)] // an index is only ever used to build sets
+    pub const fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// The position of `member`, giving it a new position if it doesn't have one yet
+    fn position_of(&mut self, member: T) -> usize {
+        *self.positions.entry(member).or_insert_with(|| {
+            self.ids.push(member);
+            self.ids.len() - 1
+        })
+    }
+
+    /// Convert members to a [`MemberSet`], giving any members that don't have a position yet a
+    /// new one
+    pub fn set_of<'a>(&mut self, members: impl IntoIterator<Item = &'a T>) -> MemberSet
+    where
+        T: 'a,
+    {
+        let positions = members
+            .into_iter()
+            .map(|&member| self.position_of(member))
+            .collect::<Vec<_>>();
+
+        let mut bits = bitvec![0; self.len()];
+        for position in positions {
+            bits.set(position, true);
+        }
+        MemberSet { bits }
+    }
+
+    /// Convert a [`MemberSet`] built from this index back to the members in it
+    pub fn members_of(&self, set: &MemberSet) -> HashSet<T> {
+        set.bits
+            .iter_ones()
+            .map(|position| self.ids[position])
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(ids: &[u64]) -> HashSet<UserId> {
+        ids.iter().copied().map(UserId).collect()
+    }
+
+    #[test]
+    fn sets_round_trip_through_the_index() {
+        let mut index = MemberIndex::default();
+        index.set_of(&ids(&[5, 6]));
+        let set = index.set_of(&ids(&[6, 7]));
+
+        assert_eq!(index.len(), 3);
+        assert_eq!(set.len(), 2);
+        assert_eq!(index.members_of(&set), ids(&[6, 7]));
+    }
+
+    #[test]
+    fn known_members_have_positions_up_front() {
+        let mut index = MemberIndex::with_members([1, 2, 3, 2].map(UserId));
+        assert_eq!(index.len(), 3);
+
+        let set = index.set_of(&ids(&[3, 4]));
+        assert_eq!(index.len(), 4);
+        assert_eq!(index.members_of(&set), ids(&[3, 4]));
+        assert_eq!(index.members_of(&set.complement(index.len())), ids(&[1, 2]));
+    }
+
+    #[test]
+    fn operators_match_hash_set_operators() {
+        let mut index = MemberIndex::default();
+        let lhs = index.set_of(&ids(&[1, 2, 3]));
+        // Sets built later are longer, since the index has grown
+        let rhs = index.set_of(&ids(&[3, 4]));
+
+        assert_eq!(
+            index.members_of(&lhs.clone().union(&rhs)),
+            ids(&[1, 2, 3, 4])
+        );
+        assert_eq!(
+            index.members_of(&rhs.clone().union(&lhs)),
+            ids(&[1, 2, 3, 4])
+        );
+        assert_eq!(index.members_of(&lhs.clone().intersection(&rhs)), ids(&[3]));
+        assert_eq!(index.members_of(&rhs.clone().intersection(&lhs)), ids(&[3]));
+        assert_eq!(
+            index.members_of(&lhs.clone().difference(&rhs)),
+            ids(&[1, 2])
+        );
+        assert_eq!(index.members_of(&rhs.difference(&lhs)), ids(&[4]));
+        assert_eq!(index.members_of(&lhs.complement(index.len())), ids(&[4]));
+    }
+
+    #[test]
+    fn empty_sets_are_empty() {
+        let mut index = MemberIndex::default();
+        assert!(MemberSet::default().is_empty());
+        assert!(index.set_of(&HashSet::new()).is_empty());
+        assert!(!index.set_of(&ids(&[1])).is_empty());
+    }
+}
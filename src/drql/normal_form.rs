@@ -0,0 +1,556 @@
+//! Normal forms and equivalence checking for DRQL queries
+//!
+//! Whether a member matches a query only depends on which of the query's leaves (its "atoms", like
+//! role names and IDs) they belong to. This module treats a query as a boolean formula over those
+//! atoms, which lets us rewrite it into a canonical form and decide whether two queries always
+//! match the same members, no matter what guild they are run in.
+//!
+//! Everything here enumerates every combination of atoms, so queries are limited to
+//! [`MAX_ATOMS`] distinct atoms.
+
+use std::{
+    collections::BTreeSet,
+    fmt::{Display, Formatter},
+};
+
+use super::ast::Expr;
+
+/// The most distinct atoms a query (or pair of queries) may have to be normalized or compared
+pub const MAX_ATOMS: usize = 12;
+
+/// The name of the atom that every member belongs to
+const EVERYONE: &str = "everyone";
+
+/// Raised when a query has too many distinct atoms to be analyzed
+#[derive(Debug)]
+pub struct TooManyAtoms {
+    /// How many distinct atoms the query had
+    pub atoms: usize,
+}
+
+impl Display for TooManyAtoms {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "This has {} distinct names and IDs, but at most {MAX_ATOMS} are supported.",
+            self.atoms
+        )
+    }
+}
+
+impl std::error::Error for TooManyAtoms {}
+
+/// A query as a boolean formula, with its leaves replaced by indices into a list of atoms
+enum Formula {
+    /// Every member, which `everyone` always refers to
+    Everyone,
+    /// Members of the atom at this index
+    Atom(usize),
+    /// Members of either side
+    Or(Box<Self>, Box<Self>),
+    /// Members of both sides
+    And(Box<Self>, Box<Self>),
+    /// Members of the left side but not the right side
+    AndNot(Box<Self>, Box<Self>),
+}
+
+impl Formula {
+    /// Convert `node` into a formula over `atoms`, which must contain every atom of `node`
+    fn new(node: &Expr, atoms: &[String]) -> Self {
+        let (lhs, rhs, combine): (_, _, fn(_, _) -> _) = match node {
+            Expr::Union(lhs, rhs, _) => (lhs, rhs, Self::Or),
+            Expr::Intersection(lhs, rhs, _) => (lhs, rhs, Self::And),
+            Expr::Difference(lhs, rhs, _) => (lhs, rhs, Self::AndNot),
+            // Conversions are opaque, so they're atoms as well
+            Expr::RolesOf(..)
+            | Expr::MembersOf(..)
+            | Expr::StringLiteral(..)
+            | Expr::UnknownID(..)
+            | Expr::UserID(..)
+            | Expr::RoleID(..) => {
+                let atom = node.to_string();
+                return if atom == EVERYONE {
+                    Self::Everyone
+                } else {
+                    Self::Atom(
+                        atoms
+                            .binary_search(&atom)
+                            .expect("every atom should have been collected"),
+                    )
+                };
+            }
+        };
+
+        combine(
+            Box::new(Self::new(lhs, atoms)),
+            Box::new(Self::new(rhs, atoms)),
+        )
+    }
+
+    /// Determine whether a member would match this formula, where bit `i` of `assignment` is set
+    /// if they belong to atom `i`
+    fn evaluate(&self, assignment: u32) -> bool {
+        match self {
+            Self::Everyone => true,
+            Self::Atom(index) => assignment & (1 << index) != 0,
+            Self::Or(lhs, rhs) => lhs.evaluate(assignment) || rhs.evaluate(assignment),
+            Self::And(lhs, rhs) => lhs.evaluate(assignment) && rhs.evaluate(assignment),
+            Self::AndNot(lhs, rhs) => lhs.evaluate(assignment) && !rhs.evaluate(assignment),
+        }
+    }
+}
+
+/// Collect the atoms of `node` into `atoms`, by their textual representation.
+///
+/// `everyone` is not an atom, since every member belongs to it.
+fn collect_atoms(node: &Expr, atoms: &mut BTreeSet<String>) {
+    if let Expr::Union(lhs, rhs, _)
+    | Expr::Intersection(lhs, rhs, _)
+    | Expr::Difference(lhs, rhs, _) = node
+    {
+        collect_atoms(lhs, atoms);
+        collect_atoms(rhs, atoms);
+    } else {
+        let atom = node.to_string();
+        if atom != EVERYONE {
+            atoms.insert(atom);
+        }
+    }
+}
+
+/// Collect the atoms of every node in `nodes`, sorted so that they can be binary searched
+fn atoms_of(nodes: &[&Expr]) -> Result<Vec<String>, TooManyAtoms> {
+    let mut atoms = BTreeSet::new();
+    for node in nodes {
+        collect_atoms(node, &mut atoms);
+    }
+
+    if atoms.len() > MAX_ATOMS {
+        return Err(TooManyAtoms { atoms: atoms.len() });
+    }
+    Ok(atoms.into_iter().collect())
+}
+
+/// The atoms of a whole query, collected once so that every node of the query can be evaluated
+/// over the same assignments
+#[derive(Debug)]
+pub struct Atoms(Vec<String>);
+
+impl Atoms {
+    /// Collect the atoms of `root`
+    pub fn new(root: &Expr) -> Result<Self, TooManyAtoms> {
+        atoms_of(&[root]).map(Self)
+    }
+
+    /// Evaluate `node`, which must be part of the query these atoms were collected from, for every
+    /// assignment of atoms
+    pub fn truth_table(&self, node: &Expr) -> TruthTable {
+        let formula = Formula::new(node, &self.0);
+        TruthTable(
+            (0..1 << self.0.len())
+                .map(|assignment| formula.evaluate(assignment))
+                .collect(),
+        )
+    }
+}
+
+/// Whether a member would match part of a query, for every assignment of the query's [`Atoms`].
+///
+/// Two parts of the same query are equivalent exactly when their truth tables are equal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TruthTable(Vec<bool>);
+
+impl TruthTable {
+    /// Combine two truth tables over the same atoms, one assignment at a time
+    fn combine(&self, other: &Self, operator: fn(bool, bool) -> bool) -> Self {
+        Self(
+            self.0
+                .iter()
+                .zip(&other.0)
+                .map(|(&lhs, &rhs)| operator(lhs, rhs))
+                .collect(),
+        )
+    }
+
+    /// The truth table of the union of both sides
+    pub fn union(&self, other: &Self) -> Self {
+        self.combine(other, |lhs, rhs| lhs || rhs)
+    }
+
+    /// The truth table of the intersection of both sides
+    pub fn intersection(&self, other: &Self) -> Self {
+        self.combine(other, |lhs, rhs| lhs && rhs)
+    }
+
+    /// The truth table of the left side without the right side
+    pub fn difference(&self, other: &Self) -> Self {
+        self.combine(other, |lhs, rhs| lhs && !rhs)
+    }
+
+    /// Determine if no member could ever match this
+    pub fn is_empty(&self) -> bool {
+        !self.0.contains(&true)
+    }
+
+    /// Determine if every member always matches this
+    pub fn is_everyone(&self) -> bool {
+        !self.0.contains(&false)
+    }
+}
+
+/// A conjunction of atoms, some of which may be negated, like `a & b - c`
+///
+/// Atom `i` is part of the term if bit `i` of `mask` is set, and is negated if bit `i` of `value`
+/// is clear.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Term {
+    /// The atoms that are part of this term
+    mask: u32,
+    /// Whether each atom in `mask` is required (set) or excluded (clear)
+    value: u32,
+}
+
+/// A query in disjunctive normal form: a union of [`Term`]s.
+///
+/// The terms are every prime implicant of the query (its Blake canonical form), so two queries have
+/// the same normal form exactly when they are equivalent. Normal forms are compared by the atoms
+/// their terms name, since equivalent queries can have different atoms that don't matter, like the
+/// `b` in `a + (b - b)`. Its [`Display`] implementation prints it as a DRQL query.
+#[derive(Debug)]
+pub struct NormalForm {
+    /// The atoms the terms refer to, in sorted order
+    atoms: Vec<String>,
+    /// The terms, sorted with the simplest first
+    terms: Vec<Term>,
+}
+
+impl NormalForm {
+    /// Convert a query into its normal form
+    pub fn new(node: &Expr) -> Result<Self, TooManyAtoms> {
+        let atoms = atoms_of(&[node])?;
+        let formula = Formula::new(node, &atoms);
+        let full_mask = (1 << atoms.len()) - 1;
+
+        // Combine terms that only differ in one atom, like `a & b` and `a - b` into `a`, until
+        // nothing else can be combined. Whatever couldn't be combined is a prime implicant.
+        let mut terms = (0..=full_mask)
+            .filter(|&assignment| formula.evaluate(assignment))
+            .map(|value| Term {
+                mask: full_mask,
+                value,
+            })
+            .collect::<BTreeSet<_>>();
+        let mut primes = BTreeSet::new();
+        while !terms.is_empty() {
+            let mut combined_terms = BTreeSet::new();
+            for term in &terms {
+                let mut combined = false;
+                for bit in (0..atoms.len()).map(|index| 1 << index) {
+                    if term.mask & bit != 0
+                        && terms.contains(&Term {
+                            mask: term.mask,
+                            value: term.value ^ bit,
+                        })
+                    {
+                        combined_terms.insert(Term {
+                            mask: term.mask & !bit,
+                            value: term.value & !bit,
+                        });
+                        combined = true;
+                    }
+                }
+                if !combined {
+                    primes.insert(*term);
+                }
+            }
+            terms = combined_terms;
+        }
+
+        let mut terms = primes.into_iter().collect::<Vec<_>>();
+        terms.sort_by_key(|term| (term.mask.count_ones(), term.mask, !term.value));
+        Ok(Self { atoms, terms })
+    }
+
+    /// The terms, with each atom named along with whether it is required or excluded
+    fn named_terms(&self) -> BTreeSet<Vec<(&str, bool)>> {
+        self.terms
+            .iter()
+            .map(|term| {
+                self.atoms
+                    .iter()
+                    .enumerate()
+                    .filter(|(index, _)| term.mask & (1 << index) != 0)
+                    .map(|(index, atom)| (atom.as_str(), term.value & (1 << index) != 0))
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Determine if no member could ever match this query
+    pub const fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    /// Determine if every member always matches this query
+    pub fn is_everyone(&self) -> bool {
+        self.terms.iter().any(|term| term.mask == 0)
+    }
+
+    /// Write a single term as a DRQL query, like `a & b - c`
+    fn fmt_term(&self, term: Term, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let (included, excluded): (Vec<_>, Vec<_>) = self
+            .atoms
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| term.mask & (1 << index) != 0)
+            .partition(|(index, _)| term.value & (1 << index) != 0);
+
+        if included.is_empty() {
+            write!(f, "{EVERYONE}")?;
+        } else {
+            let included = included
+                .into_iter()
+                .map(|(_, atom)| atom.as_str())
+                .collect::<Vec<_>>();
+            write!(f, "{}", included.join(" & "))?;
+        }
+        for (_, atom) in excluded {
+            write!(f, " - {atom}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl PartialEq for NormalForm {
+    fn eq(&self, other: &Self) -> bool {
+        self.named_terms() == other.named_terms()
+    }
+}
+
+impl Eq for NormalForm {}
+
+impl Display for NormalForm {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.terms.as_slice() {
+            [] => write!(f, "{EVERYONE} - {EVERYONE}"),
+            [term] => self.fmt_term(*term, f),
+            terms => {
+                // Every operator has the same precedence, so any term other than a single atom
+                // needs parentheses, including `everyone - a`
+                for (index, term) in terms.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, " | ")?;
+                    }
+                    if term.mask.count_ones() > 1 || term.value != term.mask {
+                        write!(f, "(")?;
+                        self.fmt_term(*term, f)?;
+                        write!(f, ")")?;
+                    } else {
+                        self.fmt_term(*term, f)?;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A kind of member that matches one query but not another, proving that they aren't equivalent
+#[derive(Debug, PartialEq, Eq)]
+pub struct Distinction {
+    /// The atoms the member belongs to
+    pub member_of: Vec<String>,
+    /// The atoms the member doesn't belong to
+    pub not_member_of: Vec<String>,
+    /// Whether the member matches the first query (and not the second), or the other way around
+    pub matches_first: bool,
+}
+
+impl Display for Distinction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        /// Join atoms into a list, like "`a`, `b`, and `c`"
+        fn list(atoms: &[String], conjunction: &str) -> String {
+            let atoms = atoms
+                .iter()
+                .map(|atom| format!("`{atom}`"))
+                .collect::<Vec<_>>();
+            match atoms.as_slice() {
+                [] => String::new(),
+                [only] => only.clone(),
+                [first, second] => format!("{first} {conjunction} {second}"),
+                [rest @ .., last] => format!("{}, {conjunction} {last}", rest.join(", ")),
+            }
+        }
+
+        match (self.member_of.is_empty(), self.not_member_of.is_empty()) {
+            (true, true) => write!(f, "Any member")?,
+            (false, true) => write!(f, "A member of {}", list(&self.member_of, "and"))?,
+            (true, false) => write!(f, "A member of none of {}", list(&self.not_member_of, "or"))?,
+            (false, false) => write!(
+                f,
+                "A member of {} but not {}",
+                list(&self.member_of, "and"),
+                list(&self.not_member_of, "or")
+            )?,
+        }
+
+        let (first, second) = if self.matches_first {
+            ("first", "second")
+        } else {
+            ("second", "first")
+        };
+        write!(f, " would match the {first} query, but not the {second}.")
+    }
+}
+
+/// Determine whether two queries always match the same members.
+///
+/// Returns [`None`] if they are equivalent, or a [`Distinction`] describing a member that only
+/// matches one of them if they aren't.
+pub fn distinguish(lhs: &Expr, rhs: &Expr) -> Result<Option<Distinction>, TooManyAtoms> {
+    let atoms = atoms_of(&[lhs, rhs])?;
+    let (lhs, rhs) = (Formula::new(lhs, &atoms), Formula::new(rhs, &atoms));
+
+    Ok((0..(1 << atoms.len()))
+        .find(|&assignment| lhs.evaluate(assignment) != rhs.evaluate(assignment))
+        .map(|assignment| {
+            let (member_of, not_member_of) = atoms
+                .iter()
+                .enumerate()
+                .partition::<Vec<_>, _>(|(index, _)| assignment & (1 << index) != 0);
+            let names = |atoms: Vec<(usize, &String)>| {
+                atoms.into_iter().map(|(_, atom)| atom.clone()).collect()
+            };
+
+            Distinction {
+                member_of: names(member_of),
+                not_member_of: names(not_member_of),
+                matches_first: lhs.evaluate(assignment),
+            }
+        }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drql::parser::parse_drql;
+
+    fn parse(query: &str) -> Expr {
+        parse_drql(query).expect("parsing should succeed")
+    }
+
+    fn normalized(query: &str) -> String {
+        NormalForm::new(&parse(query))
+            .expect("query should have few enough atoms")
+            .to_string()
+    }
+
+    fn equivalent(lhs: &str, rhs: &str) -> bool {
+        distinguish(&parse(lhs), &parse(rhs))
+            .expect("queries should have few enough atoms")
+            .is_none()
+    }
+
+    #[test]
+    fn normalizes_simple_queries() {
+        assert_eq!(normalized("a"), "a");
+        assert_eq!(normalized("b + a"), "a | b");
+        assert_eq!(normalized("a & b - c"), "a & b - c");
+        assert_eq!(normalized("a - (b + c)"), "a - b - c");
+        assert_eq!(normalized("(a + b) & c"), "(a & c) | (b & c)");
+    }
+
+    #[test]
+    fn normalizes_special_cases() {
+        assert_eq!(normalized("x - x"), "everyone - everyone");
+        assert_eq!(normalized("here - everyone"), "everyone - everyone");
+        assert_eq!(normalized("everyone & foo"), "foo");
+        assert_eq!(normalized("a + (everyone - a)"), "everyone");
+        assert!(NormalForm::new(&parse("a + (everyone - a)"))
+            .expect("query should have few enough atoms")
+            .is_everyone());
+        assert!(NormalForm::new(&parse("(a & b) - a"))
+            .expect("query should have few enough atoms")
+            .is_empty());
+    }
+
+    #[test]
+    fn normal_forms_are_canonical() {
+        assert_eq!(normalized("a & (b + c)"), normalized("(c & a) | (a & b)"));
+        assert_eq!(normalized("a + (a & b)"), normalized("a"));
+        assert_eq!(normalized("\"a\" + <@&1>"), normalized("<@&1> | a"));
+        // Consensus terms are always included, even if they're redundant
+        assert_eq!(
+            normalized("(a & b) + (c - a)"),
+            "(a & b) | (c - a) | (b & c)"
+        );
+    }
+
+    #[test]
+    fn normal_forms_read_back_as_the_same_query() {
+        assert_eq!(normalized("a + (everyone - b)"), "a | (everyone - b)");
+        for query in [
+            "a + (everyone - b)",
+            "(everyone - a) + (everyone - b)",
+            "(a & b) + (c - a)",
+            "(a - b) + (b - a)",
+            "a + (b - c - d)",
+            "x - x",
+            "a + (everyone - a)",
+        ] {
+            let printed = normalized(query);
+            assert_eq!(
+                distinguish(&parse(query), &parse(&printed))
+                    .expect("queries should have few enough atoms"),
+                None,
+                "`{query}` was printed as `{printed}`"
+            );
+        }
+    }
+
+    #[test]
+    fn equivalent_normal_forms_are_equal() {
+        let normal_form =
+            |query| NormalForm::new(&parse(query)).expect("query should have few enough atoms");
+        assert_eq!(normal_form("a + (b - b)"), normal_form("a"));
+        assert_eq!(normal_form("c & (b + a)"), normal_form("(a & c) + (c & b)"));
+        assert_ne!(normal_form("a - b"), normal_form("a"));
+    }
+
+    #[test]
+    fn decides_equivalence() {
+        assert!(equivalent("a - (b + c)", "a - b - c"));
+        assert!(equivalent("a & (b | c)", "(a & b) | (a & c)"));
+        assert!(equivalent("everyone & a", "a"));
+        assert!(!equivalent("a + b & c", "a + (b & c)"));
+    }
+
+    #[test]
+    fn gives_distinguishing_members() {
+        let distinction = distinguish(&parse("a + b & c"), &parse("a + (b & c)"))
+            .expect("queries should have few enough atoms")
+            .expect("queries should not be equivalent");
+        assert_eq!(
+            distinction,
+            Distinction {
+                member_of: vec!["a".to_string()],
+                not_member_of: vec!["b".to_string(), "c".to_string()],
+                matches_first: false,
+            }
+        );
+        assert_eq!(
+            distinction.to_string(),
+            "A member of `a` but not `b` or `c` would match the second query, but not the first."
+        );
+    }
+
+    #[test]
+    fn rejects_too_many_atoms() {
+        let query = (0..=MAX_ATOMS)
+            .map(|index| format!("r{index}"))
+            .collect::<Vec<_>>()
+            .join(" + ");
+        assert!(NormalForm::new(&parse(&query)).is_err());
+    }
+}
@@ -0,0 +1,350 @@
+//! An optimizer for DRQL ASTs
+//!
+//! Queries that are generated or expanded from aliases often contain redundancy, like `a & a` or
+//! `(a + b) + a`. This module rewrites an AST with set-algebra identities after it is bound and
+//! before it is interpreted, so that the interpreter has less to resolve. Operands are only ever
+//! dropped once every name and ID in them has been bound, which checks that they exist and that
+//! the author may use them.
+//!
+//! The optimizer never needs to know anything about the guild a query is run in, beyond the fact
+//! that `everyone` contains every member. Members outside the guild can still be mentioned by ID,
+//! so `everyone` isn't everything a query can match, and `everyone & x` isn't simply `x`.
+
+use poise::serenity_prelude::RoleId;
+
+use super::ast::{Expr, Span};
+
+/// Determine if a node is the `everyone` role, which contains every member. Once a query is bound,
+/// `everyone` is the role with the ID `everyone`.
+fn is_everyone(node: &Expr, everyone: Option<RoleId>) -> bool {
+    match node {
+        Expr::StringLiteral(name, _) => name == "everyone",
+        Expr::RoleID(id, _) => Some(*id) == everyone,
+        Expr::Union(..)
+        | Expr::Intersection(..)
+        | Expr::Difference(..)
+        | Expr::RolesOf(..)
+        | Expr::MembersOf(..)
+        | Expr::UnknownID(..)
+        | Expr::UserID(..) => false,
+    }
+}
+
+/// Determine if everything a node matches is known to be a member of the guild, and so contained
+/// in `everyone`. User IDs can belong to anyone, and unknown IDs might be user IDs.
+fn within_guild(node: &Expr) -> bool {
+    match node {
+        Expr::Union(lhs, rhs, _) => within_guild(lhs) && within_guild(rhs),
+        Expr::Intersection(lhs, rhs, _) => within_guild(lhs) || within_guild(rhs),
+        Expr::Difference(lhs, _, _) => within_guild(lhs),
+        Expr::MembersOf(..) | Expr::StringLiteral(..) | Expr::RoleID(..) => true,
+        Expr::RolesOf(..) | Expr::UnknownID(..) | Expr::UserID(..) => false,
+    }
+}
+
+/// Collect the operands of a chain of unions, like `a + (b + c) + d`, into `operands`
+fn flatten_union(node: Expr, operands: &mut Vec<Expr>) {
+    if let Expr::Union(lhs, rhs, _) = node {
+        flatten_union(*lhs, operands);
+        flatten_union(*rhs, operands);
+    } else {
+        operands.push(node);
+    }
+}
+
+/// Collect the operands of a chain of intersections, like `a & (b & c) & d`, into `operands`
+fn flatten_intersection(node: Expr, operands: &mut Vec<Expr>) {
+    if let Expr::Intersection(lhs, rhs, _) = node {
+        flatten_intersection(*lhs, operands);
+        flatten_intersection(*rhs, operands);
+    } else {
+        operands.push(node);
+    }
+}
+
+/// Remove operands that are structurally equal to an earlier operand
+fn dedup(operands: Vec<Expr>) -> Vec<Expr> {
+    let mut unique = Vec::<Expr>::new();
+    for operand in operands {
+        if !unique.iter().any(|other| other.eq_ignoring_spans(&operand)) {
+            unique.push(operand);
+        }
+    }
+    unique
+}
+
+/// Remove every operand for which `is_absorbed(operand, other)` is true for some other operand
+fn absorb(operands: Vec<Expr>, is_absorbed: impl Fn(&Expr, &Expr) -> bool) -> Vec<Expr> {
+    let absorbed = operands
+        .iter()
+        .enumerate()
+        .map(|(i, operand)| {
+            operands
+                .iter()
+                .enumerate()
+                .any(|(j, other)| i != j && is_absorbed(operand, other))
+        })
+        .collect::<Vec<_>>();
+
+    operands
+        .into_iter()
+        .zip(absorbed)
+        .filter(|(_, absorbed)| !absorbed)
+        .map(|(operand, _)| operand)
+        .collect()
+}
+
+/// Determine if `operand` is an intersection with `other` as one of its operands, so that
+/// `operand + other` is just `other`
+fn intersection_contains(operand: &Expr, other: &Expr) -> bool {
+    if let Expr::Intersection(lhs, rhs, _) = operand {
+        lhs.eq_ignoring_spans(other)
+            || rhs.eq_ignoring_spans(other)
+            || intersection_contains(lhs, other)
+            || intersection_contains(rhs, other)
+    } else {
+        false
+    }
+}
+
+/// Determine if `operand` is a union with `other` as one of its operands, so that
+/// `operand & other` is just `other`
+fn union_contains(operand: &Expr, other: &Expr) -> bool {
+    if let Expr::Union(lhs, rhs, _) = operand {
+        lhs.eq_ignoring_spans(other)
+            || rhs.eq_ignoring_spans(other)
+            || union_contains(lhs, other)
+            || union_contains(rhs, other)
+    } else {
+        false
+    }
+}
+
+/// Combine operands back into a left-associative chain, like `((a + b) + c)`
+fn rebuild(
+    operands: impl IntoIterator<Item = Expr>,
+    combine: fn(Box<Expr>, Box<Expr>, Span) -> Expr,
+) -> Option<Expr> {
+    operands.into_iter().reduce(|acc, operand| {
+        let span = acc.span().to(operand.span());
+        combine(Box::new(acc), Box::new(operand), span)
+    })
+}
+
+/// Optimize a chain of unions
+fn optimize_union(node: Expr, everyone: Option<RoleId>) -> Option<Expr> {
+    let mut chain = Vec::new();
+    flatten_union(node, &mut chain);
+
+    // Empty operands contribute nothing to a union, and optimizing an operand may produce another
+    // union to flatten.
+    let mut operands = Vec::new();
+    for operand in chain
+        .into_iter()
+        .filter_map(|operand| optimize(operand, everyone))
+    {
+        flatten_union(operand, &mut operands);
+    }
+    let mut operands = dedup(operands);
+
+    // everyone + x = everyone, unless x can match someone outside the guild
+    if let Some(position) = operands
+        .iter()
+        .position(|operand| is_everyone(operand, everyone))
+    {
+        let universe = operands.remove(position);
+        operands.retain(|operand| !within_guild(operand));
+        operands.insert(0, universe);
+        return rebuild(operands, Expr::Union);
+    }
+
+    // a + (a & b) = a
+    rebuild(absorb(operands, intersection_contains), Expr::Union)
+}
+
+/// Optimize a chain of intersections
+fn optimize_intersection(node: Expr, everyone: Option<RoleId>) -> Option<Expr> {
+    let mut chain = Vec::new();
+    flatten_intersection(node, &mut chain);
+
+    // If any operand is empty, so is the intersection
+    let mut operands = Vec::new();
+    for operand in chain {
+        flatten_intersection(optimize(operand, everyone)?, &mut operands);
+    }
+    let operands = dedup(operands);
+
+    // a & (a + b) = a
+    rebuild(absorb(operands, union_contains), Expr::Intersection)
+}
+
+/// Optimize a chain of differences
+fn optimize_difference(node: Expr, everyone: Option<RoleId>) -> Option<Expr> {
+    // a - b - c parses as ((a - b) - c), so walk down the left side to find the minuend
+    let mut chain = Vec::new();
+    let mut minuend = node;
+    while let Expr::Difference(lhs, rhs, _) = minuend {
+        chain.push(*rhs);
+        minuend = *lhs;
+    }
+    chain.reverse();
+
+    // Nothing minus anything is nothing
+    let minuend = optimize(minuend, everyone)?;
+
+    // Removing nothing does nothing, and a - (b + c) = a - b - c
+    let mut subtrahends = Vec::new();
+    for subtrahend in chain
+        .into_iter()
+        .filter_map(|subtrahend| optimize(subtrahend, everyone))
+    {
+        flatten_union(subtrahend, &mut subtrahends);
+    }
+    let subtrahends = dedup(subtrahends);
+
+    // x - everyone (for members of the guild), x - x, and (x & y) - x are all empty
+    if subtrahends.iter().any(|subtrahend| {
+        (is_everyone(subtrahend, everyone) && within_guild(&minuend))
+            || subtrahend.eq_ignoring_spans(&minuend)
+            || intersection_contains(&minuend, subtrahend)
+    }) {
+        return None;
+    }
+
+    rebuild(
+        std::iter::once(minuend).chain(subtrahends),
+        Expr::Difference,
+    )
+}
+
+/// Simplify a DRQL AST with set-algebra identities.
+///
+/// This flattens chains of the same operator, removes duplicate and redundant operands, and folds
+/// away anything that is known to be empty. If the whole query is known to always be empty, this
+/// returns [`None`].
+///
+/// Queries should be bound with [`bind`] first, so that nothing is dropped before it has been
+/// checked. `everyone` is then the ID that `everyone` was bound to, which is the guild's.
+///
+/// [`bind`]: super::binder::bind
+pub fn optimize(node: Expr, everyone: Option<RoleId>) -> Option<Expr> {
+    match node {
+        Expr::Union(..) => optimize_union(node, everyone),
+        Expr::Intersection(..) => optimize_intersection(node, everyone),
+        Expr::Difference(..) => optimize_difference(node, everyone),
+        // No members have no roles
+        Expr::RolesOf(operand, span) => {
+            Some(Expr::RolesOf(Box::new(optimize(*operand, everyone)?), span))
+        }
+        // The operand is a set of roles, where `everyone` is a single role rather than
+        // everything, so the identities above don't hold
+        Expr::MembersOf(..) => Some(node),
+        Expr::StringLiteral(..) | Expr::UnknownID(..) | Expr::UserID(..) | Expr::RoleID(..) => {
+            Some(node)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drql::parser::parse_drql;
+
+    /// Optimize a query, returning the optimized query as a string
+    fn optimized(query: &str) -> Option<String> {
+        optimize(parse_drql(query).expect("parsing should succeed"), None)
+            .map(|ast| ast.to_string())
+    }
+
+    #[test]
+    fn leaves_are_unchanged() {
+        assert_eq!(optimized("a"), Some("a".to_string()));
+        assert_eq!(optimized("a + b - c"), Some("((a | b) - c)".to_string()));
+    }
+
+    #[test]
+    fn duplicate_operands_are_removed() {
+        assert_eq!(optimized("a & a"), Some("a".to_string()));
+        assert_eq!(optimized("a + a"), Some("a".to_string()));
+        assert_eq!(optimized("(a + b) + a"), Some("(a | b)".to_string()));
+        assert_eq!(
+            optimized("a & (b & a) & c"),
+            Some("((a & b) & c)".to_string())
+        );
+    }
+
+    #[test]
+    fn chains_are_flattened() {
+        assert_eq!(
+            optimized("a + (b + (c + d))"),
+            Some("(((a | b) | c) | d)".to_string())
+        );
+        assert_eq!(optimized("a - (b + c)"), Some("((a - b) - c)".to_string()));
+    }
+
+    #[test]
+    fn everyone_is_the_universe() {
+        assert_eq!(optimized("foo + @everyone"), Some("everyone".to_string()));
+        assert_eq!(
+            optimized("everyone & everyone"),
+            Some("everyone".to_string())
+        );
+        assert_eq!(optimized("here - everyone"), None);
+    }
+
+    #[test]
+    fn everyone_is_not_everything() {
+        // Members outside the guild can be mentioned by ID, and `everyone` doesn't contain them
+        assert_eq!(
+            optimized("everyone & <@1>"),
+            Some("(everyone & <@1>)".to_string())
+        );
+        assert_eq!(
+            optimized("a + <@1> + everyone + 2"),
+            Some("((everyone | <@1>) | 2)".to_string())
+        );
+        assert_eq!(
+            optimized("<@1> - everyone"),
+            Some("(<@1> - everyone)".to_string())
+        );
+        assert_eq!(optimized("(a & <@1>) - everyone"), None);
+    }
+
+    #[test]
+    fn bound_everyone_is_recognized() {
+        let parse = || parse_drql("<@&7> + a - <@&7>").expect("parsing should succeed");
+        assert_eq!(optimize(parse(), Some(RoleId(7))), None);
+        assert_eq!(
+            optimize(parse(), Some(RoleId(8))).map(|ast| ast.to_string()),
+            Some("((<@&7> | a) - <@&7>)".to_string())
+        );
+    }
+
+    #[test]
+    fn known_empty_results_are_folded() {
+        assert_eq!(optimized("x - x"), None);
+        assert_eq!(optimized("a - (a + b)"), None);
+        assert_eq!(optimized("(a & b) - a"), None);
+        assert_eq!(optimized("(x - x) & y"), None);
+        assert_eq!(optimized("(x - x) - y"), None);
+        assert_eq!(optimized("(x - x) + y"), Some("y".to_string()));
+        assert_eq!(optimized("y - (x - x)"), Some("y".to_string()));
+    }
+
+    #[test]
+    fn absorbed_operands_are_removed() {
+        assert_eq!(optimized("a + (a & b)"), Some("a".to_string()));
+        assert_eq!(optimized("(b & a) + c + a"), Some("(c | a)".to_string()));
+        assert_eq!(optimized("a & (a + b)"), Some("a".to_string()));
+    }
+
+    #[test]
+    fn optimized_spans_cover_their_operands() {
+        let ast = optimize(
+            parse_drql("a + b + a").expect("parsing should succeed"),
+            None,
+        )
+        .expect("query should not be empty");
+        assert_eq!(ast.span(), Span::new(0, 5, 0));
+    }
+}
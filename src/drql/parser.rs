@@ -7,14 +7,96 @@ use tracing::{debug, instrument};
 use super::{ast, lexer};
 use crate::parser;
 
+/// A single error encountered while parsing a DRQL query.
+#[allow(clippy::module_name_repetitions)]
+pub type DrqlParseError = ParseError<usize, lexer::Tok, lexer::LexicalError>;
+
 /// Parse a DRQL expression with the DRQL parser.
+///
+/// The resulting [`Span`]s treat `input` as if it were the whole message. Use [`parse_drql_chunk`]
+/// when parsing a chunk that was scanned out of a larger message.
+///
+/// [`Span`]: ast::Span
+pub fn parse_drql(input: &str) -> Result<ast::Expr, Vec<DrqlParseError>> {
+    parse_drql_chunk(input, 0)
+}
+
+/// Parse a single chunk of a message with the DRQL parser, where `chunk_offset` is the byte offset
+/// of the chunk within the whole message.
+///
+/// Rather than stopping at the first problem, this reports every error it can find. If the input
+/// contains characters that can't be lexed, only those lexical errors are reported, as the
+/// syntax errors caused by skipping them would just be noise.
 #[instrument]
-pub fn parse_drql(
+pub fn parse_drql_chunk(
     input: &str,
-) -> Result<ast::Expr, ParseError<usize, lexer::Tok, lexer::LexicalError>> {
-    parser::ExprParser::new()
-        .parse(lexer::DrqlLexer::new(input))
-        .tap(|ast| debug!("Parser result: {ast:?}"))
+    chunk_offset: usize,
+) -> Result<ast::Expr, Vec<DrqlParseError>> {
+    let (tokens, lexical_errors): (Vec<_>, Vec<_>) =
+        lexer::DrqlLexer::new(input).partition(Result::is_ok);
+    if !lexical_errors.is_empty() {
+        debug!("Lexer errors: {lexical_errors:?}");
+        return Err(lexical_errors
+            .into_iter()
+            .filter_map(Result::err)
+            .map(|error| ParseError::User { error })
+            .collect());
+    }
+
+    let mut recovered_errors = Vec::new();
+    let result = parser::ExprParser::new().parse(chunk_offset, &mut recovered_errors, tokens);
+
+    let mut errors = recovered_errors
+        .into_iter()
+        .map(|recovery| recovery.error)
+        .collect::<Vec<_>>();
+    match result {
+        Ok(ast) if errors.is_empty() => Ok(ast),
+        Ok(_) => Err(errors),
+        Err(error) => {
+            errors.push(error);
+            Err(errors)
+        }
+    }
+    .tap(|ast| debug!("Parser result: {ast:?}"))
+}
+
+/// Every error from parsing a single chunk with [`parse_drql_chunks`]
+#[derive(Debug)]
+pub struct ChunkParseErrors<'a> {
+    /// The index of the chunk within the message
+    pub index: usize,
+    /// The source text of the chunk
+    pub source: &'a str,
+    /// The errors encountered while parsing the chunk
+    pub errors: Vec<DrqlParseError>,
+}
+
+/// Parse every chunk of a message, where each chunk is paired with its byte offset in the message.
+///
+/// If any chunk fails to parse, the errors from every broken chunk are returned together.
+pub fn parse_drql_chunks<'a>(
+    chunks: &[(usize, &'a str)],
+) -> Result<Vec<ast::Expr>, Vec<ChunkParseErrors<'a>>> {
+    let mut asts = Vec::new();
+    let mut errors = Vec::new();
+
+    for (index, &(offset, source)) in chunks.iter().enumerate() {
+        match parse_drql_chunk(source, offset) {
+            Ok(ast) => asts.push(ast),
+            Err(chunk_errors) => errors.push(ChunkParseErrors {
+                index,
+                source,
+                errors: chunk_errors,
+            }),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(asts)
+    } else {
+        Err(errors)
+    }
 }
 
 #[cfg(test)]
@@ -22,7 +104,7 @@ mod tests {
     use poise::serenity_prelude::model::prelude::{RoleId, UserId};
 
     use super::*;
-    use crate::drql::ast::Expr;
+    use crate::drql::ast::{Expr, Span};
 
     #[test]
     fn many_token_types() {
@@ -40,17 +122,91 @@ mod tests {
                     Box::new(Expr::Union(
                         Box::new(Expr::Union(
                             Box::new(Expr::Union(
-                                Box::new(Expr::StringLiteral("raw_name".to_string())),
-                                Box::new(Expr::StringLiteral("string literal".to_string()))
+                                Box::new(Expr::StringLiteral(
+                                    "raw_name".to_string(),
+                                    Span::new(0, 8, 0)
+                                )),
+                                Box::new(Expr::StringLiteral(
+                                    "string literal".to_string(),
+                                    Span::new(13, 29, 0)
+                                )),
+                                Span::new(0, 29, 0)
                             )),
-                            Box::new(Expr::UserID(UserId(1)))
+                            Box::new(Expr::UserID(UserId(1), Span::new(34, 38, 0))),
+                            Span::new(0, 38, 0)
                         )),
-                        Box::new(Expr::UserID(UserId(2)))
+                        Box::new(Expr::UserID(UserId(2), Span::new(43, 48, 0))),
+                        Span::new(0, 48, 0)
                     )),
-                    Box::new(Expr::RoleID(RoleId(3)))
+                    Box::new(Expr::RoleID(RoleId(3), Span::new(53, 58, 0))),
+                    Span::new(0, 58, 0)
                 )),
-                Box::new(Expr::UnknownID("4".to_string()))
+                Box::new(Expr::UnknownID("4".to_string(), Span::new(63, 64, 0))),
+                Span::new(0, 64, 0)
             ))
         );
     }
+
+    #[test]
+    fn spans_are_chunk_and_message_relative() {
+        let ast = parse_drql_chunk("a & (b - c)", 10).expect("parsing should succeed");
+        let Expr::Intersection(lhs, rhs, span) = ast else {
+            panic!("expected an intersection, got {ast:?}");
+        };
+
+        assert_eq!(span.chunk_range(), 0..11);
+        assert_eq!(span.message_range(), 10..21);
+        assert_eq!(lhs.span(), Span::new(0, 1, 10));
+        // Parentheses are not included in the span of the parenthesized expression
+        assert_eq!(rhs.span().chunk_range(), 5..10);
+        assert_eq!(rhs.span().message_range(), 15..20);
+    }
+
+    #[test]
+    fn reports_every_syntax_error() {
+        let errors = parse_drql("a + ) - b & & c").expect_err("parsing should fail");
+        assert_eq!(errors.len(), 2);
+        assert!(matches!(
+            errors[0],
+            ParseError::UnrecognizedToken {
+                token: (4, lexer::Tok::RightParen, 5),
+                ..
+            }
+        ));
+        assert!(matches!(
+            errors[1],
+            ParseError::UnrecognizedToken {
+                token: (12, lexer::Tok::Ampersand, 13),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn parse_drql_chunks_gathers_errors_from_every_chunk() {
+        let errors = parse_drql_chunks(&[(2, "a +"), (10, "b"), (20, ") c")])
+            .expect_err("parsing should fail");
+        assert_eq!(
+            errors
+                .iter()
+                .map(|chunk| (chunk.index, chunk.source, chunk.errors.len()))
+                .collect::<Vec<_>>(),
+            vec![(0, "a +", 1), (2, ") c", 1)]
+        );
+    }
+
+    #[test]
+    fn reports_every_lexical_error() {
+        assert_eq!(
+            parse_drql("a # b $ c"),
+            Err(vec![
+                ParseError::User {
+                    error: lexer::LexicalError::UnknownToken((2, '#'))
+                },
+                ParseError::User {
+                    error: lexer::LexicalError::UnknownToken((6, '$'))
+                },
+            ])
+        );
+    }
 }
@@ -0,0 +1,169 @@
+//! Renderers that make DRQL ASTs readable for debugging
+//!
+//! The [`Debug`] output of an [`Expr`] is a single line that quickly becomes unreadable. This
+//! module renders an AST as an indented ASCII tree, or as a Graphviz DOT graph that can be turned
+//! into an image.
+
+use std::fmt::Write as _;
+
+use super::ast::Expr;
+
+/// Describe a single node, without its children, like `Union [0..5]` or `StringLiteral a [0..1]`
+fn label(node: &Expr) -> String {
+    let kind = match node {
+        Expr::Union(..) => "Union",
+        Expr::Intersection(..) => "Intersection",
+        Expr::Difference(..) => "Difference",
+        Expr::RolesOf(..) => "RolesOf",
+        Expr::MembersOf(..) => "MembersOf",
+        Expr::StringLiteral(..) => "StringLiteral",
+        Expr::UnknownID(..) => "UnknownID",
+        Expr::UserID(..) => "UserID",
+        Expr::RoleID(..) => "RoleID",
+    };
+
+    match node {
+        Expr::Union(..)
+        | Expr::Intersection(..)
+        | Expr::Difference(..)
+        | Expr::RolesOf(..)
+        | Expr::MembersOf(..) => {
+            format!("{kind} [{}]", node.span())
+        }
+        Expr::StringLiteral(..) | Expr::UnknownID(..) | Expr::UserID(..) | Expr::RoleID(..) => {
+            format!("{kind} {node} [{}]", node.span())
+        }
+    }
+}
+
+/// The operands of an operator, or nothing for a leaf
+fn children(node: &Expr) -> Vec<&Expr> {
+    match node {
+        Expr::Union(lhs, rhs, _)
+        | Expr::Intersection(lhs, rhs, _)
+        | Expr::Difference(lhs, rhs, _) => {
+            vec![lhs, rhs]
+        }
+        Expr::RolesOf(operand, _) | Expr::MembersOf(operand, _) => vec![operand],
+        Expr::StringLiteral(..) | Expr::UnknownID(..) | Expr::UserID(..) | Expr::RoleID(..) => {
+            Vec::new()
+        }
+    }
+}
+
+/// Write the children of `node` to `tree`, each line starting with `prefix`
+fn write_ascii_children(node: &Expr, prefix: &str, tree: &mut String) {
+    let children = children(node);
+    for (index, child) in children.iter().enumerate() {
+        let last = index + 1 == children.len();
+        tree.push_str(prefix);
+        tree.push_str(if last { "`-- " } else { "|-- " });
+        tree.push_str(&label(child));
+        tree.push('\n');
+        write_ascii_children(
+            child,
+            &format!("{prefix}{}", if last { "    " } else { "|   " }),
+            tree,
+        );
+    }
+}
+
+/// Render an AST as an indented tree of ASCII characters, with one node on each line:
+///
+/// ```text
+/// Union [0..5]
+/// |-- StringLiteral a [0..1]
+/// `-- StringLiteral b [4..5]
+/// ```
+pub fn ascii_tree(node: &Expr) -> String {
+    let mut tree = label(node);
+    tree.push('\n');
+    write_ascii_children(node, "", &mut tree);
+    tree
+}
+
+/// Escape text so that it can be used in a quoted DOT string
+fn escape_dot(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Write `node` and its children to `graph` as DOT statements, numbering nodes from `next_id`.
+/// Returns the number of the node.
+fn write_dot_node(node: &Expr, next_id: &mut usize, graph: &mut String) -> usize {
+    let id = *next_id;
+    *next_id += 1;
+
+    let shape = match node {
+        Expr::Union(..)
+        | Expr::Intersection(..)
+        | Expr::Difference(..)
+        | Expr::RolesOf(..)
+        | Expr::MembersOf(..) => "ellipse",
+        Expr::StringLiteral(..) | Expr::UnknownID(..) | Expr::UserID(..) | Expr::RoleID(..) => {
+            "box"
+        }
+    };
+    writeln!(
+        graph,
+        "    n{id} [label=\"{}\", shape={shape}];",
+        escape_dot(&label(node))
+    )
+    .expect("writing to a String never fails");
+
+    for child in children(node) {
+        let child_id = write_dot_node(child, next_id, graph);
+        writeln!(graph, "    n{id} -> n{child_id};").expect("writing to a String never fails");
+    }
+    id
+}
+
+/// Render an AST as a Graphviz DOT graph, with an edge from each operator to its operands
+pub fn dot(node: &Expr) -> String {
+    let mut graph = "digraph drql {\n".to_string();
+    write_dot_node(node, &mut 0, &mut graph);
+    graph.push_str("}\n");
+    graph
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drql::parser::parse_drql;
+
+    fn parse(query: &str) -> Expr {
+        parse_drql(query).expect("parsing should succeed")
+    }
+
+    #[test]
+    fn renders_ascii_trees() {
+        assert_eq!(
+            ascii_tree(&parse("a + <@1> & (b - 2)")),
+            concat!(
+                "Intersection [0..18]\n",
+                "|-- Union [0..8]\n",
+                "|   |-- StringLiteral a [0..1]\n",
+                "|   `-- UserID <@1> [4..8]\n",
+                "`-- Difference [12..17]\n",
+                "    |-- StringLiteral b [12..13]\n",
+                "    `-- UnknownID 2 [16..17]\n",
+            )
+        );
+        assert_eq!(ascii_tree(&parse("a")), "StringLiteral a [0..1]\n");
+    }
+
+    #[test]
+    fn renders_dot_graphs() {
+        assert_eq!(
+            dot(&parse("a - \"b c\"")),
+            concat!(
+                "digraph drql {\n",
+                "    n0 [label=\"Difference [0..9]\", shape=ellipse];\n",
+                "    n1 [label=\"StringLiteral a [0..1]\", shape=box];\n",
+                "    n0 -> n1;\n",
+                "    n2 [label=\"StringLiteral \\\"b c\\\" [4..9]\", shape=box];\n",
+                "    n0 -> n2;\n",
+                "}\n",
+            )
+        );
+    }
+}
@@ -9,12 +9,21 @@ use regex::Regex;
 
 /// Returns an Iterator over provided text, returning every value within `@{ ... }`.
 pub fn scan(input: &str) -> impl Iterator<Item = &'_ str> {
+    scan_with_offsets(input).map(|(_, chunk)| chunk)
+}
+
+/// Like [`scan`], but also returns the byte offset of each value within the provided text.
+pub fn scan_with_offsets(input: &str) -> impl Iterator<Item = (usize, &'_ str)> {
     lazy_static! {
         static ref RE: Regex =
             Regex::new(r"@\{(.+?)\}").expect("regexp should compile successfully");
     }
-    RE.find_iter(input)
-        .map(|matched| &matched.as_str()[2..(matched.as_str().len() - 1)])
+    RE.find_iter(input).map(|matched| {
+        (
+            matched.start() + 2,
+            &matched.as_str()[2..(matched.as_str().len() - 1)],
+        )
+    })
 }
 
 #[cfg(test)]
@@ -39,4 +48,14 @@ mod tests {
             vec!["everyone - here", "staff"]
         );
     }
+
+    #[test]
+    fn scan_with_offsets_points_at_chunk_contents() {
+        let input = "Hello @{everyone - here}! Come online please! @{staff} as well.";
+        let chunks = scan_with_offsets(input).collect::<Vec<_>>();
+        assert_eq!(chunks, vec![(8, "everyone - here"), (48, "staff")]);
+        for (offset, chunk) in chunks {
+            assert_eq!(&input[offset..(offset + chunk.len())], chunk);
+        }
+    }
 }
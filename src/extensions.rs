@@ -19,6 +19,12 @@ pub trait CustomMemberImpl {
         role: &serenity::Role,
         channel: &serenity::GuildChannel,
     ) -> anyhow::Result<bool>;
+    /// Determine if this member can mention `@everyone` and `@here` in the given channel
+    fn can_mention_everyone(
+        &self,
+        ctx: &serenity::Context,
+        channel: &serenity::GuildChannel,
+    ) -> anyhow::Result<bool>;
 }
 impl CustomMemberImpl for serenity::Member {
     #[allow(clippy::cognitive_complexity)]
@@ -60,6 +66,14 @@ impl CustomMemberImpl for serenity::Member {
             Ok(false)
         }
     }
+    fn can_mention_everyone(
+        &self,
+        ctx: &serenity::Context,
+        channel: &serenity::GuildChannel,
+    ) -> anyhow::Result<bool> {
+        // Channel permissions already account for administrators and guild-wide permissions
+        Ok(channel.permissions_for_user(ctx, self)?.mention_everyone())
+    }
 }
 
 /// Custom trait implemented on all [`serenity::Guild`]s
@@ -73,6 +87,16 @@ pub trait CustomGuildImpl {
         &self,
         ctx: &serenity::Context,
     ) -> anyhow::Result<HashMap<models::mention::RoleType, HashSet<serenity::UserId>>>;
+    /// Like [`CustomGuildImpl::all_roles_and_members`], but only with the roles `bot` can mention
+    /// in `channel`, going by its permissions in that channel. `@everyone` and `@here` are only
+    /// included if both `author` and `bot` may mention them there.
+    fn mentionable_roles_and_members(
+        &self,
+        ctx: &serenity::Context,
+        author: &serenity::Member,
+        bot: &serenity::Member,
+        channel: &serenity::GuildChannel,
+    ) -> anyhow::Result<HashMap<models::mention::RoleType, HashSet<serenity::UserId>>>;
 }
 impl CustomGuildImpl for serenity::Guild {
     fn get_everyone(&self) -> HashSet<serenity::UserId> {
@@ -113,6 +137,42 @@ impl CustomGuildImpl for serenity::Guild {
 
         Ok(map)
     }
+    fn mentionable_roles_and_members(
+        &self,
+        ctx: &serenity::Context,
+        author: &serenity::Member,
+        bot: &serenity::Member,
+        channel: &serenity::GuildChannel,
+    ) -> anyhow::Result<HashMap<models::mention::RoleType, HashSet<serenity::UserId>>> {
+        // Whether the bot may mention anything here depends on its permissions in this channel,
+        // which can deny what its guild-wide permissions allow
+        let bot_can_mention_everyone = bot.can_mention_everyone(ctx, channel)?;
+        let everyone_allowed =
+            author.can_mention_everyone(ctx, channel)? && bot_can_mention_everyone;
+
+        let mut map = self.all_roles_and_members(ctx)?;
+        let mut unmentionable = Vec::new();
+        for role_type in map.keys() {
+            let allowed = match role_type {
+                models::mention::RoleType::Everyone | models::mention::RoleType::Here => {
+                    everyone_allowed
+                }
+                models::mention::RoleType::Role(id) => self
+                    .roles
+                    .get(id)
+                    .is_some_and(|role| role.mentionable || bot_can_mention_everyone),
+            };
+            if !allowed {
+                unmentionable.push(*role_type);
+            }
+        }
+
+        debug!("Leaving out roles that can't be mentioned: {unmentionable:?}");
+        for role_type in unmentionable {
+            map.remove(&role_type);
+        }
+        Ok(map)
+    }
 }
 
 /// Custom trait implemented on all [`serenity::Role`]s
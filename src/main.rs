@@ -57,7 +57,9 @@ mod commands;
 mod drql;
 mod extensions;
 mod models;
+mod provenance;
 mod resolver;
+mod settings;
 mod util;
 
 use lalrpop_util::lalrpop_mod;
@@ -81,9 +83,15 @@ lalrpop_mod!(
     parser
 );
 
-use std::{collections::HashSet, env, ops::ControlFlow, sync::Arc};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    env,
+    ops::ControlFlow,
+    sync::{Arc, Mutex},
+};
 
-use anyhow::{bail, Context as _};
+use anyhow::{anyhow, bail, Context as _};
 use dotenvy::dotenv;
 use poise::{
     serenity_prelude::{self as serenity, Guild, GuildChannel, Member, UserId},
@@ -114,6 +122,10 @@ pub struct Data {
     /// [`ShardManager`]: serenity::ShardManager
     /// [ping]: commands::ping
     shard_manager: Arc<serenity::Mutex<serenity::ShardManager>>,
+    /// The settings of every guild, which can be changed with the [settings] command.
+    ///
+    /// [settings]: commands::settings
+    settings: Arc<settings::SettingsStore>,
 }
 /// Type alias for the poise [`Context`] using our custom [`Data`] type and an anyhow [`Error`].
 ///
@@ -123,7 +135,8 @@ type Context<'a> = poise::Context<'a, Data, anyhow::Error>;
 
 /// Prompts the user to confirm they want to execute a query
 ///
-/// This is used usually when there are over 50 members_to_ping in a single query.
+/// This is used usually when there are over 50 members_to_ping in a single query, or when the
+/// mentions would also notify the `extras`, who the query doesn't match.
 ///
 /// Will return Ok(Continue) if the user accepted, Ok(Break) if the user cancelled or timed out,
 /// and Err if there was an error.
@@ -131,8 +144,10 @@ type Context<'a> = poise::Context<'a, Data, anyhow::Error>;
 async fn confirm_mention_count(
     ctx: &serenity::Context,
     msg: &serenity::Message,
-    stringified_mentions: &Vec<String>,
+    messages: &[String],
     members_to_ping: &HashSet<UserId>,
+    extras: &HashSet<UserId>,
+    bindings: &[drql::binder::Binding],
 ) -> anyhow::Result<ControlFlow<(), ()>> {
     let serenity::Channel::Guild(channel) = msg.channel(ctx).await? else {
         // DMs would have been prevented already.
@@ -142,26 +157,69 @@ async fn confirm_mention_count(
 
     trace!("sending confirmation message");
 
+    let mut extras = extras
+        .iter()
+        .map(|&id| models::mention::Mention::User(id).to_string())
+        .collect::<Vec<_>>();
+    extras.sort_unstable();
+    let extras_note = |listed: &str| {
+        if extras.is_empty() {
+            String::new()
+        } else {
+            format!(
+                concat!(
+                    "\n\nTo save mentions, this will also notify {} people your query doesn't",
+                    " match: {}"
+                ),
+                extras.len(),
+                listed
+            )
+        }
+    };
+    let content = |extras_note: String| {
+        format!(
+            concat!(
+                "**Hold up!** By running this query, you are about to",
+                " mention {} people.{} Are you sure?{}{}"
+            ),
+            members_to_ping.len(),
+            {
+                let len = messages.len();
+                if len > 2 {
+                    format!(" This will require the sending of {len} messages.")
+                } else {
+                    String::new()
+                }
+            },
+            extras_note,
+            if bindings.is_empty() {
+                String::new()
+            } else {
+                format!(
+                    "\n\nYour query was read as: {}",
+                    drql::binder::describe_bindings(bindings)
+                )
+            }
+        )
+    };
+    let mut message_content = content(extras_note(&extras.join(" ")));
+    let attachment = (message_content.len() > 2000).then(|| {
+        message_content = content(extras_note("they are listed in the attached file."));
+        serenity::AttachmentType::Bytes {
+            data: Cow::Owned(extras.join("\n").into_bytes()),
+            filename: "extras.txt".to_string(),
+        }
+    });
+
     let mut confirmation_message = channel
         .send_message(ctx, |msg_builder| {
+            if let Some(attachment) = attachment {
+                msg_builder.add_file(attachment);
+            }
             msg_builder
-                .content(format!(
-                    concat!(
-                        "**Hold up!** By running this query, you are about to",
-                        " mention {} people.{} Are you sure?"
-                    ),
-                    members_to_ping.len(),
-                    {
-                        let len = util::wrap_string_vec(stringified_mentions, " ", 2000)
-                            .expect("a mention should always fit in 2000 chars")
-                            .len();
-                        if len > 2 {
-                            format!(" This will require the sending of {len} messages.")
-                        } else {
-                            String::new()
-                        }
-                    }
-                ))
+                .content(message_content)
+                // Listing the extras mustn't notify them before the author has even confirmed
+                .allowed_mentions(|mentions| mentions.empty_parse().replied_user(true))
                 .reference_message(msg) // basically makes it a reply
                 .components(|components| {
                     components.create_action_row(|action_row| {
@@ -239,55 +297,196 @@ async fn confirm_mention_count(
     }
 }
 
+/// The result of evaluating a DRQL query with [`parse_and_evaluate_query`]
+#[derive(Debug)]
+pub struct EvaluatedQuery {
+    /// Every member the query matched
+    members_to_ping: HashSet<UserId>,
+    /// Warnings about suspicious parts of the query, from [`drql::lint::lint`]
+    lints: Vec<drql::lint::Lint>,
+    /// What each name and ID in the query was bound to, from [`drql::binder::bind`]
+    bindings: Vec<drql::binder::Binding>,
+    /// The leaves of the query that put each member in the result. This is only tracked for
+    /// queries from messages.
+    provenance: drql::interpreter::Provenance,
+}
+
+/// Add the location of the node that failed to an error raised while binding or interpreting a
+/// query, quoting it from the chunk it came from
+fn locate_query_error(
+    chunks: &[(usize, &str)],
+    drql::interpreter::InterpreterError { span, error }: drql::interpreter::InterpreterError<
+        anyhow::Error,
+    >,
+) -> anyhow::Error {
+    let location = chunks
+        .iter()
+        .enumerate()
+        .find(|(_, (offset, _))| *offset == span.chunk_offset)
+        .and_then(|(n, (_, chunk))| {
+            chunk
+                .get(span.chunk_range())
+                .map(|source| format!(" for `{source}` in chunk {n}"))
+        })
+        .unwrap_or_default();
+
+    error.context(format!(
+        "Error calculating result{location} (characters {span} of your query)"
+    ))
+}
+
 /// Process a DRQL query from a single slice of Query chunk strings
-/// and return the resulting members_to_ping
+/// and return the resulting members_to_ping, along with any warnings about the query
+///
+/// Each chunk is paired with its byte offset within the message it was scanned from, which is
+/// used to point at the part of the query that caused an error. Any questions for the author are
+/// asked at `prompt_target`, which also says whether the query came from a message.
 #[instrument(skip_all)]
 pub async fn parse_and_evaluate_query(
     ctx: &serenity::Context,
-    chunks: &[&str],
+    chunks: &[(usize, &str)],
     guild: &Guild,
     member: &Member,
     channel: &GuildChannel,
-) -> anyhow::Result<HashSet<UserId>> {
+    settings: &settings::GuildSettings,
+    prompt_target: resolver::PromptTarget<'_>,
+) -> anyhow::Result<EvaluatedQuery> {
+    settings.limits.check_chunks(chunks.len())?;
+
     trace!("Parsing each chunk...");
 
-    let ast = chunks
-        .iter()
-        .enumerate()
-        .map(|(n, chunk)| {
-            drql::parser::parse_drql(chunk).context(format!("Error parsing chunk {n}"))
-        })
-        .collect::<Result<Vec<_>, _>>()?
-        .into_iter()
-        .reduce(|acc, chunk| Expr::Union(Box::new(acc), Box::new(chunk)))
-        .context("There is no DRQL query in your message to handle.")?; // This should never happen, as we already checked that there was at least one chunk in the input
+    let ast = Expr::union_all(
+        drql::parser::parse_drql_chunks(chunks)
+            .map_err(|errors| anyhow!(drql::diagnostics::describe_chunk_parse_errors(&errors)))?,
+    )
+    .context("There is no DRQL query in your message to handle.")?; // This should never happen, as we already checked that there was at least one chunk in the input
 
     debug!("Fully parsed and reduced AST: {ast:?}");
 
+    settings.limits.check(&ast)?;
+
+    let lints = drql::lint::lint(&ast);
+    debug!("Lint warnings: {lints:?}");
+
+    let resolver = resolver::Resolver {
+        guild,
+        member,
+        ctx,
+        channel,
+        settings,
+        prompt_target,
+        targets: Mutex::default(),
+        prompt_lock: tokio::sync::Mutex::default(),
+    };
+
+    trace!("Binding names and IDs in AST");
+    let (ast, bindings) = drql::binder::bind(ast, &resolver)
+        .await
+        .map_err(|error| locate_query_error(chunks, error))?;
+
+    debug!("Bound AST: {ast:?}");
+
+    // Every name and ID has been checked by now, so the optimizer can drop whatever it likes
+    let Some(ast) = drql::optimizer::optimize(ast, Some(serenity::RoleId(guild.id.0))) else {
+        debug!("Optimizer determined that the query is always empty");
+        return Ok(EvaluatedQuery {
+            members_to_ping: HashSet::new(),
+            lints,
+            bindings,
+            provenance: drql::interpreter::Provenance::new(),
+        });
+    };
+
+    debug!("Optimized AST: {ast:?}");
+
     trace!("Running DRQL interpreter on AST");
-    let members_to_ping = drql::interpreter::interpret(
-        ast,
-        &mut resolver::Resolver {
-            guild,
-            member,
-            ctx,
-            channel,
-        },
-    )
-    .await
-    .context("Error calculating result")?;
+    let options = drql::interpreter::InterpretOptions {
+        validate_skipped: settings.validate_skipped_branches,
+    };
+    // Only queries from messages send notifications, which need to know why each member matched
+    let (members_to_ping, provenance) =
+        if matches!(prompt_target, resolver::PromptTarget::Reply(_)) {
+            drql::interpreter::interpret_with_provenance(ast, &resolver, options).await
+        } else {
+            drql::interpreter::interpret::<_, UserId>(ast, &resolver, options)
+                .await
+                .map(|members_to_ping| (members_to_ping, drql::interpreter::Provenance::new()))
+        }
+        .map_err(|error| locate_query_error(chunks, error))?;
 
     debug!(
         "Evaluated result: {:?}",
         members_to_ping.iter().map(|id| id.0).collect::<Vec<_>>()
     );
 
-    Ok(members_to_ping)
+    Ok(EvaluatedQuery {
+        members_to_ping,
+        lints,
+        bindings,
+        provenance,
+    })
+}
+
+/// Reply to the message containing a query with the notification that it triggered, which has a
+/// button for members to find out why they were mentioned.
+async fn send_notification(
+    ctx: &serenity::Context,
+    msg: &serenity::Message,
+    content: String,
+) -> anyhow::Result<serenity::Message> {
+    Ok(msg
+        .channel_id
+        .send_message(ctx, |msg_builder| {
+            msg_builder
+                .content(content)
+                .reference_message(msg) // basically makes it a reply
+                .components(|components| {
+                    components.create_action_row(|action_row| {
+                        action_row.create_button(|button| {
+                            button
+                                .custom_id(provenance::WHY_MENTIONED_BUTTON)
+                                // question mark emoji
+                                .emoji(serenity::ReactionType::Unicode("\u{2753}".to_string()))
+                                .label("Why was I mentioned?")
+                                .style(serenity::ButtonStyle::Secondary)
+                        })
+                    })
+                })
+        })
+        .await?)
+}
+
+/// Quote the parts of the message that put each member in the result of its query
+fn quote_provenance(
+    msg: &serenity::Message,
+    provenance: drql::interpreter::Provenance,
+) -> provenance::Matches {
+    let mut members_by_span = HashMap::<_, Vec<UserId>>::new();
+    for (member, spans) in provenance {
+        for span in spans {
+            members_by_span.entry(span).or_default().push(member);
+        }
+    }
+    let mut members_by_span = members_by_span.into_iter().collect::<Vec<_>>();
+    members_by_span.sort_unstable_by_key(|(span, _)| span.message_range().start);
+
+    let mut matches = provenance::Matches::default();
+    for (span, members) in members_by_span {
+        if let Some(part) = msg.content.get(span.message_range()) {
+            matches.insert(part, members);
+        }
+    }
+    matches
 }
 
 /// Handle a DRQL query from a message, sending the response message(s) to the channel.
 #[instrument(skip_all)]
-async fn handle_drql_query(ctx: &serenity::Context, msg: &serenity::Message) -> anyhow::Result<()> {
+async fn handle_drql_query(
+    ctx: &serenity::Context,
+    msg: &serenity::Message,
+    settings: &settings::SettingsStore,
+    provenance_store: &provenance::ProvenanceStore,
+) -> anyhow::Result<()> {
     if msg.guild(ctx).is_none() {
         debug!("Ignoring DRQL query sent in DMs.");
         bail!("DRQL queries are not available in DMs.");
@@ -303,48 +502,80 @@ async fn handle_drql_query(ctx: &serenity::Context, msg: &serenity::Message) ->
     };
 
     trace!("Running DRQL parser/interpreter on message");
-    let members_to_ping = parse_and_evaluate_query(
+    let settings = settings.get(guild.id);
+    let EvaluatedQuery {
+        members_to_ping,
+        lints,
+        bindings,
+        provenance,
+    } = parse_and_evaluate_query(
         ctx,
-        &drql::scanner::scan(msg.content.as_str()).collect::<Vec<_>>(),
+        &drql::scanner::scan_with_offsets(msg.content.as_str()).collect::<Vec<_>>(),
         &guild,
         &member,
         &channel,
+        &settings,
+        resolver::PromptTarget::Reply(msg),
     )
     .await?;
 
-    // A hashmap of every role in the guild and its members.
-    let roles_and_their_members = guild.all_roles_and_members(ctx)?;
+    let warnings = (settings.show_lint_warnings && !lints.is_empty())
+        .then(|| drql::lint::describe_lints(&msg.content, &lints));
+
+    // A hashmap of every role in the guild that can be mentioned here, and its members. Members of
+    // any other role would silently go without a notification if it were chosen.
+    let bot = guild
+        .member(ctx, ctx.cache.current_user_id())
+        .await
+        .context("Error fetching the bot's member")?;
+    let roles_and_their_members =
+        Arc::new(guild.mentionable_roles_and_members(ctx, &member, &bot, &channel)?);
+    let members_to_ping = Arc::new(members_to_ping);
 
     // next, we represent the list of users as a bunch of roles containing them and one outliers set.
-    let util::unionize_set::UnionizeSetResult { sets, outliers } =
-        util::unionize_set::unionize_set(&members_to_ping, &roles_and_their_members);
+    // If the guild allows it, some of the roles may also notify a few people the query doesn't
+    // match, who are the extras
+    let util::unionize_set::OwnedUnionizeSetResult {
+        sets,
+        outliers,
+        extras,
+    } = util::unionize_set::unionize_set_exact_blocking(
+        Arc::clone(&members_to_ping),
+        Arc::clone(&roles_and_their_members),
+        util::unionize_set::RenderedLength,
+        settings.solver_budget,
+        settings.overshoot,
+    )
+    .await?;
 
     debug!(
-        "unionize_set result sets: {sets:?}, outliers: {outliers:?}",
+        "unionize_set result sets: {sets:?}, outliers: {outliers:?}, extras: {extras:?}",
         sets = sets,
-        outliers = outliers
+        outliers = outliers,
+        extras = extras
     );
 
-    // Now we need to split the output message into individual pings. First, stringify each user mention...
-    // TODO: Once message splitting is complete this could result in a user being
-    // pinged multiple times if they are present in a role that is split into multiple
-    // messages.
-    // e.g.
-    // user is in @A and @C
-    // message 1: @A @B ...
-    // message 2: @C @D ...
-    // double ping!
+    // Now we need to split the output message into individual pings. First, stringify each mention...
     let stringified_mentions = sets
-        .into_iter()
-        .copied()
-        .map(models::mention::Mention::Role)
+        .iter()
+        .map(|&role| models::mention::Mention::Role(role))
         .chain(
             outliers
-                .into_iter()
+                .iter()
                 .map(|&id| models::mention::Mention::User(id)),
         )
         .map(|x| x.to_string())
         .collect::<Vec<_>>();
+    // ...then split them into messages. A member in two roles would be notified twice if the roles
+    // were mentioned in different messages, so roles that share members are kept together.
+    let messages = util::split_mentions(
+        &sets
+            .iter()
+            .map(|&role| (role, &roles_and_their_members[&role]))
+            .collect::<Vec<_>>(),
+        &outliers.iter().copied().collect::<Vec<_>>(),
+        2000,
+    )?;
 
     debug!(
         "stringified_mentions: {stringified_mentions:?}",
@@ -353,13 +584,43 @@ async fn handle_drql_query(ctx: &serenity::Context, msg: &serenity::Message) ->
 
     if stringified_mentions.is_empty() {
         debug!("Nobody to mention!");
-        msg.reply(ctx, "No users matched.").await?;
+        msg.reply(
+            ctx,
+            warnings.map_or_else(
+                || "No users matched.".to_string(),
+                |warnings| format!("No users matched.\n\n{warnings}"),
+            ),
+        )
+        .await?;
         return Ok(());
     }
 
-    if members_to_ping.len() > 50 {
-        debug!("need to wait for user to confirm large mention");
-        if confirm_mention_count(ctx, msg, &stringified_mentions, &members_to_ping).await?
+    let needs_confirmation = members_to_ping.len() > 50 || !extras.is_empty();
+    // The confirmation prompt echoes the bindings itself, so the author sees what their query was
+    // read as before anyone is mentioned either way
+    let echo = (!needs_confirmation && !bindings.is_empty()).then(|| {
+        format!(
+            "Your query was read as: {}",
+            drql::binder::describe_bindings(&bindings)
+        )
+    });
+    let preamble = [echo, warnings].into_iter().flatten().collect::<Vec<_>>();
+    if !preamble.is_empty() {
+        trace!("Sending the resolved query and lint warnings");
+        msg.channel_id
+            .send_message(ctx, |msg_builder| {
+                msg_builder
+                    .content(preamble.join("\n\n"))
+                    // Bound targets like "@everyone" are only being described, not mentioned
+                    .allowed_mentions(|mentions| mentions.empty_parse().replied_user(true))
+                    .reference_message(msg)
+            })
+            .await?;
+    }
+
+    if needs_confirmation {
+        debug!("need to wait for user to confirm large mention or extras");
+        if confirm_mention_count(ctx, msg, &messages, &members_to_ping, &extras, &bindings).await?
             == ControlFlow::Break(())
         {
             debug!("User cancelled or timed out");
@@ -377,26 +638,37 @@ async fn handle_drql_query(ctx: &serenity::Context, msg: &serenity::Message) ->
         util::mention_application_command(ctx, "about landing").await?
     );
 
+    let mut matches = quote_provenance(msg, provenance);
+    for &role in &sets {
+        for &extra in roles_and_their_members[&role].intersection(&extras) {
+            matches.insert_extra(extra, role);
+        }
+    }
+
     if stringified_mentions.join(" ").len() <= (2000 - notification_string.len()) {
         trace!("Sending single message for mentions");
-        msg.reply(
+        let notification = send_notification(
             ctx,
+            msg,
             format!("{}{}", notification_string, stringified_mentions.join(" ")),
         )
         .await?;
+        provenance_store.insert(notification.id, matches);
     } else {
-        let messages = util::wrap_string_vec(&stringified_mentions, " ", 2000)?;
         trace!("Need to send {} messages.", messages.len());
-        msg.reply(
+        let notification = send_notification(
             ctx,
+            msg,
             format!(
                 "Notification triggered by Intersection. Please wait, sending {} messages...",
                 messages.len()
             ),
         )
         .await?;
+        provenance_store.insert(notification.id, matches);
         for message in messages {
-            msg.reply(ctx, message).await?;
+            let message = send_notification(ctx, msg, message).await?;
+            provenance_store.link(notification.id, message.id);
         }
         msg.reply(
             ctx,
@@ -420,10 +692,28 @@ async fn handle_drql_query(ctx: &serenity::Context, msg: &serenity::Message) ->
 ///
 /// [`EventHandler`]: serenity::EventHandler
 /// [`Message`]: serenity::Message
-struct Handler;
+struct Handler {
+    /// The settings of every guild, shared with [`Data`]
+    settings: Arc<settings::SettingsStore>,
+    /// Why members were mentioned by recent notifications
+    provenance: provenance::ProvenanceStore,
+}
 #[serenity::async_trait]
 #[allow(clippy::ignored_unit_patterns)] // bugged
 impl serenity::EventHandler for Handler {
+    #[instrument(skip_all)]
+    async fn interaction_create(&self, ctx: serenity::Context, interaction: serenity::Interaction) {
+        // Every other interaction is either a command, which poise handles, or is collected by
+        // whatever is waiting for it
+        if let serenity::Interaction::MessageComponent(component) = interaction {
+            if component.data.custom_id == provenance::WHY_MENTIONED_BUTTON {
+                if let Err(err) = self.provenance.respond(&ctx, &component).await {
+                    warn!("Unable to explain why a member was mentioned: {err:#}");
+                }
+            }
+        }
+    }
+
     #[instrument(skip_all, fields(author = msg.author.id.0, content = msg.content))]
     async fn message(&self, ctx: serenity::Context, msg: serenity::Message) {
         debug!("Received new message event");
@@ -435,7 +725,7 @@ impl serenity::EventHandler for Handler {
 
         if drql::scanner::scan(msg.content.as_str()).count() > 0 {
             debug!("Found DRQL queries in message! Handling queries.");
-            match handle_drql_query(&ctx, &msg)
+            match handle_drql_query(&ctx, &msg, &self.settings, &self.provenance)
                 .await
                 .context("Error handling DRQL query")
             {
@@ -513,14 +803,25 @@ async fn main() -> Result<(), anyhow::Error> {
         .with(rolling_appender)
         .init();
 
+    let settings = Arc::new(settings::SettingsStore::load(
+        env::var("GUILD_SETTINGS_PATH").unwrap_or_else(|_| "./guild_settings.json".to_string()),
+    )?);
+    let handler = Handler {
+        settings: Arc::clone(&settings),
+        provenance: provenance::ProvenanceStore::default(),
+    };
+
     let framework: poise::FrameworkBuilder<Data, anyhow::Error> = poise::Framework::builder()
         .options(poise::FrameworkOptions {
             commands: vec![
                 commands::ping(),
                 commands::about(),
                 commands::debug(),
+                commands::drql(),
                 commands::version(),
                 commands::dry_run(),
+                commands::roles(),
+                commands::settings(),
             ],
             on_error: |error| {
                 Box::pin(async move {
@@ -535,10 +836,10 @@ async fn main() -> Result<(), anyhow::Error> {
 
             ..Default::default()
         })
-        .client_settings(|client| client.event_handler(Handler))
+        .client_settings(|client| client.event_handler(handler))
         .token(env::var("TOKEN").expect("Expected a token in the environment"))
         .intents(serenity::GatewayIntents::all())
-        .setup(|ctx, ready, framework| {
+        .setup(move |ctx, ready, framework| {
             Box::pin(async move {
                 info!(
                     "Logged in as {}#{}!",
@@ -554,6 +855,7 @@ async fn main() -> Result<(), anyhow::Error> {
 
                 Ok(Data {
                     shard_manager: Arc::clone(framework.shard_manager()),
+                    settings,
                 })
             })
         });
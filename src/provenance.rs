@@ -0,0 +1,361 @@
+//! Why each member was mentioned by a notification
+//!
+//! Members often don't know why a query mentioned them. Every notification has a button that shows
+//! whoever clicks it the parts of the query that matched them, which are remembered here for the
+//! most recent notifications. Members the query didn't match, but who were notified anyway through
+//! a role that was mentioned to save mentions, are told which role that was instead.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use poise::serenity_prelude::{self as serenity, MessageId, UserId};
+use tracing::debug;
+
+use crate::models::mention::RoleType;
+
+/// The custom ID of the button on notifications that asks why the clicking member was mentioned
+pub const WHY_MENTIONED_BUTTON: &str = "why_mentioned";
+
+/// The most notifications whose matches are remembered. The oldest are forgotten first.
+const MAX_NOTIFICATIONS: usize = 1000;
+
+/// The most members remembered across every notification, counting a member once for each part of
+/// a query that matched them. A single notification on a big server can mention tens of thousands
+/// of members, so the number of notifications alone doesn't bound how much is remembered.
+const MAX_ENTRIES: usize = 250_000;
+
+/// The members that each part of a query matched in a single notification
+#[derive(Debug, Default)]
+pub struct Matches {
+    /// Each quoted part of the query, in the order they appear, with the members it matched
+    parts: Vec<(String, HashSet<UserId>)>,
+    /// The members the query didn't match, with the mentioned roles that notified them anyway
+    extras: HashMap<UserId, Vec<RoleType>>,
+}
+
+/// Why a notification mentioned a member, see [`ProvenanceStore::get`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reason {
+    /// They matched these parts of the query
+    Matched(Vec<String>),
+    /// The query didn't match them, but they have these roles, which were mentioned to save
+    /// mentions
+    Extra(Vec<RoleType>),
+    /// They weren't mentioned at all
+    NotMentioned,
+}
+
+impl Matches {
+    /// Record that `part` of the query matched `members`. Parts that are quoted the same way are
+    /// remembered together.
+    pub fn insert(&mut self, part: &str, members: impl IntoIterator<Item = UserId>) {
+        if let Some((_, matched)) = self.parts.iter_mut().find(|(quoted, _)| quoted == part) {
+            matched.extend(members);
+        } else {
+            self.parts
+                .push((part.to_string(), members.into_iter().collect()));
+        }
+    }
+
+    /// Record that `member` wasn't matched by the query, but was notified through `role`
+    pub fn insert_extra(&mut self, member: UserId, role: RoleType) {
+        self.extras.entry(member).or_default().push(role);
+    }
+
+    /// Why `member` was mentioned
+    fn reason(&self, member: UserId) -> Reason {
+        let parts = self
+            .parts
+            .iter()
+            .filter(|(_, members)| members.contains(&member))
+            .map(|(part, _)| part.clone())
+            .collect::<Vec<_>>();
+        if !parts.is_empty() {
+            return Reason::Matched(parts);
+        }
+        self.extras
+            .get(&member)
+            .map_or(Reason::NotMentioned, |roles| Reason::Extra(roles.clone()))
+    }
+
+    /// How many entries these matches count for towards [`MAX_ENTRIES`]
+    fn entries(&self) -> usize {
+        self.parts
+            .iter()
+            .map(|(_, members)| members.len())
+            .chain(self.extras.values().map(Vec::len))
+            .sum()
+    }
+}
+
+/// The matches of the most recent notifications, oldest first
+#[derive(Debug, Default)]
+struct Notifications {
+    /// The messages of each notification in the order they were sent, along with how many entries
+    /// its matches count for
+    order: VecDeque<(Vec<MessageId>, usize)>,
+    /// The matches of each message, shared by every message of the same notification
+    matches: HashMap<MessageId, Arc<Matches>>,
+    /// How many entries every notification counts for together
+    entries: usize,
+}
+
+impl Notifications {
+    /// Remember the matches of a notification, forgetting the oldest notifications if there are
+    /// too many or they have too many entries. The newest notification is always remembered.
+    fn insert(&mut self, notification: MessageId, matches: Matches) {
+        let entries = matches.entries();
+        self.order.push_back((vec![notification], entries));
+        self.matches.insert(notification, Arc::new(matches));
+        self.entries += entries;
+        while self.order.len() > 1
+            && (self.order.len() > MAX_NOTIFICATIONS || self.entries > MAX_ENTRIES)
+        {
+            if let Some((messages, entries)) = self.order.pop_front() {
+                for message in messages {
+                    self.matches.remove(&message);
+                }
+                self.entries -= entries;
+            }
+        }
+    }
+
+    /// Give `message` the same matches as `notification`, if it is still remembered
+    fn link(&mut self, notification: MessageId, message: MessageId) {
+        let Some(matches) = self.matches.get(&notification).cloned() else {
+            return;
+        };
+        self.matches.insert(message, matches);
+        if let Some((messages, _)) = self
+            .order
+            .iter_mut()
+            .rev()
+            .find(|(messages, _)| messages.contains(&notification))
+        {
+            messages.push(message);
+        }
+    }
+}
+
+/// Where the [`Matches`] of recent notifications are kept
+#[derive(Debug, Default)]
+#[allow(clippy::module_name_repetitions)]
+pub struct ProvenanceStore {
+    /// The notifications being remembered
+    notifications: Mutex<Notifications>,
+}
+
+impl ProvenanceStore {
+    /// Remember the matches of a notification, forgetting the oldest notifications if there are
+    /// too many
+    pub fn insert(&self, notification: MessageId, matches: Matches) {
+        self.notifications
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(notification, matches);
+    }
+
+    /// Remember that `message` belongs to the same notification as `notification`, for
+    /// notifications whose mentions are split over several messages
+    pub fn link(&self, notification: MessageId, message: MessageId) {
+        self.notifications
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .link(notification, message);
+    }
+
+    /// Why a notification mentioned `member`. Returns [`None`] if the notification has been
+    /// forgotten.
+    pub fn get(&self, notification: MessageId, member: UserId) -> Option<Reason> {
+        self.notifications
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .matches
+            .get(&notification)
+            .map(|matches| matches.reason(member))
+    }
+
+    /// Tell the member who clicked the [`WHY_MENTIONED_BUTTON`] why they were mentioned, in a
+    /// message only they can see
+    pub async fn respond(
+        &self,
+        ctx: &serenity::Context,
+        interaction: &serenity::MessageComponentInteraction,
+    ) -> anyhow::Result<()> {
+        let content = match self.get(interaction.message.id, interaction.user.id) {
+            None => concat!(
+                "This notification was sent too long ago to remember why it mentioned anyone.",
+                " Try asking whoever sent the query."
+            )
+            .to_string(),
+            Some(Reason::NotMentioned) => "This notification didn't mention you.".to_string(),
+            Some(Reason::Extra(roles)) => format!(
+                concat!(
+                    "The query didn't match you, but you were notified through {}, which {}",
+                    " mentioned to save mentions, since {} reached almost everyone the query",
+                    " matched."
+                ),
+                roles
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                if roles.len() == 1 { "was" } else { "were" },
+                if roles.len() == 1 { "it" } else { "they" }
+            ),
+            Some(Reason::Matched(parts)) => format!(
+                "You were mentioned because you matched {} of the query:\n{}",
+                if parts.len() == 1 {
+                    "this part"
+                } else {
+                    "these parts"
+                },
+                parts
+                    .iter()
+                    .map(|part| format!("- `{part}`"))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            ),
+        };
+        debug!("Explaining why {} was mentioned", interaction.user.id);
+
+        interaction
+            .create_interaction_response(ctx, |response| {
+                response
+                    .kind(serenity::InteractionResponseType::ChannelMessageWithSource)
+                    .interaction_response_data(|data| data.content(content).ephemeral(true))
+            })
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use poise::serenity_prelude::RoleId;
+
+    use super::*;
+
+    /// Matches where a single part of the query matched `members`
+    fn matches(part: &str, members: impl IntoIterator<Item = u64>) -> Matches {
+        let mut matches = Matches::default();
+        matches.insert(part, members.into_iter().map(UserId));
+        matches
+    }
+
+    #[test]
+    fn remembers_matches_of_each_notification() {
+        let store = ProvenanceStore::default();
+        let mut mods = matches("mods", [2]);
+        mods.insert("admins", [UserId(3)]);
+        mods.insert("mods", [UserId(3)]);
+        store.insert(MessageId(1), mods);
+
+        assert_eq!(
+            store.get(MessageId(1), UserId(2)),
+            Some(Reason::Matched(vec!["mods".to_string()]))
+        );
+        assert_eq!(
+            store.get(MessageId(1), UserId(3)),
+            Some(Reason::Matched(vec![
+                "mods".to_string(),
+                "admins".to_string()
+            ]))
+        );
+        assert_eq!(
+            store.get(MessageId(1), UserId(4)),
+            Some(Reason::NotMentioned)
+        );
+        assert_eq!(store.get(MessageId(4), UserId(2)), None);
+    }
+
+    #[test]
+    fn remembers_roles_that_notified_extras() {
+        let store = ProvenanceStore::default();
+        let mut mods = matches("mods", [2]);
+        mods.insert_extra(UserId(3), RoleType::Role(RoleId(10)));
+        mods.insert_extra(UserId(3), RoleType::Here);
+        mods.insert_extra(UserId(2), RoleType::Here);
+        store.insert(MessageId(1), mods);
+
+        // Matching the query explains a mention better than being an extra
+        assert_eq!(
+            store.get(MessageId(1), UserId(2)),
+            Some(Reason::Matched(vec!["mods".to_string()]))
+        );
+        assert_eq!(
+            store.get(MessageId(1), UserId(3)),
+            Some(Reason::Extra(vec![
+                RoleType::Role(RoleId(10)),
+                RoleType::Here
+            ]))
+        );
+    }
+
+    #[test]
+    fn shares_matches_between_linked_messages() {
+        let store = ProvenanceStore::default();
+        store.insert(MessageId(1), matches("mods", [2]));
+        store.link(MessageId(1), MessageId(5));
+
+        assert_eq!(
+            store.get(MessageId(5), UserId(2)),
+            Some(Reason::Matched(vec!["mods".to_string()]))
+        );
+
+        for id in 2..=MAX_NOTIFICATIONS + 1 {
+            store.insert(
+                MessageId(id.try_into().expect("IDs should fit in a u64")),
+                Matches::default(),
+            );
+        }
+        assert_eq!(store.get(MessageId(1), UserId(2)), None);
+        assert_eq!(store.get(MessageId(5), UserId(2)), None);
+    }
+
+    #[test]
+    fn forgets_the_oldest_notifications() {
+        let store = ProvenanceStore::default();
+        for id in 0..=MAX_NOTIFICATIONS {
+            store.insert(
+                MessageId(id.try_into().expect("IDs should fit in a u64")),
+                Matches::default(),
+            );
+        }
+
+        assert_eq!(store.get(MessageId(0), UserId(1)), None);
+        assert_eq!(
+            store.get(MessageId(1), UserId(1)),
+            Some(Reason::NotMentioned)
+        );
+    }
+
+    #[test]
+    fn forgets_notifications_with_too_many_entries() {
+        let half = u64::try_from(MAX_ENTRIES / 2).expect("entries should fit in a u64");
+        let store = ProvenanceStore::default();
+        store.insert(MessageId(1), matches("a", 0..half));
+        store.insert(MessageId(2), matches("b", 0..half));
+        assert_eq!(
+            store.get(MessageId(1), UserId(0)),
+            Some(Reason::Matched(vec!["a".to_string()]))
+        );
+
+        store.insert(MessageId(3), matches("c", [0]));
+        assert_eq!(store.get(MessageId(1), UserId(0)), None);
+        assert_eq!(
+            store.get(MessageId(2), UserId(0)),
+            Some(Reason::Matched(vec!["b".to_string()]))
+        );
+
+        // Even a notification with too many entries on its own is remembered
+        store.insert(MessageId(4), matches("d", 0..=half * 2));
+        assert_eq!(
+            store.get(MessageId(4), UserId(0)),
+            Some(Reason::Matched(vec!["d".to_string()]))
+        );
+    }
+}
@@ -1,17 +1,88 @@
-//! The instance of the DRQL interpreter resolver used for Intersection
+//! The instances of the DRQL interpreter resolver used for Intersection
 
-use std::collections::HashSet;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{Debug, Formatter},
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
 
-use anyhow::{bail, Context as _};
+use anyhow::{anyhow, bail, Context as _};
 use poise::{async_trait, serenity_prelude as serenity};
 use tap::Tap;
 use tracing::{debug, error, instrument, trace};
 
 use crate::{
-    drql::interpreter::InterpreterResolver,
+    drql::{
+        ast::Expr,
+        binder::{BinderResolver, Bound},
+        interpreter::InterpreterResolver,
+    },
     extensions::{CustomGuildImpl, CustomMemberImpl, CustomRoleImpl},
+    settings::GuildSettings,
+    util,
 };
 
+/// The most names to suggest when a name can't be found
+const MAX_SUGGESTIONS: usize = 3;
+/// The most candidates the author can choose between for an ambiguous name, as Discord select
+/// menus can only have 25 options
+const MAX_DISAMBIGUATION_CANDIDATES: usize = 25;
+/// The most candidates shown in detail (with their avatars and roles) for an ambiguous name, as a
+/// message can only have 10 embeds
+const MAX_DISAMBIGUATION_EMBEDS: usize = 10;
+/// The longest a select menu option's label or description may be
+const MAX_SELECT_OPTION_LENGTH: usize = 100;
+/// How long the author has to choose what they meant by an ambiguous name
+const DISAMBIGUATION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Where questions for the author, like which member or role they meant, are asked
+#[derive(Clone, Copy)]
+pub enum PromptTarget<'a> {
+    /// In a reply to the message containing the query, which only its author may answer
+    Reply(&'a serenity::Message),
+    /// In an ephemeral follow-up to the slash command the query was given to, which only its
+    /// author can see
+    Command(crate::Context<'a>),
+}
+
+impl Debug for PromptTarget<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Reply(query) => f.debug_tuple("Reply").field(&query.id).finish(),
+            Self::Command(ctx) => f.debug_tuple("Command").field(&ctx.id()).finish(),
+        }
+    }
+}
+
+/// Which of the candidates for an ambiguous name the author chose in [`Resolver::disambiguate`]
+#[derive(Debug, Clone, Copy)]
+enum Choice {
+    /// The member at this index in the list of possible members
+    Member(usize),
+    /// The role at this index in the list of possible roles
+    Role(usize),
+}
+
+/// Describe a member that a leaf was resolved to
+fn describe_member(member: &serenity::Member) -> String {
+    format!("user {}", member.user.tag())
+}
+
+/// Describe a role that a leaf was resolved to
+fn describe_role(role: &serenity::Role) -> String {
+    format!("role @{}", role.name)
+}
+
+/// Shorten `text` to at most `max` characters, adding an ellipsis if anything was cut off
+fn truncate(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        text.to_string()
+    } else {
+        format!("{}...", text.chars().take(max - 3).collect::<String>())
+    }
+}
+
 /// The custom instance of the DRQL [`InterpreterResolver`] used for Intersection.
 pub struct Resolver<'a> {
     /// The guild that the query was originally sent in
@@ -22,37 +93,493 @@ pub struct Resolver<'a> {
     pub ctx: &'a serenity::Context,
     /// `THe` channel the query was originally sent in
     pub channel: &'a serenity::GuildChannel,
+    /// The settings of the guild the query was sent in
+    pub settings: &'a GuildSettings,
+    /// Where to ask the author questions about the query
+    pub prompt_target: PromptTarget<'a>,
+    /// Held while asking the author a question, since names are bound concurrently but the
+    /// author should only be asked one question at a time
+    pub prompt_lock: tokio::sync::Mutex<()>,
+    /// A description of the member or role each name has been resolved to, which can't be worked
+    /// out again afterwards if the author had to choose between several
+    pub targets: Mutex<HashMap<String, String>>,
 }
-#[async_trait]
-impl<'a> InterpreterResolver<anyhow::Error> for Resolver<'a> {
-    #[instrument(skip(self))]
-    async fn resolve_string_literal(
-        &mut self,
-        literal: String,
-    ) -> Result<HashSet<serenity::UserId>, anyhow::Error> {
-        if literal == "everyone" || literal == "here" {
-            if !self.member.permissions(self.ctx)?.mention_everyone() {
-                debug!("Member does not have permission to mention everyone or here, bailing!");
-                bail!(
-                    concat!(
-                        "You do not have the \"Mention everyone, here, and ",
-                        "All Roles\" permission required to use the role {}."
-                    ),
-                    literal
-                );
+
+impl Resolver<'_> {
+    /// Remember what `literal` was resolved to, for [`InterpreterResolver::describe_target`]
+    fn record_target(&self, literal: &str, bound: &Bound) {
+        let (Bound::User(_, target) | Bound::Role(_, target)) = bound;
+        self.targets
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(literal.to_string(), target.clone());
+    }
+
+    /// Check that the author may mention `@everyone` and `@here`, which `literal` needs
+    fn check_mention_everyone(&self, literal: &str) -> anyhow::Result<()> {
+        if !self.member.permissions(self.ctx)?.mention_everyone() {
+            debug!("Member does not have permission to mention everyone or here, bailing!");
+            bail!(
+                concat!(
+                    "You do not have the \"Mention everyone, here, and ",
+                    "All Roles\" permission required to use the role {}."
+                ),
+                literal
+            );
+        }
+        Ok(())
+    }
+
+    /// Check that the author may mention `role`, which every role a query mentions or expands to
+    /// its members needs
+    fn check_mention_role(&self, role: &serenity::Role) -> anyhow::Result<()> {
+        if !self.member.can_mention_role(self.ctx, role, self.channel)? {
+            debug!("User cannot mention role {}, bailing!", role.id.0);
+            bail!(
+                concat!(
+                    "The role {} is not mentionable and you do not have",
+                    " the \"Mention everyone, here, and All Roles\"",
+                    " permission."
+                ),
+                role.name
+            );
+        }
+        Ok(())
+    }
+
+    /// The `@everyone` role, whose ID is the guild's
+    fn everyone(&self) -> Bound {
+        Bound::Role(serenity::RoleId(self.guild.id.0), "@everyone".to_string())
+    }
+
+    /// The members of a role, treating the guild's ID as `@everyone`. The author must be allowed
+    /// to mention the role, even if it was never named, like the roles `members_of(...)` expands.
+    fn role_members(&self, id: serenity::RoleId) -> anyhow::Result<HashSet<serenity::UserId>> {
+        if id.to_string() == self.guild.id.to_string() {
+            debug!("Role ID is the guild's ID, treating it as everyone");
+            self.check_mention_everyone("everyone")?;
+            Ok(self.guild.get_everyone())
+        } else {
+            let role = self
+                .guild
+                .roles
+                .get(&id)
+                .context(format!("Unable to resolve role with ID {id}"))?;
+            self.check_mention_role(role)?;
+            Ok(role
+                .members(self.guild)
+                .tap(|x| debug!("Resolved role ID to {x:?}")))
+        }
+    }
+
+    /// The roles a member has, not including `@everyone`
+    async fn member_roles(
+        &self,
+        id: serenity::UserId,
+    ) -> anyhow::Result<HashSet<serenity::RoleId>> {
+        Ok(self
+            .guild
+            .member(self.ctx, id)
+            .await
+            .context(format!("Unable to resolve member with ID {id}"))?
+            .roles
+            .into_iter()
+            .collect::<HashSet<_>>()
+            .tap(|x| debug!("Resolved member ID to roles {x:?}")))
+    }
+
+    /// Resolve a member or role that a name or ID was bound to, to members
+    fn bound_members(&self, bound: &Bound) -> anyhow::Result<HashSet<serenity::UserId>> {
+        match *bound {
+            Bound::User(id, _) => Ok(HashSet::from([id])),
+            Bound::Role(id, _) => self.role_members(id),
+        }
+    }
+
+    /// Resolve a member or role that a name or ID was bound to, to roles
+    async fn bound_roles(&self, bound: Bound) -> anyhow::Result<HashSet<serenity::RoleId>> {
+        match bound {
+            Bound::User(id, _) => self.member_roles(id).await,
+            Bound::Role(id, _) => Ok(HashSet::from([id])),
+        }
+    }
+
+    /// Describe the member or role with an ID, treating the guild's ID as `@everyone`
+    fn describe_id(&self, id: u64) -> String {
+        if id == self.guild.id.0 {
+            return "@everyone".to_string();
+        }
+        self.guild.roles.get(&serenity::RoleId(id)).map_or_else(
+            || {
+                self.guild
+                    .members
+                    .get(&serenity::UserId(id))
+                    .map_or_else(|| format!("user {id}"), describe_member)
+            },
+            describe_role,
+        )
+    }
+
+    /// Every role name, nickname, and username in the guild, which names may be suggested from
+    fn names_in_guild(&self) -> impl Iterator<Item = &str> {
+        self.guild
+            .roles
+            .values()
+            .map(|role| role.name.as_str())
+            .chain(self.guild.members.values().flat_map(|member| {
+                member
+                    .nick
+                    .as_deref()
+                    .into_iter()
+                    .chain([member.user.name.as_str()])
+            }))
+    }
+
+    /// Ask the author which of several members and roles they meant by `literal`, using a select
+    /// menu. The prompt is deleted once they have chosen.
+    #[instrument(skip_all)]
+    async fn disambiguate(
+        &self,
+        literal: &str,
+        members: &[serenity::Member],
+        roles: &[(&serenity::RoleId, &serenity::Role)],
+    ) -> anyhow::Result<Choice> {
+        let _prompt = self.prompt_lock.lock().await;
+        let role_names = |member: &serenity::Member| {
+            member
+                .roles
+                .iter()
+                .filter_map(|id| self.guild.roles.get(id))
+                .map(|role| role.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        let mut embeds = Vec::new();
+        for member in members {
+            let mut embed = serenity::CreateEmbed::default();
+            embed
+                .title(format!("{} ({})", member.display_name(), member.user.tag()))
+                .thumbnail(member.face())
+                .description(format!(
+                    "Member with the roles: {}",
+                    member
+                        .roles
+                        .iter()
+                        .map(|id| format!("<@&{id}>"))
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                ));
+            embeds.push(embed);
+        }
+        for (_, role) in roles {
+            let mut embed = serenity::CreateEmbed::default();
+            embed
+                .title(format!("@{}", role.name))
+                .colour(role.colour)
+                .description(format!(
+                    "Role with {} members",
+                    role.members(self.guild).len()
+                ));
+            embeds.push(embed);
+        }
+        embeds.truncate(MAX_DISAMBIGUATION_EMBEDS);
+
+        let content = format!(
+            "**{} members and roles match \"{literal}\".** Which one did you mean?",
+            members.len() + roles.len()
+        );
+        let mut components = serenity::CreateComponents::default();
+        components.create_action_row(|action_row| {
+            action_row.create_select_menu(|menu| {
+                menu.custom_id("disambiguate_select")
+                    .placeholder(truncate(
+                        &format!("What did you mean by \"{literal}\"?"),
+                        MAX_SELECT_OPTION_LENGTH,
+                    ))
+                    .options(|options| {
+                        for (index, member) in members.iter().enumerate() {
+                            options.create_option(|option| {
+                                option
+                                    .label(truncate(
+                                        &format!(
+                                            "{} ({})",
+                                            member.display_name(),
+                                            member.user.tag()
+                                        ),
+                                        MAX_SELECT_OPTION_LENGTH,
+                                    ))
+                                    .value(format!("member:{index}"))
+                                    .description(truncate(
+                                        &format!("Member: {}", role_names(member)),
+                                        MAX_SELECT_OPTION_LENGTH,
+                                    ))
+                            });
+                        }
+                        for (index, (_, role)) in roles.iter().enumerate() {
+                            options.create_option(|option| {
+                                option
+                                    .label(truncate(
+                                        &format!("@{}", role.name),
+                                        MAX_SELECT_OPTION_LENGTH,
+                                    ))
+                                    .value(format!("role:{index}"))
+                                    .description("Role")
+                            });
+                        }
+                        options
+                    })
+            })
+        });
+
+        trace!("sending disambiguation prompt");
+
+        // The prompt goes away once it has been answered either way
+        let interaction = match self.prompt_target {
+            PromptTarget::Reply(query) => {
+                let prompt = self
+                    .channel
+                    .send_message(self.ctx, |msg_builder| {
+                        msg_builder
+                            .reference_message(query) // basically makes it a reply
+                            .content(content)
+                            .set_embeds(embeds)
+                            .set_components(components)
+                    })
+                    .await?;
+                let interaction = self.await_choice(&prompt).await;
+                prompt.delete(self.ctx).await?;
+                interaction?
+            }
+            PromptTarget::Command(ctx) => {
+                let handle = ctx
+                    .send(|reply| {
+                        reply.embeds = embeds;
+                        reply.components = Some(components);
+                        reply.content(content).ephemeral(true)
+                    })
+                    .await?;
+                let interaction = match handle.message().await {
+                    Ok(prompt) => self.await_choice(&prompt).await,
+                    Err(error) => Err(error.into()),
+                };
+                handle.delete(ctx).await?;
+                interaction?
             }
+        };
 
-            Ok(match literal.as_str() {
-                "everyone" => self.guild.get_everyone(),
-                "here" => self.guild.get_here(),
-                _ => unreachable!(),
+        let Some(interaction) = interaction else {
+            debug!("timed out waiting for the author to choose");
+            bail!("Timed out waiting for you to choose what you meant by \"{literal}\".");
+        };
+
+        let choice = match interaction
+            .data
+            .values
+            .first()
+            .and_then(|value| value.split_once(':'))
+        {
+            Some(("member", index)) => Choice::Member(index.parse()?),
+            Some(("role", index)) => Choice::Role(index.parse()?),
+            _ => bail!("Discord sent us an invalid select menu value!"),
+        };
+        debug!("Author chose {choice:?}");
+
+        Ok(choice)
+    }
+
+    /// Wait for the author to choose an option from the select menu on `prompt`, telling anyone
+    /// else who tries that only the author can answer it
+    async fn await_choice(
+        &self,
+        prompt: &serenity::Message,
+    ) -> anyhow::Result<Option<Arc<serenity::MessageComponentInteraction>>> {
+        trace!("waiting for the author to choose");
+
+        let deadline = Instant::now() + DISAMBIGUATION_TIMEOUT;
+        loop {
+            let Some(interaction) = prompt
+                .await_component_interaction(self.ctx)
+                .collect_limit(1)
+                .timeout(deadline.saturating_duration_since(Instant::now()))
+                .await
+            else {
+                return Ok(None);
+            };
+
+            if interaction.user.id == self.member.user.id {
+                interaction
+                    .create_interaction_response(self.ctx, |response| {
+                        response.kind(serenity::InteractionResponseType::DeferredUpdateMessage)
+                    })
+                    .await?;
+                return Ok(Some(interaction));
             }
-            .tap(|x| {
-                debug!(
-                    "Resolved everyone/here literal to {:?}",
-                    &x.iter().map(|x| x.0).collect::<Vec<_>>()
-                );
-            }))
+
+            debug!(
+                "{} tried to answer someone else's prompt",
+                interaction.user.id
+            );
+            interaction
+                .create_interaction_response(self.ctx, |response| {
+                    response
+                        .kind(serenity::InteractionResponseType::ChannelMessageWithSource)
+                        .interaction_response_data(|data| {
+                            data.ephemeral(true)
+                                .content("Only the author of the query can answer this.")
+                        })
+                })
+                .await?;
+        }
+    }
+
+    /// The error for a name that matches no role or member, suggesting similar names if there are
+    /// any
+    fn name_not_found(&self, literal: &str) -> anyhow::Error {
+        let suggestions =
+            util::fuzzy::closest_matches(literal, self.names_in_guild(), MAX_SUGGESTIONS);
+        debug!("Suggesting similar names: {suggestions:?}");
+
+        match suggestions.as_slice() {
+            [] => anyhow!(
+                concat!(
+                    "Unable to find a role or member with the name {}. Searches for roles",
+                    " are case sensitive! Try using the ID instead?"
+                ),
+                literal
+            ),
+            [suggestion] => anyhow!(
+                concat!(
+                    "Unable to find a role or member with the name {}. Did you mean",
+                    " `{}`? Searches for roles are case sensitive!"
+                ),
+                literal,
+                suggestion
+            ),
+            _ => anyhow!(
+                concat!(
+                    "Unable to find a role or member with the name {}. Did you mean one",
+                    " of {}? Searches for roles are case sensitive!"
+                ),
+                literal,
+                suggestions
+                    .iter()
+                    .map(|suggestion| format!("`{suggestion}`"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+
+    /// Find the members and roles whose names match `literal` when ignoring case
+    fn case_insensitive_matches(
+        &self,
+        literal: &str,
+    ) -> (
+        Vec<serenity::Member>,
+        Vec<(&serenity::RoleId, &serenity::Role)>,
+    ) {
+        let literal = literal.to_lowercase();
+        let members = self
+            .guild
+            .members
+            .values()
+            .filter(|member| {
+                member.user.name.to_lowercase() == literal
+                    || member
+                        .nick
+                        .as_ref()
+                        .is_some_and(|nick| nick.to_lowercase() == literal)
+            })
+            .cloned()
+            .collect();
+        let roles = self
+            .guild
+            .roles
+            .iter()
+            .filter(|(_, role)| role.name.to_lowercase() == literal)
+            .collect();
+        (members, roles)
+    }
+
+    /// Check that a name or ID belongs to a role or cached member of the guild, for
+    /// [`InterpreterResolver::validate_leaf`]. This can't check everything resolving a name does,
+    /// like whether the author may mention a role, as that would need HTTP requests.
+    #[instrument(skip(self))]
+    fn validate(&self, leaf: &Expr) -> Result<(), anyhow::Error> {
+        let is_guild_id = |id: u64| id == self.guild.id.0;
+
+        match leaf {
+            Expr::StringLiteral(literal, _) => {
+                let (members, roles) = self.case_insensitive_matches(literal);
+                let exact_match = literal == "everyone"
+                    || literal == "here"
+                    || roles.iter().any(|(_, role)| &role.name == literal)
+                    || members.iter().any(|member| {
+                        &member.user.name == literal || member.nick.as_ref() == Some(literal)
+                    });
+                let accepted_case_insensitive_match =
+                    self.settings.auto_accept_case_insensitive && members.len() + roles.len() == 1;
+
+                if !exact_match && !accepted_case_insensitive_match {
+                    return Err(self.name_not_found(literal));
+                }
+            }
+            Expr::UnknownID(id, _) => {
+                let id = id.parse::<u64>()?;
+                if !is_guild_id(id)
+                    && !self.guild.roles.contains_key(&serenity::RoleId(id))
+                    && !self.guild.members.contains_key(&serenity::UserId(id))
+                {
+                    bail!("Unable to resolve role or member ID: {}", id);
+                }
+            }
+            Expr::RoleID(id, _) => {
+                if !is_guild_id(id.0) && !self.guild.roles.contains_key(id) {
+                    bail!("Unable to resolve role with ID {id}");
+                }
+            }
+            Expr::UserID(..)
+            | Expr::Union(..)
+            | Expr::Intersection(..)
+            | Expr::Difference(..)
+            | Expr::RolesOf(..)
+            | Expr::MembersOf(..) => {}
+        }
+
+        Ok(())
+    }
+
+    /// Describe the member or role a leaf was resolved to, for
+    /// [`InterpreterResolver::describe_target`]
+    fn describe(&self, leaf: &Expr) -> Option<String> {
+        match leaf {
+            Expr::StringLiteral(literal, _) => self
+                .targets
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .get(literal)
+                .cloned(),
+            Expr::UnknownID(id, _) => id.parse().ok().map(|id| self.describe_id(id)),
+            Expr::UserID(id, _) => Some(self.describe_id(id.0)),
+            Expr::RoleID(id, _) => Some(self.describe_id(id.0)),
+            Expr::Union(..)
+            | Expr::Intersection(..)
+            | Expr::Difference(..)
+            | Expr::RolesOf(..)
+            | Expr::MembersOf(..) => None,
+        }
+    }
+}
+
+#[async_trait]
+impl BinderResolver<anyhow::Error> for Resolver<'_> {
+    #[instrument(skip(self))]
+    async fn bind_string_literal(&self, literal: String) -> Result<Option<Bound>, anyhow::Error> {
+        if literal == "everyone" || literal == "here" {
+            self.check_mention_everyone(&literal)?;
+            // Who is online changes all the time, so `here` is left for the interpreter
+            Ok((literal == "everyone").then(|| self.everyone()))
         } else {
             trace!("Finding possible members/roles for string literal");
 
@@ -68,6 +595,23 @@ impl<'a> InterpreterResolver<anyhow::Error> for Resolver<'a> {
                 .filter(|(_, role)| role.name == literal)
                 .collect::<Vec<_>>();
 
+            // If nothing matched exactly, the guild may have opted in to accepting a unique
+            // case-insensitive match instead
+            let (possible_members, possible_roles) = if possible_members.is_empty()
+                && possible_roles.is_empty()
+                && self.settings.auto_accept_case_insensitive
+            {
+                let (members, roles) = self.case_insensitive_matches(&literal);
+                if members.len() + roles.len() == 1 {
+                    debug!("Accepting the only case-insensitive match");
+                    (members, roles)
+                } else {
+                    (possible_members, possible_roles)
+                }
+            } else {
+                (possible_members, possible_roles)
+            };
+
             debug!(
                 "Found possible members: {:?}",
                 possible_members
@@ -83,6 +627,35 @@ impl<'a> InterpreterResolver<anyhow::Error> for Resolver<'a> {
                     .collect::<Vec<_>>()
             );
 
+            // If there are a few candidates, ask the author which one they meant. If there are
+            // too many to list, they'll have to narrow their query instead.
+            let candidates = possible_members.len() + possible_roles.len();
+            let (possible_members, possible_roles) = if candidates > 1
+                && candidates <= MAX_DISAMBIGUATION_CANDIDATES
+            {
+                debug!("Found multiple members/roles that matched the query, asking the author");
+                match self
+                    .disambiguate(&literal, &possible_members, &possible_roles)
+                    .await?
+                {
+                    Choice::Member(index) => (
+                        vec![possible_members
+                            .get(index)
+                            .cloned()
+                            .ok_or_else(|| anyhow!("Invalid member choice {index}"))?],
+                        Vec::new(),
+                    ),
+                    Choice::Role(index) => (
+                        Vec::new(),
+                        vec![*possible_roles
+                            .get(index)
+                            .ok_or_else(|| anyhow!("Invalid role choice {index}"))?],
+                    ),
+                }
+            } else {
+                (possible_members, possible_roles)
+            };
+
             match (possible_members.len(), possible_roles.len()) {
                 (members_matched, roles_matched) if members_matched >= 1 && roles_matched >= 1 => {
                     debug!("Found both members and roles that matched the query, bailing!");
@@ -124,13 +697,7 @@ impl<'a> InterpreterResolver<anyhow::Error> for Resolver<'a> {
                 // only ONE of them is 1. Let's make sure that they aren't both 0:
                 (members_matched, roles_matched) if members_matched == 0 && roles_matched == 0 => {
                     debug!("Found no members or roles that matched the query, bailing!");
-                    bail!(
-                        concat!(
-                            "Unable to find a role or member with the name {}. Searches for roles",
-                            " are case sensitive! Try using the ID instead?"
-                        ),
-                        literal
-                    );
+                    return Err(self.name_not_found(&literal));
                 }
                 // Continue, members_matched + roles_matched == 1.
                 _ => {}
@@ -146,29 +713,13 @@ impl<'a> InterpreterResolver<anyhow::Error> for Resolver<'a> {
             match (member, role) {
                 (Some(member), None) => {
                     debug!("Chose to use member {}", member.user.id.0);
-                    self.resolve_user_id(member.user.id).await
-                }
-
-                (None, Some(role))
-                    if !self.member.can_mention_role(self.ctx, role, self.channel)? =>
-                {
-                    debug!(
-                        "Chose to use role {}, but user cannot mention it!",
-                        role.id.0
-                    );
-                    bail!(
-                        concat!(
-                            "The role {} is not mentionable and you do not have",
-                            " the \"Mention everyone, here, and All",
-                            " Roles\" permission."
-                        ),
-                        role.name
-                    );
+                    Ok(Some(Bound::User(member.user.id, describe_member(member))))
                 }
 
                 (None, Some(role)) => {
+                    self.check_mention_role(role)?;
                     debug!("Chose to use role {}", role.id.0);
-                    self.resolve_role_id(role.id).await
+                    Ok(Some(Bound::Role(role.id, describe_role(role))))
                 }
 
                 // All other cases have been eliminated above.
@@ -178,13 +729,11 @@ impl<'a> InterpreterResolver<anyhow::Error> for Resolver<'a> {
     }
 
     #[instrument(skip(self))]
-    async fn resolve_unknown_id(
-        &mut self,
-        id: String,
-    ) -> Result<HashSet<serenity::UserId>, anyhow::Error> {
+    async fn bind_unknown_id(&self, id: String) -> Result<Bound, anyhow::Error> {
         if id == self.guild.id.to_string() {
             debug!("Unknown ID is the guild's ID, treating it as everyone");
-            self.resolve_string_literal("everyone".to_string()).await
+            self.check_mention_everyone("everyone")?;
+            Ok(self.everyone())
         } else {
             let id = id.parse::<u64>()?;
             debug!("Finding possible member/role for unknown ID");
@@ -207,26 +756,13 @@ impl<'a> InterpreterResolver<anyhow::Error> for Resolver<'a> {
 
                 (Ok(member), None) => {
                     debug!("Treating ID as a user ID.");
-                    self.resolve_user_id(member.user.id).await
-                }
-
-                (Err(_), Some(role))
-                    if !self.member.can_mention_role(self.ctx, role, self.channel)? =>
-                {
-                    debug!("Treating ID as a role ID, but user cannot mention role! Bailing.");
-                    bail!(
-                        concat!(
-                            "The role {} is not mentionable and you do not have",
-                            " the \"Mention everyone, here, and All Roles\"",
-                            " permission."
-                        ),
-                        role.name
-                    )
+                    Ok(Bound::User(member.user.id, describe_member(&member)))
                 }
 
                 (Err(_), Some(role)) => {
+                    self.check_mention_role(role)?;
                     debug!("Treating ID as a role ID.");
-                    self.resolve_role_id(role.id).await
+                    Ok(Bound::Role(role.id, describe_role(role)))
                 }
 
                 (Err(_), None) => {
@@ -236,10 +772,46 @@ impl<'a> InterpreterResolver<anyhow::Error> for Resolver<'a> {
             }
         }
     }
+}
+
+#[async_trait]
+impl InterpreterResolver<anyhow::Error> for Resolver<'_> {
+    #[instrument(skip(self))]
+    async fn resolve_string_literal(
+        &self,
+        literal: String,
+    ) -> Result<HashSet<serenity::UserId>, anyhow::Error> {
+        self.bind_string_literal(literal.clone())
+            .await?
+            .map_or_else(
+                // Only `here` is left unbound
+                || {
+                    Ok(self.guild.get_here().tap(|x| {
+                        debug!(
+                            "Resolved here literal to {:?}",
+                            &x.iter().map(|x| x.0).collect::<Vec<_>>()
+                        );
+                    }))
+                },
+                |bound| {
+                    self.record_target(&literal, &bound);
+                    self.bound_members(&bound)
+                },
+            )
+    }
+
+    #[instrument(skip(self))]
+    async fn resolve_unknown_id(
+        &self,
+        id: String,
+    ) -> Result<HashSet<serenity::UserId>, anyhow::Error> {
+        let bound = self.bind_unknown_id(id).await?;
+        self.bound_members(&bound)
+    }
 
     #[instrument(skip(self))]
     async fn resolve_user_id(
-        &mut self,
+        &self,
         id: serenity::UserId,
     ) -> Result<HashSet<serenity::UserId>, anyhow::Error> {
         debug!("Resolving User ID to itself: {}", id);
@@ -248,20 +820,113 @@ impl<'a> InterpreterResolver<anyhow::Error> for Resolver<'a> {
 
     #[instrument(skip(self))]
     async fn resolve_role_id(
-        &mut self,
+        &self,
         id: serenity::RoleId,
     ) -> Result<HashSet<serenity::UserId>, anyhow::Error> {
-        if id.to_string() == self.guild.id.to_string() {
-            debug!("Role ID is the guild's ID, treating it as everyone");
-            self.resolve_string_literal("everyone".to_string()).await
-        } else {
-            Ok(self
+        self.role_members(id)
+    }
+
+    fn validate_leaf(&self, leaf: &Expr) -> Result<(), anyhow::Error> {
+        self.validate(leaf)
+    }
+
+    fn describe_target(&self, leaf: &Expr) -> Option<String> {
+        self.describe(leaf)
+    }
+
+    fn known_elements(&self) -> Vec<serenity::UserId> {
+        self.guild.members.keys().copied().collect()
+    }
+
+    fn members_resolver(
+        &self,
+    ) -> Option<&(dyn InterpreterResolver<anyhow::Error, serenity::UserId> + Sync)> {
+        Some(self)
+    }
+
+    fn roles_resolver(
+        &self,
+    ) -> Option<&(dyn InterpreterResolver<anyhow::Error, serenity::RoleId> + Sync)> {
+        Some(self)
+    }
+}
+
+/// Resolves leaves to roles rather than members, for queries over roles: a role resolves to itself,
+/// a member to the roles they have, and `here` to the roles of every member who is online. No one
+/// is mentioned by these queries.
+#[async_trait]
+impl InterpreterResolver<anyhow::Error, serenity::RoleId> for Resolver<'_> {
+    #[instrument(skip(self))]
+    async fn resolve_string_literal(
+        &self,
+        literal: String,
+    ) -> Result<HashSet<serenity::RoleId>, anyhow::Error> {
+        match self.bind_string_literal(literal.clone()).await? {
+            Some(bound) => {
+                self.record_target(&literal, &bound);
+                self.bound_roles(bound).await
+            }
+            // Only `here` is left unbound
+            None => Ok(self
                 .guild
-                .roles
-                .get(&id)
-                .context(format!("Unable to resolve role with ID {id}"))?
-                .members(self.guild)
-                .tap(|x| debug!("Resolved role ID to {x:?}")))
+                .get_here()
+                .iter()
+                .filter_map(|id| self.guild.members.get(id))
+                .flat_map(|member| member.roles.iter().copied())
+                .collect()),
         }
     }
+
+    #[instrument(skip(self))]
+    async fn resolve_unknown_id(
+        &self,
+        id: String,
+    ) -> Result<HashSet<serenity::RoleId>, anyhow::Error> {
+        let bound = self.bind_unknown_id(id).await?;
+        self.bound_roles(bound).await
+    }
+
+    #[instrument(skip(self))]
+    async fn resolve_user_id(
+        &self,
+        id: serenity::UserId,
+    ) -> Result<HashSet<serenity::RoleId>, anyhow::Error> {
+        self.member_roles(id).await
+    }
+
+    #[instrument(skip(self))]
+    async fn resolve_role_id(
+        &self,
+        id: serenity::RoleId,
+    ) -> Result<HashSet<serenity::RoleId>, anyhow::Error> {
+        if id.0 != self.guild.id.0 && !self.guild.roles.contains_key(&id) {
+            bail!("Unable to resolve role with ID {id}");
+        }
+        debug!("Resolving role ID to itself: {}", id);
+        Ok(HashSet::from([id]))
+    }
+
+    fn validate_leaf(&self, leaf: &Expr) -> Result<(), anyhow::Error> {
+        self.validate(leaf)
+    }
+
+    fn describe_target(&self, leaf: &Expr) -> Option<String> {
+        self.describe(leaf)
+    }
+
+    fn known_elements(&self) -> Vec<serenity::RoleId> {
+        self.guild.roles.keys().copied().collect()
+    }
+
+    fn members_resolver(
+        &self,
+    ) -> Option<&(dyn InterpreterResolver<anyhow::Error, serenity::UserId> + Sync)> {
+        Some(self)
+    }
+
+    fn roles_resolver(
+        &self,
+    ) -> Option<&(dyn InterpreterResolver<anyhow::Error, serenity::RoleId> + Sync)> {
+        Some(self)
+    }
 }
@@ -0,0 +1,154 @@
+//! Per-guild settings
+//!
+//! Settings are changed by server managers with the `/settings` command and are persisted to a
+//! JSON file, whose path can be set with the `GUILD_SETTINGS_PATH` environment variable.
+
+use std::{
+    collections::HashMap,
+    fs,
+    io::ErrorKind,
+    path::PathBuf,
+    sync::{PoisonError, RwLock},
+};
+
+use anyhow::Context as _;
+use poise::serenity_prelude::GuildId;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info};
+
+use crate::{
+    drql::limits::QueryLimits,
+    util::unionize_set::{Overshoot, SolverBudget},
+};
+
+/// The settings for a single guild
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+#[allow(clippy::module_name_repetitions)]
+pub struct GuildSettings {
+    /// When a name matches nothing exactly, use the role or member whose name matches it
+    /// case-insensitively, as long as there is only one.
+    pub auto_accept_case_insensitive: bool,
+    /// Include warnings about suspicious queries, like ones that can never match anyone, when
+    /// replying to a query.
+    pub show_lint_warnings: bool,
+    /// Check names and IDs in the parts of a query that are skipped because they can't change the
+    /// result, so that typos in them are still reported.
+    pub validate_skipped_branches: bool,
+    /// How complex queries may be
+    pub limits: QueryLimits,
+    /// How long to search for the fewest mentions that notify everyone a query matches
+    pub solver_budget: SolverBudget,
+    /// How many people a query doesn't match may be notified, when that saves mentions
+    pub overshoot: Overshoot,
+}
+
+/// Where every guild's [`GuildSettings`] are kept
+#[derive(Debug)]
+#[allow(clippy::module_name_repetitions)]
+pub struct SettingsStore {
+    /// The file settings are persisted to
+    path: PathBuf,
+    /// The settings of every guild that has changed them from the defaults
+    guilds: RwLock<HashMap<GuildId, GuildSettings>>,
+    /// Held while an update is being saved, so that concurrent updates are saved in order without
+    /// keeping anyone from reading settings in the meantime
+    saving: tokio::sync::Mutex<()>,
+}
+
+impl SettingsStore {
+    /// Load the settings persisted at `path`. If the file does not exist yet, every guild starts
+    /// with the default settings.
+    pub fn load(path: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let path = path.into();
+        let guilds = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .context(format!("Invalid guild settings file {}", path.display()))?,
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                info!(
+                    "No guild settings file at {}, using defaults",
+                    path.display()
+                );
+                HashMap::new()
+            }
+            Err(err) => {
+                return Err(err).context(format!(
+                    "Unable to read guild settings file {}",
+                    path.display()
+                ))
+            }
+        };
+
+        Ok(Self {
+            path,
+            guilds: RwLock::new(guilds),
+            saving: tokio::sync::Mutex::default(),
+        })
+    }
+
+    /// Get the current settings for a guild
+    pub fn get(&self, guild: GuildId) -> GuildSettings {
+        self.guilds
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&guild)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Change the settings for a guild, persisting them to disk and returning the new settings
+    pub async fn update(
+        &self,
+        guild: GuildId,
+        change: impl FnOnce(&mut GuildSettings) + Send,
+    ) -> anyhow::Result<GuildSettings> {
+        let _saving = self.saving.lock().await;
+
+        let (settings, contents) = {
+            let mut guilds = self.guilds.write().unwrap_or_else(PoisonError::into_inner);
+            let settings = guilds.entry(guild).or_default();
+            change(settings);
+            (settings.clone(), serde_json::to_string_pretty(&*guilds)?)
+        };
+
+        debug!("Saving guild settings for {guild}: {settings:?}");
+        tokio::fs::write(&self.path, contents)
+            .await
+            .context(format!(
+                "Unable to save guild settings file {}",
+                self.path.display()
+            ))?;
+
+        Ok(settings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn settings_round_trip_through_disk() {
+        let path = env::temp_dir().join(format!(
+            "intersection-settings-test-{}.json",
+            std::process::id()
+        ));
+        let store = SettingsStore::load(&path).expect("a missing file should load");
+        assert_eq!(store.get(GuildId(1)), GuildSettings::default());
+
+        store
+            .update(GuildId(1), |settings| {
+                settings.auto_accept_case_insensitive = true;
+            })
+            .await
+            .expect("saving should succeed");
+
+        let reloaded = SettingsStore::load(&path).expect("the saved file should load");
+        assert!(reloaded.get(GuildId(1)).auto_accept_case_insensitive);
+        assert_eq!(reloaded.get(GuildId(2)), GuildSettings::default());
+
+        fs::remove_file(path).expect("cleaning up should succeed");
+    }
+}
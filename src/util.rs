@@ -2,9 +2,12 @@
 
 #![allow(clippy::missing_docs_in_private_items)] // because we don't expect all of these small modules to have docs
 
+pub mod fuzzy;
 mod mention_application_command;
+mod split_mentions;
 pub mod unionize_set;
 mod wrap_string_vec;
 
 pub use mention_application_command::mention_application_command;
+pub use split_mentions::split_mentions;
 pub use wrap_string_vec::wrap_string_vec;
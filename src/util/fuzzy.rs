@@ -0,0 +1,100 @@
+/// Compute the Levenshtein edit distance between two strings, counted in characters.
+pub fn edit_distance(lhs: &str, rhs: &str) -> usize {
+    let rhs = rhs.chars().collect::<Vec<_>>();
+
+    // Only the previous row of the usual dynamic programming table is needed at any time
+    let mut previous_row = (0..=rhs.len()).collect::<Vec<_>>();
+    for (i, lhs_char) in lhs.chars().enumerate() {
+        let mut current_row = vec![i + 1; rhs.len() + 1];
+        for (j, rhs_char) in rhs.iter().enumerate() {
+            let substitution_cost = usize::from(lhs_char != *rhs_char);
+            current_row[j + 1] = (previous_row[j] + substitution_cost)
+                .min(previous_row[j + 1] + 1)
+                .min(current_row[j] + 1);
+        }
+        previous_row = current_row;
+    }
+
+    previous_row[rhs.len()]
+}
+
+/// Find up to `limit` candidates that are closest to `query`, closest first.
+///
+/// Comparisons ignore case, and only candidates within a small edit distance of the query (about a
+/// third of its length) are returned, so unrelated names are never suggested.
+pub fn closest_matches<'a>(
+    query: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+    limit: usize,
+) -> Vec<&'a str> {
+    let folded_query = query.to_lowercase();
+    let max_distance = (folded_query.chars().count() / 3).max(1);
+
+    let mut matches = candidates
+        .into_iter()
+        .map(|candidate| {
+            (
+                edit_distance(&folded_query, &candidate.to_lowercase()),
+                candidate,
+            )
+        })
+        .filter(|(distance, _)| *distance <= max_distance)
+        .collect::<Vec<_>>();
+    matches.sort_unstable();
+    matches.dedup_by_key(|(_, candidate)| *candidate);
+
+    matches
+        .into_iter()
+        .take(limit)
+        .map(|(_, candidate)| candidate)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edit_distance_works() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("abc", ""), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("flaw", "lawn"), 2);
+        assert_eq!(edit_distance("Moderators", "Moderators"), 0);
+        // Characters, not bytes
+        assert_eq!(edit_distance("caf\u{e9}", "cafe"), 1);
+    }
+
+    #[test]
+    fn closest_matches_ignores_case_and_sorts_by_distance() {
+        assert_eq!(
+            closest_matches(
+                "moderater",
+                ["Moderators", "Moderator", "Admins", "moderator"],
+                3
+            ),
+            vec!["Moderator", "moderator", "Moderators"]
+        );
+    }
+
+    #[test]
+    fn closest_matches_skips_distant_candidates() {
+        assert_eq!(
+            closest_matches("staff", ["Moderators", "Admins", "everyone"], 3),
+            Vec::<&str>::new()
+        );
+    }
+
+    #[test]
+    fn closest_matches_deduplicates_and_limits() {
+        assert_eq!(
+            closest_matches(
+                "alice",
+                ["alice_", "Alice", "alice_", "alicia", "malice"],
+                2
+            ),
+            vec!["Alice", "alice_"]
+        );
+    }
+}
@@ -0,0 +1,274 @@
+use std::collections::HashSet;
+
+use anyhow::bail;
+use poise::serenity_prelude::UserId;
+use tracing::debug;
+
+use super::wrap_string_vec;
+use crate::models::mention::{Mention, RoleType};
+
+/// Mentions that have to be sent in the same message, along with how long they are when joined
+struct Group {
+    /// The mentions, rendered
+    mentions: Vec<String>,
+    /// The length of the mentions joined with spaces
+    len: usize,
+}
+
+impl Group {
+    /// Group rendered mentions together
+    fn new(mentions: Vec<String>) -> Self {
+        let len =
+            mentions.iter().map(String::len).sum::<usize>() + mentions.len().saturating_sub(1);
+        Self { mentions, len }
+    }
+}
+
+/// Split the `remaining` roles into groups of roles whose members overlap, directly or through
+/// other roles. Roles and groups are indices into `roles`.
+fn overlapping_groups(
+    roles: &[(RoleType, &HashSet<UserId>)],
+    remaining: &[usize],
+) -> Vec<Vec<usize>> {
+    let mut unvisited = remaining.to_vec();
+    let mut groups = Vec::new();
+    while let Some(first) = unvisited.pop() {
+        let mut group = vec![first];
+        let mut next = 0;
+        while let Some(&role) = group.get(next) {
+            let (overlapping, rest) = unvisited
+                .into_iter()
+                .partition::<Vec<_>, _>(|&other| !roles[role].1.is_disjoint(roles[other].1));
+            group.extend(overlapping);
+            unvisited = rest;
+            next += 1;
+        }
+        groups.push(group);
+    }
+    groups
+}
+
+/// Join mentions of `roles` and `outliers` into messages of at most `size` bytes, such that no
+/// member is notified by mentions in more than one message.
+///
+/// `roles` are the roles to mention along with their members, and `outliers` are members to mention
+/// directly, who shouldn't be in any of those roles. Roles that share members are always sent in
+/// the same message. If roles that share members don't fit in a single message together, some of
+/// them are replaced by mentions of their members who aren't in the other roles.
+pub fn split_mentions(
+    roles: &[(RoleType, &HashSet<UserId>)],
+    outliers: &[UserId],
+    size: usize,
+) -> anyhow::Result<Vec<String>> {
+    let mut remaining = (0..roles.len()).collect::<Vec<_>>();
+    let mut users = outliers.to_vec();
+
+    let role_groups = loop {
+        let groups = overlapping_groups(roles, &remaining)
+            .into_iter()
+            .map(|group| {
+                let rendered = Group::new(
+                    group
+                        .iter()
+                        .map(|&role| Mention::Role(roles[role].0).to_string())
+                        .collect(),
+                );
+                (group, rendered)
+            })
+            .collect::<Vec<_>>();
+
+        let Some((group, _)) = groups.iter().find(|(_, rendered)| rendered.len > size) else {
+            break groups;
+        };
+        if group.len() == 1 {
+            bail!(
+                "Mention of role {:?} too large for size {size}",
+                roles[group[0]].0
+            );
+        }
+
+        // Mentioning the smallest role's members directly adds the fewest mentions
+        let replaced = *group
+            .iter()
+            .min_by_key(|&&role| roles[role].1.len())
+            .expect("groups shouldn't be empty");
+        let others = group
+            .iter()
+            .filter(|&&role| role != replaced)
+            .flat_map(|&role| roles[role].1)
+            .collect::<HashSet<_>>();
+        debug!(
+            "Overlapping roles don't fit in one message, mentioning the members of {:?} instead",
+            roles[replaced].0
+        );
+        users.extend(
+            roles[replaced]
+                .1
+                .iter()
+                .filter(|member| !others.contains(member)),
+        );
+        remaining.retain(|&role| role != replaced);
+    };
+
+    // Each group is joined into one chunk, so that wrapping never breaks a group apart
+    let chunks = role_groups
+        .into_iter()
+        .map(|(_, rendered)| rendered.mentions.join(" "))
+        .chain(users.into_iter().map(|id| Mention::User(id).to_string()))
+        .collect::<Vec<_>>();
+    wrap_string_vec(&chunks, " ", size)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use poise::serenity_prelude::RoleId;
+    use rand::{seq::IteratorRandom, Rng};
+
+    use super::*;
+
+    /// The members each message notifies
+    fn notified(
+        messages: &[String],
+        roles: &[(RoleType, &HashSet<UserId>)],
+    ) -> Vec<HashSet<UserId>> {
+        let members = roles
+            .iter()
+            .map(|&(role, members)| (Mention::Role(role).to_string(), members))
+            .collect::<HashMap<_, _>>();
+
+        messages
+            .iter()
+            .map(|message| {
+                message
+                    .split(' ')
+                    .flat_map(|mention| {
+                        members.get(mention).map_or_else(
+                            || {
+                                let id = mention
+                                    .strip_prefix("<@")
+                                    .and_then(|id| id.strip_suffix('>'))
+                                    .and_then(|id| id.parse().ok())
+                                    .expect("a mention should be a role or member");
+                                vec![UserId(id)]
+                            },
+                            |members| members.iter().copied().collect(),
+                        )
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Check that the messages fit, notify exactly `expected`, and that no member is notified on
+    /// both sides of any boundary between messages
+    fn check_boundaries(
+        messages: &[String],
+        roles: &[(RoleType, &HashSet<UserId>)],
+        expected: &HashSet<UserId>,
+        size: usize,
+    ) {
+        assert!(messages.iter().all(|message| message.len() <= size));
+
+        let notified = notified(messages, roles);
+        for boundary in 1..messages.len() {
+            let before = notified[..boundary]
+                .iter()
+                .flatten()
+                .collect::<HashSet<_>>();
+            let after = notified[boundary..]
+                .iter()
+                .flatten()
+                .collect::<HashSet<_>>();
+            assert!(
+                before.is_disjoint(&after),
+                "no one should be notified before and after message {boundary} of {messages:?}"
+            );
+        }
+        assert_eq!(
+            &notified.into_iter().flatten().collect::<HashSet<_>>(),
+            expected
+        );
+    }
+
+    fn members(ids: impl IntoIterator<Item = u64>) -> HashSet<UserId> {
+        ids.into_iter().map(UserId).collect()
+    }
+
+    #[test]
+    fn overlapping_roles_share_a_message() {
+        let (first, second, third) = (members([1, 2]), members([2, 3]), members([4, 5]));
+        let roles = [
+            (RoleType::Role(RoleId(10)), &first),
+            (RoleType::Role(RoleId(11)), &third),
+            (RoleType::Role(RoleId(12)), &second),
+        ];
+
+        // Each message fits only two role mentions
+        let messages = split_mentions(&roles, &[UserId(6)], 17).expect("splitting should succeed");
+        assert_eq!(messages.len(), 2);
+        assert!(
+            messages.contains(&"<@&10> <@&12>".to_string())
+                || messages.contains(&"<@&12> <@&10>".to_string())
+        );
+        check_boundaries(&messages, &roles, &members(1..=6), 17);
+    }
+
+    #[test]
+    fn roles_that_cant_share_a_message_are_replaced_by_their_members() {
+        let (first, second) = (members([1, 2, 3]), members([3, 4]));
+        let roles = [
+            (RoleType::Role(RoleId(10)), &first),
+            (RoleType::Role(RoleId(11)), &second),
+        ];
+
+        // Only one role mention fits in a message
+        let messages = split_mentions(&roles, &[], 8).expect("splitting should succeed");
+        assert!(messages.contains(&"<@&10>".to_string()));
+        assert!(messages.contains(&"<@4>".to_string()));
+        check_boundaries(&messages, &roles, &members(1..=4), 8);
+    }
+
+    #[test]
+    fn mentions_that_never_fit_are_rejected() {
+        let everyone = members([1]);
+        assert!(split_mentions(&[(RoleType::Everyone, &everyone)], &[], 5).is_err());
+        assert!(split_mentions(&[], &[UserId(123)], 5).is_err());
+    }
+
+    #[test]
+    fn no_one_is_notified_across_any_boundary() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..200 {
+            let sets = (0..rng.gen_range(0..8))
+                .map(|_| {
+                    let amount = rng.gen_range(1..6);
+                    (1..30).choose_multiple(&mut rng, amount)
+                })
+                .map(members)
+                .collect::<Vec<_>>();
+            let roles = sets
+                .iter()
+                .enumerate()
+                .map(|(index, set)| {
+                    (
+                        RoleType::Role(RoleId(100 + u64::try_from(index).expect("fits"))),
+                        set,
+                    )
+                })
+                .collect::<Vec<_>>();
+            let covered = sets.iter().flatten().copied().collect::<HashSet<_>>();
+            let outliers = (30..40)
+                .filter(|_| rng.gen_bool(0.3))
+                .map(UserId)
+                .collect::<Vec<_>>();
+            let size = rng.gen_range(8..40);
+
+            let messages =
+                split_mentions(&roles, &outliers, size).expect("splitting should succeed");
+            let expected = covered.into_iter().chain(outliers).collect();
+            check_boundaries(&messages, &roles, &expected, size);
+        }
+    }
+}
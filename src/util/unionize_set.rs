@@ -7,6 +7,14 @@ use std::{
 use bitvec::prelude::*;
 use tracing::{debug, instrument, trace, warn};
 
+mod cost;
+mod exact;
+mod overshoot;
+
+pub use cost::{CoverCost, MentionCount, RenderedLength};
+pub use exact::{unionize_set_exact_blocking, SolverBudget};
+pub use overshoot::Overshoot;
+
 /// Results from [`unionize_set`].
 #[derive(Debug, PartialEq, Eq)]
 #[allow(clippy::module_name_repetitions)]
@@ -19,6 +27,41 @@ where
     pub sets: HashSet<&'a Key>,
     /// Those values not included when you calculate the union of all sets in `sets` versus `target`
     pub outliers: HashSet<&'a Value>,
+    /// Those values in the union of all sets in `sets` that aren't in `target`, which is always
+    /// empty unless an [`Overshoot`] was allowed
+    pub extras: HashSet<&'a Value>,
+}
+
+impl<Key, Value> UnionizeSetResult<'_, Key, Value>
+where
+    Key: PartialEq + Eq + Hash + Copy,
+    Value: PartialEq + Eq + Hash + Copy,
+{
+    /// Copy the keys and values out of this result, so that it no longer borrows the sets it was
+    /// found in
+    pub fn into_owned(self) -> OwnedUnionizeSetResult<Key, Value> {
+        OwnedUnionizeSetResult {
+            sets: self.sets.into_iter().copied().collect(),
+            outliers: self.outliers.into_iter().copied().collect(),
+            extras: self.extras.into_iter().copied().collect(),
+        }
+    }
+}
+
+/// A [`UnionizeSetResult`] that owns its keys and values, see [`UnionizeSetResult::into_owned`]
+#[derive(Debug, PartialEq, Eq)]
+#[allow(clippy::module_name_repetitions)]
+pub struct OwnedUnionizeSetResult<Key, Value>
+where
+    Key: PartialEq + Eq + Hash,
+    Value: PartialEq + Eq + Hash,
+{
+    /// The keys within `preexisting_sets` that were used to create the output set
+    pub sets: HashSet<Key>,
+    /// Those values not included when you calculate the union of all sets in `sets` versus `target`
+    pub outliers: HashSet<Value>,
+    /// Those values in the union of all sets in `sets` that aren't in `target`
+    pub extras: HashSet<Value>,
 }
 
 /// Represent a set as the union of many other pre-existing sets
@@ -31,6 +74,10 @@ where
 ///
 /// This function takes a [`HashMap`] of keys to pre-existing sets and returns keys from that `HashMap`.
 ///
+/// With an `overshoot` other than [`Overshoot::Disabled`], pre-existing sets that aren't subsets of
+/// the target may be used too, as long as all of the sets used together contain no more values
+/// outside the target than it allows. Those values are returned as the extras.
+///
 /// You may be confused as to how this function is used within Intersection: It's quite simple, actually.
 /// Given a list of Discord users to @-mention (the target set) and every role within the server (the
 /// pre-existing sets), determine which roles (the output set of pre-existing sets) and members (the outliers)
@@ -42,7 +89,8 @@ where
 /// This has not been proven to be the exact most optimal solution, and that's not the primary goal currently.
 /// The main goal of this function is to be as fast and performant as possible, while still providing an _almost_
 /// complete solution. You can read about all of the possible methods that were considered for this algorithm in
-/// issue #16 and PR #18.
+/// issue #16 and PR #18. [`unionize_set_exact`](exact::unionize_set_exact) searches for the optimal solution instead, starting
+/// from this one.
 ///
 // TODO: What's the time complexity of this?
 // TODO: Is this a _perfect_ solution? Proof would be nice.
@@ -59,6 +107,7 @@ where
 pub fn unionize_set<'a, Key, Value>(
     target: &'a HashSet<Value>,
     preexisting_sets: &'a HashMap<Key, HashSet<Value>>,
+    overshoot: Overshoot,
 ) -> UnionizeSetResult<'a, Key, Value>
 where
     Key: PartialEq + Eq + Hash + Copy + Debug,
@@ -74,10 +123,24 @@ where
     trace!("Filtering preexisting_sets for subsets of target");
     // FIXME: This step takes around 8 seconds with the large fuzz test that's found below.
     //        Probably the is_subset?
+    let allowance = overshoot.allowance(target.len());
     let filtered_preexisting_sets = preexisting_sets
         .iter()
-        .filter(|(_, set)| set.is_subset(target))
+        .filter(|(_, set)| {
+            set.is_subset(target)
+                || (set.intersection(target).nth(1).is_some()
+                    && set.difference(target).count() <= allowance)
+        })
+        .collect::<HashMap<_, _>>();
+
+    // The values outside of target in each set that isn't a subset of it, and those values
+    // brought in by the sets chosen so far
+    let set_extras = filtered_preexisting_sets
+        .iter()
+        .map(|(key, set)| (*key, set.difference(target).collect::<HashSet<_>>()))
+        .filter(|(_, extras)| !extras.is_empty())
         .collect::<HashMap<_, _>>();
+    let mut extras: HashSet<&Value> = HashSet::new();
 
     // This function takes the un-named and unknown time complexity approach that we believe (not
     // yet proven) is optimal from issue #16. This is a best-effort optimization and some cases
@@ -151,13 +214,9 @@ where
     // FIXME: This step takes 10 seconds with the large fuzz test which is #[ignore]d below
     trace!("Mapping every Value to i32 for bit index within bitfields");
     let mut next_id: usize = 0;
-    let (value_to_index, index_to_value) = filtered_preexisting_sets
-        .values()
-        .copied()
-        .flatten()
-        .chain(target)
-        .collect::<HashSet<_>>()
-        .into_iter()
+    // Only values in target are ever covered, so values outside of it don't need an index
+    let (value_to_index, index_to_value) = target
+        .iter()
         .map(|value| {
             let id = next_id;
             next_id += 1;
@@ -191,7 +250,7 @@ where
             (key, {
                 let mut bitfield = bitvec![0; next_id];
 
-                for value in *set {
+                for value in set.iter().filter(|value| target.contains(value)) {
                     let index = value_to_index
                         .get(value)
                         .expect("value not in value_to_index");
@@ -308,6 +367,9 @@ where
         debug!("Selected set: {:?}", selected_set.0);
 
         output_keys.insert(***selected_set.0);
+        if let Some(selected_extras) = set_extras.get(*selected_set.0) {
+            extras.extend(selected_extras);
+        }
 
         // Now, we set the target bitfield to itself minus the values in selected_set.1:
         // TODO: Should we avoid cloning here? Excessive benchmark tests don't show this as a bottleneck
@@ -318,6 +380,16 @@ where
             .map(|(key, bitfield)| (*key, !selected_set.1.clone() & bitfield))
             .collect::<HashMap<_, _>>();
         preexisting_set_bitfields = new_preexisting_set_bitfields;
+
+        // Finally, sets that would bring in too many values outside of target are no longer
+        // candidates. Neither are those that would only cover one more value, since mentioning it
+        // as an outlier is just as short without notifying anyone else.
+        preexisting_set_bitfields.retain(|key, bitfield| {
+            set_extras.get(*key).is_none_or(|set_extras| {
+                bitfield.count_ones() > 1
+                    && extras.len() + set_extras.difference(&extras).count() <= allowance
+            })
+        });
     }
 
     trace!("Unionize sets completed.");
@@ -347,6 +419,7 @@ where
                     .expect("target did not contain an outlier")
             })
             .collect(),
+        extras,
     }
 }
 
@@ -370,10 +443,11 @@ mod tests {
         ]);
 
         assert_eq!(
-            unionize_set(&target, &preexisting_sets),
+            unionize_set(&target, &preexisting_sets, Overshoot::Disabled),
             UnionizeSetResult {
                 sets: HashSet::from([&"1..=3", &"4..=6", &"7..=9"]),
-                outliers: HashSet::from([&10, &11, &12])
+                outliers: HashSet::from([&10, &11, &12]),
+                extras: HashSet::new(),
             }
         );
     }
@@ -388,10 +462,11 @@ mod tests {
         let preexisting_sets: HashMap<&str, HashSet<i32>> = HashMap::new();
 
         assert_eq!(
-            unionize_set(&target, &preexisting_sets),
+            unionize_set(&target, &preexisting_sets, Overshoot::Disabled),
             UnionizeSetResult {
                 sets: HashSet::new(),
-                outliers: HashSet::from([&1, &2, &3])
+                outliers: HashSet::from([&1, &2, &3]),
+                extras: HashSet::new(),
             }
         );
     }
@@ -406,10 +481,52 @@ mod tests {
         let preexisting_sets = HashMap::from([("not a subset", HashSet::from([2, 3, 7]))]);
 
         assert_eq!(
-            unionize_set(&target, &preexisting_sets),
+            unionize_set(&target, &preexisting_sets, Overshoot::Disabled),
+            UnionizeSetResult {
+                sets: HashSet::new(),
+                outliers: HashSet::from([&1, &2, &3]),
+                extras: HashSet::new(),
+            }
+        );
+    }
+
+    /// Target: {1, 2, 3, 4, 5}
+    /// Input set 0: {1, 2, 3, 4, 6}
+    /// Input set 1: {4, 5, 7, 8}
+    /// Output sets: [R0] if at least 1 extra value is allowed, otherwise []
+    /// Output outliers: {5}, otherwise {1, 2, 3, 4, 5}
+    /// Output extras: {6}, otherwise {}
+    #[test]
+    fn unionize_set_uses_supersets_within_overshoot() {
+        let target = HashSet::from([1, 2, 3, 4, 5]);
+        let preexisting_sets = HashMap::from([
+            ("A", HashSet::from([1, 2, 3, 4, 6])),
+            ("B", HashSet::from([4, 5, 7, 8])),
+        ]);
+
+        assert_eq!(
+            unionize_set(&target, &preexisting_sets, Overshoot::Values(1)),
+            UnionizeSetResult {
+                sets: HashSet::from([&"A"]),
+                outliers: HashSet::from([&5]),
+                extras: HashSet::from([&6]),
+            }
+        );
+        // B would only cover 5 once A is chosen, which isn't worth notifying 7 and 8
+        assert_eq!(
+            unionize_set(&target, &preexisting_sets, Overshoot::Percent(60)),
+            UnionizeSetResult {
+                sets: HashSet::from([&"A"]),
+                outliers: HashSet::from([&5]),
+                extras: HashSet::from([&6]),
+            }
+        );
+        assert_eq!(
+            unionize_set(&target, &preexisting_sets, Overshoot::Disabled),
             UnionizeSetResult {
                 sets: HashSet::new(),
-                outliers: HashSet::from([&1, &2, &3])
+                outliers: HashSet::from([&1, &2, &3, &4, &5]),
+                extras: HashSet::new(),
             }
         );
     }
@@ -427,7 +544,8 @@ mod tests {
             ("B", HashSet::from([1, 2, 3])),
         ]);
 
-        let UnionizeSetResult { sets, outliers } = unionize_set(&target, &preexisting_sets);
+        let UnionizeSetResult { sets, outliers, .. } =
+            unionize_set(&target, &preexisting_sets, Overshoot::Disabled);
         assert_eq!(outliers.len(), 0);
         assert_eq!(sets.len(), 1);
         assert!(sets == HashSet::from([&"A"]) || sets == HashSet::from([&"B"]));
@@ -449,10 +567,11 @@ mod tests {
         ]);
 
         assert_eq!(
-            unionize_set(&target, &preexisting_sets),
+            unionize_set(&target, &preexisting_sets, Overshoot::Disabled),
             UnionizeSetResult {
                 sets: HashSet::from([&"A", &"C"]),
-                outliers: HashSet::new()
+                outliers: HashSet::new(),
+                extras: HashSet::new(),
             }
         );
     }
@@ -473,10 +592,11 @@ mod tests {
         ]);
 
         assert_eq!(
-            unionize_set(&target, &preexisting_sets),
+            unionize_set(&target, &preexisting_sets, Overshoot::Disabled),
             UnionizeSetResult {
                 sets: HashSet::from([&"A", &"C"]),
-                outliers: HashSet::new()
+                outliers: HashSet::new(),
+                extras: HashSet::new(),
             }
         );
     }
@@ -505,6 +625,6 @@ mod tests {
             })
             .collect::<HashMap<_, _>>();
 
-        std::hint::black_box(unionize_set(&target, &map));
+        std::hint::black_box(unionize_set(&target, &map, Overshoot::Disabled));
     }
 }
@@ -0,0 +1,71 @@
+//! What a solution to [`unionize_set_exact`] costs
+//!
+//! [`unionize_set_exact`]: super::exact::unionize_set_exact
+
+use poise::serenity_prelude::UserId;
+
+use crate::models::mention::{Mention, RoleType};
+
+/// Weighs the sets and outliers of a solution, so that [`unionize_set_exact`] finds the solution
+/// they weigh the least in total
+///
+/// [`unionize_set_exact`]: super::exact::unionize_set_exact
+pub trait CoverCost<Key, Value> {
+    /// What choosing the pre-existing set `key` costs
+    fn set_cost(&self, key: &Key) -> usize;
+
+    /// What leaving `value` as an outlier costs
+    fn outlier_cost(&self, value: &Value) -> usize;
+}
+
+/// Every set and outlier costs the same, so the cheapest solution has the fewest mentions
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MentionCount;
+
+impl<Key, Value> CoverCost<Key, Value> for MentionCount {
+    fn set_cost(&self, _key: &Key) -> usize {
+        1
+    }
+
+    fn outlier_cost(&self, _value: &Value) -> usize {
+        1
+    }
+}
+
+/// Each role or member costs the length of its mention followed by a space, so the cheapest
+/// solution is the shortest message.
+///
+/// Mentions are packed into messages by [`split_mentions`], which only breaks between mentions, so
+/// the shortest solution also needs the fewest messages, give or take the space left over at the
+/// end of each message.
+///
+/// [`split_mentions`]: crate::util::split_mentions
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RenderedLength;
+
+impl CoverCost<RoleType, UserId> for RenderedLength {
+    fn set_cost(&self, key: &RoleType) -> usize {
+        Mention::Role(*key).to_string().len() + 1
+    }
+
+    fn outlier_cost(&self, value: &UserId) -> usize {
+        Mention::User(*value).to_string().len() + 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use poise::serenity_prelude::RoleId;
+
+    use super::*;
+
+    #[test]
+    fn rendered_length_counts_mentions_and_spaces() {
+        assert_eq!(RenderedLength.set_cost(&RoleType::Here), "@here ".len());
+        assert_eq!(
+            RenderedLength.set_cost(&RoleType::Role(RoleId(123))),
+            "<@&123> ".len()
+        );
+        assert_eq!(RenderedLength.outlier_cost(&UserId(45)), "<@45> ".len());
+    }
+}
@@ -0,0 +1,793 @@
+//! An exact solver for the problem [`unionize_set`] solves greedily
+//!
+//! Choosing the cheapest sets and outliers is a weighted set cover problem, which is NP-hard, so
+//! this is a branch-and-bound search that gives up once it has used up a [`SolverBudget`]. It
+//! starts from the greedy solution, so giving up early is never worse than not searching at all.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    hash::Hash,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use bitvec::prelude::*;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, instrument};
+
+use super::{unionize_set, CoverCost, Overshoot, OwnedUnionizeSetResult, UnionizeSetResult};
+
+/// How much searching [`unionize_set_exact`] may do before settling for the best solution it has
+/// found. Guilds may change this, up to [`SolverBudget::MAXIMUM`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SolverBudget {
+    /// How long the search may take, in milliseconds
+    pub time_ms: u64,
+    /// How many nodes of the search tree may be visited
+    pub nodes: usize,
+}
+
+impl SolverBudget {
+    /// The largest budget that can be set
+    pub const MAXIMUM: Self = Self {
+        time_ms: 2000,
+        nodes: 10_000_000,
+    };
+}
+
+impl Default for SolverBudget {
+    fn default() -> Self {
+        Self {
+            time_ms: 100,
+            nodes: 100_000,
+        }
+    }
+}
+
+/// The state of a branch-and-bound search for the cheapest cover of the target
+struct Search {
+    /// The candidate sets, as bitsets over the positions of the target's values. Only distinct
+    /// sets within the overshoot that cost less than leaving their values as outliers are
+    /// candidates.
+    sets: Vec<BitVec>,
+    /// The values outside the target in each candidate set, as bitsets over their positions
+    extras: Vec<BitVec>,
+    /// How many values outside the target a solution may include
+    allowance: usize,
+    /// What choosing each candidate set costs
+    set_costs: Vec<usize>,
+    /// What leaving each value of the target as an outlier costs
+    outlier_costs: Vec<usize>,
+    /// The candidate sets containing each value of the target
+    containing: Vec<Vec<usize>>,
+    /// The cheapest solution found so far, if it is cheaper than the greedy solution
+    best: Option<Vec<usize>>,
+    /// The cost of the cheapest solution found so far
+    best_cost: usize,
+    /// How many nodes have been visited
+    nodes: usize,
+    /// The most nodes that may be visited
+    max_nodes: usize,
+    /// When the search has to stop
+    deadline: Instant,
+    /// Whether the search stopped before it could prove that its best solution is optimal
+    exhausted: bool,
+}
+
+/// Convert a cost to a float, for comparing shares of costs
+fn to_f64(cost: usize) -> f64 {
+    f64::from(u32::try_from(cost).unwrap_or(u32::MAX))
+}
+
+impl Search {
+    /// Check whether the budget has been used up, counting a visit to a new node.
+    ///
+    /// Bounding a node takes time proportional to the number of sets times the size of the
+    /// target, which dwarfs reading the clock, so the deadline is checked at every node.
+    fn out_of_budget(&mut self) -> bool {
+        if !self.exhausted {
+            self.nodes += 1;
+            self.exhausted = self.nodes > self.max_nodes || Instant::now() >= self.deadline;
+        }
+        self.exhausted
+    }
+
+    /// What covering the values of `set` in `uncovered` with it costs per value
+    fn cost_per_value(&self, set: usize, uncovered: &BitSlice) -> f64 {
+        let covered = (self.sets[set].clone() & uncovered).count_ones();
+        to_f64(self.set_costs[set]) / to_f64(covered.max(1))
+    }
+
+    /// Determine whether covering `uncovered` with the `allowed` sets and outliers might cost less
+    /// than `limit`, by comparing it to a lower bound on the cost.
+    ///
+    /// If each value pays an equal share of the set that covers it, or all of its own cost as an
+    /// outlier, a value pays at least the smallest of those shares.
+    fn might_cost_less_than(&self, uncovered: &BitSlice, allowed: &BitSlice, limit: usize) -> bool {
+        let shares = (0..self.sets.len())
+            .map(|set| self.cost_per_value(set, uncovered))
+            .collect::<Vec<_>>();
+
+        let total = uncovered
+            .iter_ones()
+            .map(|value| {
+                self.containing[value]
+                    .iter()
+                    .filter(|&&set| allowed[set])
+                    .map(|&set| shares[set])
+                    .fold(to_f64(self.outlier_costs[value]), f64::min)
+            })
+            .sum::<f64>();
+        // The cost is a whole number, so the bound can be rounded up, leaving some room for
+        // rounding errors
+        (total - 1e-9).ceil() < to_f64(limit)
+    }
+
+    /// Search for a cheaper cover of `uncovered`, having already chosen `chosen`, which include
+    /// the values outside the target in `included`, and paid `cost` for it. Sets that aren't
+    /// `allowed` can't be chosen in this branch.
+    fn search(
+        &mut self,
+        mut uncovered: BitVec,
+        allowed: BitVec,
+        included: &BitSlice,
+        chosen: &mut Vec<usize>,
+        mut cost: usize,
+    ) {
+        if self.out_of_budget() {
+            return;
+        }
+
+        // Values that no allowed set contains have to be outliers
+        let stranded = uncovered
+            .iter_ones()
+            .filter(|&value| !self.containing[value].iter().any(|&set| allowed[set]))
+            .collect::<Vec<_>>();
+        for value in stranded {
+            cost += self.outlier_costs[value];
+            uncovered.set(value, false);
+        }
+
+        if uncovered.not_any() {
+            if cost < self.best_cost {
+                debug!("Found a cover costing {cost}");
+                self.best_cost = cost;
+                self.best = Some(chosen.clone());
+            }
+            return;
+        }
+        if cost >= self.best_cost
+            || !self.might_cost_less_than(&uncovered, &allowed, self.best_cost - cost)
+        {
+            return;
+        }
+
+        // Branch on how the value in the fewest allowed sets is covered, which keeps the tree
+        // narrow. Every solution covers it with one of those sets or leaves it as an outlier.
+        let value = uncovered
+            .iter_ones()
+            .min_by_key(|&value| {
+                self.containing[value]
+                    .iter()
+                    .filter(|&&set| allowed[set])
+                    .count()
+            })
+            .expect("some value should be uncovered");
+        let mut options = self.containing[value]
+            .iter()
+            .map(|&set| (set, self.cost_per_value(set, &uncovered)))
+            .filter(|&(set, _)| allowed[set])
+            .collect::<Vec<_>>();
+        // Trying the sets that cost the least per value first finds cheap solutions, and so
+        // prunes, sooner
+        options.sort_by(|(_, lhs), (_, rhs)| lhs.total_cmp(rhs));
+
+        let mut remaining = allowed;
+        for (set, _) in options {
+            // Solutions with the sets tried before this one were already searched, and there are
+            // none with sets that include too many values outside the target
+            remaining.set(set, false);
+            let included = included.to_bitvec() | self.extras[set].as_bitslice();
+            if included.count_ones() > self.allowance {
+                continue;
+            }
+            chosen.push(set);
+            self.search(
+                uncovered.clone() & !self.sets[set].clone(),
+                remaining.clone(),
+                &included,
+                chosen,
+                cost + self.set_costs[set],
+            );
+            chosen.pop();
+        }
+
+        // Every set containing the value is now ruled out, so it has to be an outlier
+        uncovered.set(value, false);
+        let cost = cost + self.outlier_costs[value];
+        self.search(uncovered, remaining, included, chosen, cost);
+    }
+}
+
+/// The total cost of a solution
+fn solution_cost<Key, Value>(
+    result: &UnionizeSetResult<'_, Key, Value>,
+    cost: &impl CoverCost<Key, Value>,
+) -> usize
+where
+    Key: PartialEq + Eq + Hash,
+    Value: PartialEq + Eq + Hash,
+{
+    result
+        .sets
+        .iter()
+        .map(|key| cost.set_cost(key))
+        .chain(result.outliers.iter().map(|value| cost.outlier_cost(value)))
+        .sum()
+}
+
+/// Represent a set as the union of pre-existing sets and outliers that cost the least.
+///
+/// This solves the same problem as [`unionize_set`], but searches for the cheapest solution
+/// according to `cost` instead of building one greedily. With [`MentionCount`], that is the
+/// solution with the fewest sets plus outliers. The search stops once it has used up `budget`,
+/// returning the best solution found by then, which is the greedy one if nothing better was found.
+/// Building the greedy solution and the candidates counts against the time budget too.
+///
+/// Like [`unionize_set`], sets that aren't subsets of the target are only used within `overshoot`.
+/// Notifying someone outside the target isn't counted as a cost, so the cheapest solution uses as
+/// much of the overshoot as saves anything.
+///
+/// [`MentionCount`]: super::MentionCount
+#[instrument(skip_all)]
+pub fn unionize_set_exact<'a, Key, Value>(
+    target: &'a HashSet<Value>,
+    preexisting_sets: &'a HashMap<Key, HashSet<Value>>,
+    cost: &impl CoverCost<Key, Value>,
+    budget: SolverBudget,
+    overshoot: Overshoot,
+) -> UnionizeSetResult<'a, Key, Value>
+where
+    Key: PartialEq + Eq + Hash + Copy + Debug,
+    Value: PartialEq + Eq + Hash + Copy + Debug,
+{
+    let deadline = Instant::now() + Duration::from_millis(budget.time_ms);
+    let greedy = unionize_set(target, preexisting_sets, overshoot);
+    let greedy_cost = solution_cost(&greedy, cost);
+
+    let values = target.iter().collect::<Vec<_>>();
+    let positions = values
+        .iter()
+        .enumerate()
+        .map(|(position, &value)| (value, position))
+        .collect::<HashMap<_, _>>();
+    let outlier_costs = values
+        .iter()
+        .map(|value| cost.outlier_cost(value))
+        .collect::<Vec<_>>();
+
+    let allowance = overshoot.allowance(target.len());
+    let mut keys = Vec::new();
+    let mut sets = Vec::new();
+    let mut set_costs = Vec::new();
+    let mut set_extras = Vec::new();
+    // The values outside the target that candidate sets contain, and their positions
+    let mut outside = Vec::new();
+    let mut outside_positions = HashMap::new();
+    // Sets with the same members are interchangeable, so only the cheapest is a candidate
+    let mut candidates = HashMap::new();
+    for (key, set) in preexisting_sets {
+        let set_cost = cost.set_cost(key);
+        let (inside, extras) = set
+            .iter()
+            .partition::<Vec<_>, _>(|value| target.contains(value));
+        if extras.len() > allowance
+            || set_cost
+                >= inside
+                    .iter()
+                    .map(|value| outlier_costs[positions[value]])
+                    .sum()
+        {
+            continue;
+        }
+        let mut bits = bitvec![0; values.len()];
+        for value in inside {
+            bits.set(positions[value], true);
+        }
+        let mut extras = extras
+            .into_iter()
+            .map(|value| {
+                *outside_positions.entry(value).or_insert_with(|| {
+                    outside.push(value);
+                    outside.len() - 1
+                })
+            })
+            .collect::<Vec<_>>();
+        extras.sort_unstable();
+        match candidates.get(&(bits.clone(), extras.clone())) {
+            Some(&index) if set_costs[index] <= set_cost => {}
+            Some(&index) => {
+                keys[index] = key;
+                set_costs[index] = set_cost;
+            }
+            None => {
+                candidates.insert((bits.clone(), extras.clone()), sets.len());
+                keys.push(key);
+                sets.push(bits);
+                set_costs.push(set_cost);
+                set_extras.push(extras);
+            }
+        }
+    }
+    let extras = set_extras
+        .into_iter()
+        .map(|positions| {
+            let mut bits = bitvec![0; outside.len()];
+            for position in positions {
+                bits.set(position, true);
+            }
+            bits
+        })
+        .collect::<Vec<_>>();
+
+    let mut containing = vec![Vec::new(); values.len()];
+    for (index, set) in sets.iter().enumerate() {
+        for value in set.iter_ones() {
+            containing[value].push(index);
+        }
+    }
+
+    let candidates = sets.len();
+    let mut search = Search {
+        sets,
+        extras,
+        allowance,
+        set_costs,
+        outlier_costs,
+        containing,
+        best: None,
+        best_cost: greedy_cost,
+        nodes: 0,
+        max_nodes: budget.nodes,
+        deadline,
+        exhausted: false,
+    };
+    search.search(
+        bitvec![1; values.len()],
+        bitvec![1; candidates],
+        &bitvec![0; outside.len()],
+        &mut Vec::new(),
+        0,
+    );
+    debug!(
+        "Searched {} nodes, {}",
+        search.nodes,
+        if search.exhausted {
+            "running out of budget"
+        } else {
+            "proving the best cover optimal"
+        }
+    );
+
+    let Some(chosen) = search.best else {
+        debug!("The greedy cover costing {greedy_cost} was the best found");
+        return greedy;
+    };
+
+    let mut covered = bitvec![0; values.len()];
+    let mut included = bitvec![0; outside.len()];
+    for &set in &chosen {
+        covered |= search.sets[set].as_bitslice();
+        included |= search.extras[set].as_bitslice();
+    }
+    UnionizeSetResult {
+        sets: chosen.into_iter().map(|set| keys[set]).collect(),
+        outliers: covered
+            .iter_zeros()
+            .map(|position| values[position])
+            .collect(),
+        extras: included
+            .iter_ones()
+            .map(|position| outside[position])
+            .collect(),
+    }
+}
+
+/// Run [`unionize_set_exact`] on a thread set aside for blocking work.
+///
+/// The search can take as long as `budget` allows without ever yielding, which would otherwise
+/// hold up every other task scheduled on the same async worker thread.
+pub async fn unionize_set_exact_blocking<Key, Value, Cost>(
+    target: Arc<HashSet<Value>>,
+    preexisting_sets: Arc<HashMap<Key, HashSet<Value>>>,
+    cost: Cost,
+    budget: SolverBudget,
+    overshoot: Overshoot,
+) -> Result<OwnedUnionizeSetResult<Key, Value>, tokio::task::JoinError>
+where
+    Key: PartialEq + Eq + Hash + Copy + Debug + Send + Sync + 'static,
+    Value: PartialEq + Eq + Hash + Copy + Debug + Send + Sync + 'static,
+    Cost: CoverCost<Key, Value> + Send + 'static,
+{
+    tokio::task::spawn_blocking(move || {
+        unionize_set_exact(&target, &preexisting_sets, &cost, budget, overshoot).into_owned()
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::{super::MentionCount, *};
+
+    /// Costs picked for each set and value
+    struct Weights {
+        /// What choosing each set costs
+        sets: HashMap<u32, usize>,
+        /// What leaving each value as an outlier costs
+        outliers: HashMap<u32, usize>,
+    }
+
+    impl CoverCost<u32, u32> for Weights {
+        fn set_cost(&self, key: &u32) -> usize {
+            self.sets[key]
+        }
+
+        fn outlier_cost(&self, value: &u32) -> usize {
+            self.outliers[value]
+        }
+    }
+
+    /// The cheapest cost of any solution including at most `allowance` values outside the target,
+    /// found by trying every combination of sets
+    fn brute_force_cost(
+        target: &HashSet<u32>,
+        preexisting_sets: &HashMap<u32, HashSet<u32>>,
+        cost: &impl CoverCost<u32, u32>,
+        allowance: usize,
+    ) -> usize {
+        let sets = preexisting_sets.iter().collect::<Vec<_>>();
+
+        (0..1_usize << sets.len())
+            .filter_map(|combination| {
+                let chosen = sets
+                    .iter()
+                    .enumerate()
+                    .filter(|(index, _)| combination & (1 << index) != 0)
+                    .map(|(_, &set)| set)
+                    .collect::<Vec<_>>();
+                let extras = chosen
+                    .iter()
+                    .flat_map(|(_, set)| set.difference(target))
+                    .collect::<HashSet<_>>();
+                if extras.len() > allowance {
+                    return None;
+                }
+                let outliers = target
+                    .iter()
+                    .filter(|value| !chosen.iter().any(|(_, set)| set.contains(value)))
+                    .map(|value| cost.outlier_cost(value))
+                    .sum::<usize>();
+                Some(
+                    chosen
+                        .iter()
+                        .map(|(key, _)| cost.set_cost(key))
+                        .sum::<usize>()
+                        + outliers,
+                )
+            })
+            .min()
+            .expect("there should be at least one combination")
+    }
+
+    /// Check that a result is a valid solution including at most `allowance` values outside the
+    /// target, returning its cost
+    fn checked_cost(
+        target: &HashSet<u32>,
+        preexisting_sets: &HashMap<u32, HashSet<u32>>,
+        result: &UnionizeSetResult<'_, u32, u32>,
+        cost: &impl CoverCost<u32, u32>,
+        allowance: usize,
+    ) -> usize {
+        let mut union = result
+            .outliers
+            .iter()
+            .copied()
+            .copied()
+            .collect::<HashSet<_>>();
+        for key in &result.sets {
+            union.extend(&preexisting_sets[key]);
+        }
+        let extras = union.difference(target).copied().collect::<HashSet<_>>();
+        assert!(
+            union.is_superset(target),
+            "the solution should cover the target"
+        );
+        assert_eq!(
+            extras,
+            result.extras.iter().copied().copied().collect(),
+            "the extras should be everything outside the target"
+        );
+        assert!(extras.len() <= allowance, "the overshoot should be kept to");
+        solution_cost(result, cost)
+    }
+
+    /// Greedy picks the biggest set first, after which it needs both of the others anyway
+    fn greedy_trap() -> (HashSet<u32>, HashMap<u32, HashSet<u32>>) {
+        (
+            (1..=6).collect(),
+            HashMap::from([
+                (0, HashSet::from([1, 2, 3])),
+                (1, HashSet::from([4, 5, 6])),
+                (2, HashSet::from([2, 3, 4, 5])),
+            ]),
+        )
+    }
+
+    /// A budget that is never used up for the small instances in these tests
+    const UNLIMITED: SolverBudget = SolverBudget {
+        time_ms: 60_000,
+        nodes: usize::MAX,
+    };
+
+    #[test]
+    fn exact_solver_beats_greedy() {
+        let (target, preexisting_sets) = greedy_trap();
+        let greedy = unionize_set(&target, &preexisting_sets, Overshoot::Disabled);
+        assert_eq!(greedy.sets.len() + greedy.outliers.len(), 3);
+
+        assert_eq!(
+            unionize_set_exact(
+                &target,
+                &preexisting_sets,
+                &MentionCount,
+                SolverBudget::default(),
+                Overshoot::Disabled,
+            ),
+            UnionizeSetResult {
+                sets: HashSet::from([&0, &1]),
+                outliers: HashSet::new(),
+                extras: HashSet::new(),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn blocking_search_finds_the_same_solution() {
+        let (target, preexisting_sets) = greedy_trap();
+        let expected = unionize_set_exact(
+            &target,
+            &preexisting_sets,
+            &MentionCount,
+            SolverBudget::default(),
+            Overshoot::Disabled,
+        )
+        .into_owned();
+
+        assert_eq!(
+            unionize_set_exact_blocking(
+                Arc::new(target),
+                Arc::new(preexisting_sets),
+                MentionCount,
+                SolverBudget::default(),
+                Overshoot::Disabled,
+            )
+            .await
+            .expect("the search should not panic"),
+            expected
+        );
+    }
+
+    #[test]
+    fn exhausted_budget_falls_back_to_greedy() {
+        let (target, preexisting_sets) = greedy_trap();
+        let result = unionize_set_exact(
+            &target,
+            &preexisting_sets,
+            &MentionCount,
+            SolverBudget {
+                time_ms: 1000,
+                nodes: 0,
+            },
+            Overshoot::Disabled,
+        );
+        assert_eq!(
+            result,
+            unionize_set(&target, &preexisting_sets, Overshoot::Disabled)
+        );
+    }
+
+    #[test]
+    fn elapsed_deadline_falls_back_to_greedy() {
+        let (target, preexisting_sets) = greedy_trap();
+        let result = unionize_set_exact(
+            &target,
+            &preexisting_sets,
+            &MentionCount,
+            SolverBudget {
+                time_ms: 0,
+                nodes: SolverBudget::MAXIMUM.nodes,
+            },
+            Overshoot::Disabled,
+        );
+        assert_eq!(
+            result,
+            unionize_set(&target, &preexisting_sets, Overshoot::Disabled)
+        );
+    }
+
+    #[test]
+    fn costs_decide_the_cheapest_cover() {
+        let target = HashSet::from([1, 2, 3]);
+        let preexisting_sets = HashMap::from([(0, target.clone())]);
+        let weights = Weights {
+            sets: HashMap::from([(0, 10)]),
+            outliers: HashMap::from([(1, 2), (2, 2), (3, 2)]),
+        };
+
+        assert_eq!(
+            unionize_set_exact(
+                &target,
+                &preexisting_sets,
+                &MentionCount,
+                UNLIMITED,
+                Overshoot::Disabled
+            ),
+            UnionizeSetResult {
+                sets: HashSet::from([&0]),
+                outliers: HashSet::new(),
+                extras: HashSet::new(),
+            }
+        );
+        assert_eq!(
+            unionize_set_exact(
+                &target,
+                &preexisting_sets,
+                &weights,
+                UNLIMITED,
+                Overshoot::Disabled
+            ),
+            UnionizeSetResult {
+                sets: HashSet::new(),
+                outliers: HashSet::from([&1, &2, &3]),
+                extras: HashSet::new(),
+            }
+        );
+    }
+
+    /// Create a random instance, where values past the target make some sets unusable
+    fn random_instance(rng: &mut impl Rng) -> (HashSet<u32>, HashMap<u32, HashSet<u32>>) {
+        let size = rng.gen_range(0..12);
+        let target = (0..size).collect();
+        let preexisting_sets = (0..rng.gen_range(0..10))
+            .map(|key| {
+                let set = (0..size + 2).filter(|_| rng.gen_bool(0.4)).collect();
+                (key, set)
+            })
+            .collect();
+        (target, preexisting_sets)
+    }
+
+    #[test]
+    fn exact_solver_matches_brute_force() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..300 {
+            let (target, preexisting_sets) = random_instance(&mut rng);
+
+            let exact = unionize_set_exact(
+                &target,
+                &preexisting_sets,
+                &MentionCount,
+                UNLIMITED,
+                Overshoot::Disabled,
+            );
+            let greedy = unionize_set(&target, &preexisting_sets, Overshoot::Disabled);
+            let cost = checked_cost(&target, &preexisting_sets, &exact, &MentionCount, 0);
+            assert_eq!(
+                cost,
+                brute_force_cost(&target, &preexisting_sets, &MentionCount, 0),
+                "{target:?} from {preexisting_sets:?} should cost as little as possible"
+            );
+            assert!(cost <= checked_cost(&target, &preexisting_sets, &greedy, &MentionCount, 0));
+        }
+    }
+
+    #[test]
+    fn weighted_exact_solver_matches_brute_force() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..300 {
+            let (target, preexisting_sets) = random_instance(&mut rng);
+            let weights = Weights {
+                sets: preexisting_sets
+                    .keys()
+                    .map(|&key| (key, rng.gen_range(1..30)))
+                    .collect(),
+                outliers: (0..14).map(|value| (value, rng.gen_range(1..10))).collect(),
+            };
+
+            let exact = unionize_set_exact(
+                &target,
+                &preexisting_sets,
+                &weights,
+                UNLIMITED,
+                Overshoot::Disabled,
+            );
+            assert_eq!(
+                checked_cost(&target, &preexisting_sets, &exact, &weights, 0),
+                brute_force_cost(&target, &preexisting_sets, &weights, 0),
+                "{target:?} from {preexisting_sets:?} should cost as little as possible"
+            );
+        }
+    }
+
+    #[test]
+    fn overshoot_allows_a_cheaper_cover() {
+        let target = (1..=6).collect::<HashSet<_>>();
+        let preexisting_sets = HashMap::from([(0, (1..=7).collect::<HashSet<_>>())]);
+
+        assert_eq!(
+            unionize_set_exact(
+                &target,
+                &preexisting_sets,
+                &MentionCount,
+                UNLIMITED,
+                Overshoot::Values(1)
+            ),
+            UnionizeSetResult {
+                sets: HashSet::from([&0]),
+                outliers: HashSet::new(),
+                extras: HashSet::from([&7]),
+            }
+        );
+        // 10% of 6 values rounds down to none
+        assert_eq!(
+            unionize_set_exact(
+                &target,
+                &preexisting_sets,
+                &MentionCount,
+                UNLIMITED,
+                Overshoot::Percent(10)
+            )
+            .outliers
+            .len(),
+            6
+        );
+    }
+
+    #[test]
+    fn overshooting_exact_solver_matches_brute_force() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..300 {
+            let (target, preexisting_sets) = random_instance(&mut rng);
+            let allowance = rng.gen_range(0..=2);
+            let overshoot = Overshoot::Values(allowance);
+
+            let exact = unionize_set_exact(
+                &target,
+                &preexisting_sets,
+                &MentionCount,
+                UNLIMITED,
+                overshoot,
+            );
+            let greedy = unionize_set(&target, &preexisting_sets, overshoot);
+            let cost = checked_cost(&target, &preexisting_sets, &exact, &MentionCount, allowance);
+            assert_eq!(
+                cost,
+                brute_force_cost(&target, &preexisting_sets, &MentionCount, allowance),
+                "{target:?} from {preexisting_sets:?} should cost as little as possible"
+            );
+            assert!(
+                cost <= checked_cost(
+                    &target,
+                    &preexisting_sets,
+                    &greedy,
+                    &MentionCount,
+                    allowance
+                )
+            );
+        }
+    }
+}
@@ -0,0 +1,48 @@
+//! How far a solution to [`unionize_set`] may reach beyond its target
+//!
+//! [`unionize_set`]: super::unionize_set
+
+use serde::{Deserialize, Serialize};
+
+/// How many values outside the target a solution may include, by using pre-existing sets that
+/// aren't subsets of the target. On big servers, a role with almost everyone a query matches, plus
+/// a few people it doesn't, can save a lot of mentions.
+///
+/// Guilds have to opt in to this, since it notifies people who weren't asked for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Overshoot {
+    /// Only subsets of the target may be used
+    #[default]
+    Disabled,
+    /// At most this many values outside the target may be included
+    Values(usize),
+    /// At most this percentage of the size of the target may be included, rounded down
+    Percent(usize),
+}
+
+impl Overshoot {
+    /// The largest percentage that can be set
+    pub const MAX_PERCENT: usize = 100;
+
+    /// How many values outside a target of `target_len` values may be included
+    pub const fn allowance(self, target_len: usize) -> usize {
+        match self {
+            Self::Disabled => 0,
+            Self::Values(values) => values,
+            Self::Percent(percent) => target_len.saturating_mul(percent) / 100,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allowance_scales_with_the_target() {
+        assert_eq!(Overshoot::Disabled.allowance(1000), 0);
+        assert_eq!(Overshoot::Values(3).allowance(0), 3);
+        assert_eq!(Overshoot::Percent(5).allowance(1000), 50);
+        assert_eq!(Overshoot::Percent(5).allowance(39), 1);
+    }
+}
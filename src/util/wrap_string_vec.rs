@@ -10,7 +10,7 @@ pub fn wrap_string_vec(input: &Vec<String>, sep: &str, size: usize) -> anyhow::R
         if next.len() > size {
             bail!("Chunk of length {} too large for size {}", next.len(), size);
         }
-        if current.len() + next.len() + sep.len() > size {
+        if !current.is_empty() && current.len() + sep.len() + next.len() > size {
             result.push(current);
             current = next.clone();
         } else {
@@ -74,6 +74,13 @@ mod tests {
         );
     }
 
+    #[test]
+    fn wrap_string_vec_fills_messages_exactly() {
+        let result = wrap_string_vec(&vec!["ABCDE".to_string(), "F".to_string()], " ", 5)
+            .expect("wrapping should succeed");
+        assert_eq!(result, vec!["ABCDE".to_string(), "F".to_string()]);
+    }
+
     #[test]
     fn wrap_string_vec_has_overflow() {
         assert!(wrap_string_vec(&vec!["ABCDEF".to_string()], " ", 5).is_err());